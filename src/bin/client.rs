@@ -1,20 +1,35 @@
-use core::str;
-use std::{io, net::SocketAddr};
+use std::{env, io, net::SocketAddr, sync::Arc};
 use tokio::{
     net::UdpSocket,
     time::{Duration, sleep},
 };
 
 use rusty_moves::{
-    GameAndPlayer, Message,
-    tictactoe::{
-        TTTGameResult, TTTGameState, TTTPlayer, pretty_print_board, tictactoe_rand,
-        ttt_get_game_status,
-    },
+    Message, Rejection,
+    chess::{ChessGame, Tablebase},
+    game::{Game, play_move},
+    protocol::{GameKind, Packet, encode_message},
+    reliable::ReliableSocket,
+    strategy::{HumanStrategy, SearchBudget, Strategy, StrategyKind},
+    tictactoe::TTTGameState,
 };
 
+const GAMES: usize = 1000;
+
+fn arg_value(name: &str) -> Option<String> {
+    env::args().skip_while(|arg| arg != name).nth(1)
+}
+
 #[tokio::main]
 async fn main() -> io::Result<()> {
+    // Usage: client [--game <ttc|chess>] [--board <config>] [--strategy <name>] [--opponent <name>] [--move-time <ms>] [--tablebase <dir>]
+    // e.g. --board 3x3/3 --strategy minimax --opponent mcts, or --game chess --strategy human to type moves
+    // Chess variants go in --board: standard, chess960 (a random start) or chess960:<0-959>,
+    // kingofthehill, threecheck, atomic or crazyhouse
+    let game = arg_value("--game")
+        .map(|game| game.parse::<GameKind>().expect("Invalid game"))
+        .unwrap_or(GameKind::TicTacToe);
+
     // Allow system to allocate a free port
     let client_addr = "0.0.0.0:0".parse::<SocketAddr>().unwrap();
     let sock = UdpSocket::bind(client_addr).await?;
@@ -22,93 +37,229 @@ async fn main() -> io::Result<()> {
     println!("Client running on {}", sock.local_addr()?);
 
     let server_addr = "127.0.0.1:8080".parse::<SocketAddr>().unwrap();
-    sock.connect(server_addr).await?; // Sets default address of recv and send
-
-    let mut buf = [0; 1024];
+    sock.connect(server_addr).await?; // Only accept packets from the server
+    let sock = ReliableSocket::new(sock);
 
-    // Start off with new game
-    // Server plays first move, client chooses side
-    let mut player = TTTPlayer::Circle;
-
-    let mut win_count = 0;
-    let mut loss_count = 0;
-    let mut draw_count = 0;
+    match game {
+        GameKind::TicTacToe => play::<TTTGameState>(sock, server_addr, ()).await,
+        GameKind::Chess => {
+            // The server's tables, to check the draws it adjudicates with them
+            let tablebase = arg_value("--tablebase")
+                .map(|dir| Arc::new(Tablebase::open(dir).expect("Invalid tablebase")));
+            play::<ChessGame>(sock, server_addr, tablebase).await
+        }
+    }
+}
 
-    let msg = Message::NewGame(GameAndPlayer::TicTacToe(player));
-    let str = msg.to_string();
+/// Plays games of `G` against the server until enough have finished
+async fn play<G: Game>(
+    sock: ReliableSocket,
+    server_addr: SocketAddr,
+    referee: G::Referee,
+) -> io::Result<()> {
+    let config = arg_value("--board")
+        .map(|config| config.parse::<G::Config>().expect("Invalid board config"))
+        .unwrap_or_default();
+    let strategy_kind = arg_value("--strategy")
+        .map(|name| name.parse::<StrategyKind>().expect("Invalid strategy"))
+        .unwrap_or_default();
+    let strategy: Box<dyn Strategy<G>> = match strategy_kind {
+        StrategyKind::Human => Box::new(HumanStrategy),
+        kind => G::strategy(kind).unwrap_or_else(|| panic!("No {} strategy for {}", kind, G::KIND)),
+    };
+    let opponent_strategy = arg_value("--opponent").map(|name| {
+        name.parse::<StrategyKind>()
+            .expect("Invalid opponent strategy")
+    });
+    let budget = arg_value("--move-time").map(|ms| {
+        SearchBudget::time(Duration::from_millis(
+            ms.parse().expect("Invalid move time"),
+        ))
+    });
 
-    let len = sock.send(str.as_bytes()).await?;
-    println!("Sent: {} bytes", len);
+    let mut client = Client {
+        sock,
+        server_addr,
+        session_id: 0,
+        config,
+        opponent_strategy,
+        strategy,
+        player: G::Side::default(),
+        game_state: G::with_config(config),
+        draw_offered: false,
+        win_count: 0,
+        draw_count: 0,
+        loss_count: 0,
+    };
+    // Start off with new game
+    client.new_game().await?;
 
     loop {
-        let len = sock.recv(&mut buf[..]).await?;
-        let str = str::from_utf8(&buf[..len]).unwrap();
-        //println!("Received: {} bytes", len);
+        let (buf, _) = client.sock.recv_from().await?;
+        //println!("Received: {} bytes", buf.len());
+        let packet = match Packet::<G>::try_from(&buf[..]) {
+            Ok(packet) => packet,
+            Err(e) => {
+                println!("Error: {}", e);
+                continue;
+            }
+        };
+        client.session_id = packet.session_id;
 
         sleep(Duration::from_millis(5)).await;
 
-        let msg = Message::from(str);
-        match msg {
-            Message::NewGame(GameAndPlayer::TicTacToe(opponent)) => {
-                player = match opponent {
-                    TTTPlayer::Circle => TTTPlayer::Cross,
-                    TTTPlayer::Cross => TTTPlayer::Circle,
-                };
-                let game_state = TTTGameState::new();
-                let (chosen_move, msg) = tictactoe_rand(game_state, &player);
-
-                let str = msg.to_string();
-                let len = sock.send(str.as_bytes()).await?;
+        let message = match packet.message {
+            Message::MoveText(text, hash) => match client.game_state.parse_move_text(&text) {
+                Some(game_move) => Message::Move(game_move, hash),
+                None => {
+                    let reason = format!("Unrecognised move {:?}", text);
+                    let msg = Message::<G>::Rejected(Rejection::IllegalMove, reason);
+                    client.send(&msg).await?;
+                    continue;
+                }
+            },
+            message => message,
+        };
 
-                pretty_print_board(&str);
-                println!("Move: {:?}\nSent: {} bytes", chosen_move, len);
+        match message {
+            Message::NewGame(opponent, config, _) => {
+                client.player = G::opponent(opponent);
+                client.game_state = G::with_config(config);
+                client.strategy.new_game();
+                client.draw_offered = false;
+                if client.game_state.side_to_move() != client.player {
+                    continue;
+                }
             }
-            Message::GameMsg(board) => {
-                let game_state = TTTGameState::try_from(board).expect("Game invalid");
-                let (chosen_move, msg) = tictactoe_rand(game_state, &player);
-
-                let str = msg.to_string();
-                let len = sock.send(str.as_bytes()).await?;
-
-                pretty_print_board(&str);
-                println!("Move: {:?}\nSent: {} bytes", chosen_move, len);
-
-                if let Message::GameOver(_, res) = &msg {
-                    if res == "draw" {
-                        draw_count += 1;
-                    } else {
-                        win_count += 1;
-                    }
+            Message::GameMsg(board) => match G::parse(&board) {
+                Ok(game_state) => client.game_state = game_state,
+                Err(e) => {
+                    // Nothing to resync from, so start over
+                    println!("Error: Invalid board from server: {}\nBoard: {}", e, board);
+                    client.new_game().await?;
+                    continue;
+                }
+            },
+            Message::Move(game_move, hash) => {
+                let opponent = G::opponent(client.player);
+                let applied = client.game_state.apply_move(&game_move, opponent);
+                if applied.is_err() || client.game_state.state_hash() != hash {
                     println!(
-                        "Client Stats: {} W | {} D | {} L",
-                        win_count, draw_count, loss_count
+                        "State mismatch after move {}, requesting resync",
+                        G::format_move(&game_move)
                     );
-                    if win_count + draw_count + loss_count >= 1000 {
-                        break;
-                    }
-                    sleep(Duration::from_millis(50)).await;
+                    client.send(&Message::<G>::ResyncRequest).await?;
+                    continue;
+                }
+            }
+            Message::MoveText(..) => unreachable!("Read as a move above"),
+            Message::ResyncRequest => {
+                let msg = Message::<G>::GameMsg(client.game_state.to_string());
+                client.send(&msg).await?;
+                continue;
+            }
+            Message::Rejected(Rejection::Forfeit, reason) => {
+                println!("Forfeited game: {}", reason);
+                client.loss_count += 1;
+                if client.next_game().await? {
+                    break;
+                }
+                continue;
+            }
+            Message::Rejected(rejection, reason) => {
+                // Take the server's board and play again from there
+                println!("Move rejected ({}): {}", rejection, reason);
+                client.send(&Message::<G>::ResyncRequest).await?;
+                continue;
+            }
+            Message::DrawOffer => {
+                let accept = client
+                    .strategy
+                    .accept_draw(&client.game_state, client.player);
+                let msg = match accept {
+                    true => Message::<G>::DrawAccept,
+                    false => Message::<G>::DrawDecline,
+                };
+                client.send(&msg).await?;
+                if !accept {
+                    // The server moves once it has the answer
+                    println!("Declined draw offer");
+                    continue;
+                }
+
+                println!("Accepted draw offer");
+                client.draw_count += 1;
+                if client.next_game().await? {
+                    break;
+                }
+                continue;
+            }
+            Message::DrawAccept => {
+                println!("Draw agreed");
+                client.draw_count += 1;
+                if client.next_game().await? {
+                    break;
+                }
+                continue;
+            }
+            Message::DrawDecline => {
+                // Still our turn
+                println!("Draw declined");
+            }
+            Message::Resign => {
+                println!("Server resigned");
+                client.win_count += 1;
+                if client.next_game().await? {
+                    break;
                 }
+                continue;
+            }
+            Message::Claim => {
+                let Some(result) = client.game_state.claim_draw() else {
+                    let reason = "No draw to claim".to_string();
+                    let msg = Message::<G>::Rejected(Rejection::IllegalMove, reason);
+                    client.send(&msg).await?;
+                    continue;
+                };
+                println!("Server claimed a draw: {}", result);
+                client.draw_count += 1;
+                if client.next_game().await? {
+                    break;
+                }
+                continue;
             }
             Message::GameOver(board, server_result) => {
-                let game_state = TTTGameState::try_from(board).expect("Game invalid");
-                if let Some(client_result) = ttt_get_game_status(&game_state, None) {
+                let final_state = match G::parse(&board) {
+                    Ok(final_state) => final_state,
+                    Err(e) => {
+                        println!("Error: Invalid board from server: {}\nBoard: {}", e, board);
+                        client.new_game().await?;
+                        continue;
+                    }
+                };
+                // Either the game ended by itself, our draw claim was upheld or the referee ended it
+                let client_result = final_state
+                    .status()
+                    .or_else(|| final_state.claim_draw())
+                    .or_else(|| final_state.adjudicate(&referee));
+                if let Some(client_result) = client_result {
                     if client_result.to_string() == server_result {
-                        match client_result {
-                            TTTGameResult::Draw => {
+                        match G::winner(&client_result) {
+                            None => {
                                 println!("Draw acknowledged by client.");
-                                draw_count += 1;
+                                client.draw_count += 1;
                             }
-                            _ => {
+                            Some(side) if side == client.player => {
                                 println!("Win acknowledged by client.");
-                                loss_count += 1;
+                                client.win_count += 1;
+                            }
+                            Some(_) => {
+                                println!("Loss acknowledged by client.");
+                                client.loss_count += 1;
                             }
                         };
 
-                        println!(
-                            "Client Stats: {} W | {} D | {} L",
-                            win_count, draw_count, loss_count
-                        );
-                        if win_count + draw_count + loss_count >= 1000 {
+                        if client.print_stats() {
                             break;
                         }
 
@@ -116,28 +267,142 @@ async fn main() -> io::Result<()> {
 
                         sleep(Duration::from_millis(100)).await;
 
-                        player = TTTPlayer::Circle;
-                        let msg = Message::NewGame(GameAndPlayer::TicTacToe(player));
-                        let str = msg.to_string();
-
-                        let len = sock.send(str.as_bytes()).await?;
-                        println!("Sent: {} bytes", len);
+                        client.new_game().await?;
                     } else {
                         println!(
                             "Error: Result mismatch!\nServer: {}\nClient: {}\nBoard: {}",
-                            server_result, client_result, game_state
+                            server_result, client_result, final_state
                         );
                     }
                 } else {
                     println!(
                         "Error: Result mismatch!\nServer: {}\nClient: Game not finished.\nBoard: {}",
-                        server_result, game_state
+                        server_result, final_state
                     );
                 }
+                continue;
+            }
+        }
+
+        // Our turn
+        let (game_state, player) = (&client.game_state, client.player);
+        if client.strategy.resign(game_state, player) {
+            println!("Resigning");
+            client.send(&Message::<G>::Resign).await?;
+            client.loss_count += 1;
+            if client.next_game().await? {
+                break;
+            }
+            continue;
+        }
+        if let Some(result) = game_state.claim_draw()
+            && client.strategy.accept_draw(game_state, player)
+        {
+            // The server answers with the game over
+            println!("Claiming a draw: {}", result);
+            client.send(&Message::<G>::Claim).await?;
+            continue;
+        }
+        if !client.draw_offered && client.strategy.offer_draw(game_state, player) {
+            // Move once the server has answered
+            println!("Offering a draw");
+            client.draw_offered = true;
+            client.send(&Message::<G>::DrawOffer).await?;
+            continue;
+        }
+
+        let chosen_move = client.strategy.choose_move(game_state, player, budget);
+        let move_text = game_state.move_text(&chosen_move);
+        let msg = play_move(&mut client.game_state, player, chosen_move.clone());
+        let len = client.send(&msg).await?;
+
+        client.game_state.pretty_print();
+        println!("Move: {}\nSent: {} bytes", move_text, len);
+
+        if let Message::GameOver(_, res) = &msg {
+            match client.game_state.status().and_then(|res| G::winner(&res)) {
+                None => client.draw_count += 1,
+                Some(_) => client.win_count += 1,
+            }
+            println!(
+                "Client Stats: {} W | {} D | {} L - {}",
+                client.win_count, client.draw_count, client.loss_count, res
+            );
+            if client.games_played() >= GAMES {
+                break;
             }
-            Message::NewGame(GameAndPlayer::Chess(_)) => todo!(),
+            sleep(Duration::from_millis(50)).await;
+        }
+    }
+
+    // Our last message may still need retransmitting
+    client.sock.flush().await
+}
+
+/// The client's end of the session, kept from game to game
+struct Client<G: Game> {
+    sock: ReliableSocket,
+    server_addr: SocketAddr,
+    session_id: u32, // Assigned by the server in its reply
+    config: G::Config,
+    opponent_strategy: Option<StrategyKind>, // Asked of the server in every new game
+    strategy: Box<dyn Strategy<G>>,
+    player: G::Side,
+    game_state: G,
+    draw_offered: bool, // At most once a game
+    win_count: usize,
+    draw_count: usize,
+    loss_count: usize,
+}
+
+impl<G: Game> Client<G> {
+    async fn send(&mut self, msg: &Message<G>) -> io::Result<usize> {
+        send(&mut self.sock, self.session_id, msg, self.server_addr).await
+    }
+
+    /// Asks the server for a new game
+    ///
+    /// Client chooses side, whoever's turn it is then plays first.
+    async fn new_game(&mut self) -> io::Result<()> {
+        self.player = G::Side::default();
+        self.game_state = G::with_config(self.config);
+        self.strategy.new_game();
+        self.draw_offered = false;
+        let msg = Message::<G>::NewGame(self.player, self.config, self.opponent_strategy);
+        let len = self.send(&msg).await?;
+        println!("Sent: {} bytes", len);
+        Ok(())
+    }
+
+    /// Starts the next game once one has ended, returning whether enough have been played instead
+    async fn next_game(&mut self) -> io::Result<bool> {
+        if self.print_stats() {
+            return Ok(true);
         }
+        self.new_game().await?;
+        Ok(false)
     }
 
-    Ok(())
+    fn games_played(&self) -> usize {
+        self.win_count + self.draw_count + self.loss_count
+    }
+
+    /// Prints the totals so far, returning whether enough games have been played
+    fn print_stats(&self) -> bool {
+        println!(
+            "Client Stats: {} W | {} D | {} L",
+            self.win_count, self.draw_count, self.loss_count
+        );
+        self.games_played() >= GAMES
+    }
+}
+
+async fn send<G: Game>(
+    sock: &mut ReliableSocket,
+    session_id: u32,
+    msg: &Message<G>,
+    server_addr: SocketAddr,
+) -> io::Result<usize> {
+    sock.send_to(&encode_message(session_id, msg), server_addr)
+        .await
 }
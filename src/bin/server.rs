@@ -1,126 +1,669 @@
-use core::str;
-use std::{io, net::SocketAddr, time::Duration};
-use tokio::{net::UdpSocket, time::sleep};
+use std::{
+    env,
+    fs::OpenOptions,
+    io::{self, Write},
+    net::SocketAddr,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    net::UdpSocket,
+    time::{interval, sleep},
+};
 
 use rusty_moves::{
-    GameAndPlayer, Message,
-    tictactoe::{
-        TTTGameResult, TTTGameState, TTTPlayer, pretty_print_board, tictactoe_rand,
-        ttt_get_game_status,
-    },
+    Message, Rejection,
+    chess::{ChessGame, Tablebase},
+    game::{Ending, Game, GameRecord, play_move},
+    protocol::{GameKind, Packet, encode_message, peek_game},
+    reliable::ReliableSocket,
+    session::{DEFAULT_SESSION_TIMEOUT, Session, SessionManager},
+    strategy::{SearchBudget, StrategyKind},
+    tictactoe::TTTGameState,
 };
 
+const GAMES_PER_SESSION: usize = 1000;
+const DEFAULT_MAX_REJECTED_MOVES: usize = 3;
+
+fn arg_value(name: &str) -> Option<String> {
+    env::args().skip_while(|arg| arg != name).nth(1)
+}
+
+struct Options {
+    max_rejected_moves: usize, // 0 to never forfeit
+    default_strategy: StrategyKind,
+    budget: Option<SearchBudget>,
+    archive: Option<String>, // File finished games are appended to, in the game's own format
+}
+
 #[tokio::main]
 async fn main() -> io::Result<()> {
-    let addr = "0.0.0.0:8080".parse::<SocketAddr>().unwrap();
-    let sock = UdpSocket::bind(addr).await?;
-    println!("Server running on {}", sock.local_addr()?);
+    // Usage: server [--session-timeout <secs>] [--max-rejected-moves <n>] [--strategy <name>] [--move-time <ms>] [--archive <file>] [--tablebase <dir>]
+    let session_timeout = arg_value("--session-timeout")
+        .map(|secs| Duration::from_secs(secs.parse().expect("Invalid session timeout")))
+        .unwrap_or(DEFAULT_SESSION_TIMEOUT);
+    let options = Options {
+        // Rejected moves in a single game before the client forfeits
+        max_rejected_moves: arg_value("--max-rejected-moves")
+            .map(|n| n.parse().expect("Invalid max rejected moves"))
+            .unwrap_or(DEFAULT_MAX_REJECTED_MOVES),
+        // Used unless the client asks for another one when starting a game
+        default_strategy: arg_value("--strategy")
+            .map(|name| name.parse::<StrategyKind>().expect("Invalid strategy"))
+            .unwrap_or_default(),
+        budget: arg_value("--move-time").map(|ms| {
+            SearchBudget::time(Duration::from_millis(
+                ms.parse().expect("Invalid move time"),
+            ))
+        }),
+        // e.g. games.pgn for chess
+        archive: arg_value("--archive"),
+    };
+
+    // Syzygy tables to adjudicate drawn chess endgames with, clients need the same ones
+    let tablebase = arg_value("--tablebase")
+        .map(|dir| Arc::new(Tablebase::open(dir).expect("Invalid tablebase")));
 
-    let mut buf = [0; 1024];
-    let mut player = TTTPlayer::Circle;
+    let addr = "0.0.0.0:8080".parse::<SocketAddr>().unwrap();
+    let mut sock = ReliableSocket::new(UdpSocket::bind(addr).await?);
+    println!("Server running on {}", sock.get_ref().local_addr()?);
 
-    let mut win_count = 0;
-    let mut loss_count = 0;
-    let mut draw_count = 0;
+    let mut tictactoe_sessions = SessionManager::<TTTGameState>::new(session_timeout);
+    let mut chess_sessions = SessionManager::<ChessGame>::new(session_timeout);
+    let mut expiry_timer = interval(session_timeout.min(Duration::from_secs(10)));
 
     loop {
-        let (len, addr) = sock.recv_from(&mut buf).await?;
-        let str = str::from_utf8(&buf[..len]).unwrap();
-        //println!("[{}] Received: {} bytes", addr, len);
-
-        sleep(Duration::from_millis(5)).await;
-
-        let msg = Message::from(str);
-        match msg {
-            Message::NewGame(GameAndPlayer::TicTacToe(opponent)) => {
-                player = match opponent {
-                    TTTPlayer::Circle => TTTPlayer::Cross,
-                    TTTPlayer::Cross => TTTPlayer::Circle,
-                };
-                let game_state = TTTGameState::new();
-                let (chosen_move, msg) = tictactoe_rand(game_state, &player);
-
-                let str = msg.to_string();
-                let len = sock.send_to(str.as_bytes(), addr).await?;
-
-                pretty_print_board(&str);
-                println!("Move: {:?}\nSent: {} bytes", chosen_move, len);
-            }
-            Message::GameMsg(board) => {
-                let game_state = TTTGameState::try_from(board).expect("Game invalid");
-                let (chosen_move, msg) = tictactoe_rand(game_state, &player);
-
-                let str = msg.to_string();
-                let len = sock.send_to(str.as_bytes(), addr).await?;
-
-                pretty_print_board(&str);
-                println!("Move: {:?}\nSent: {} bytes", chosen_move, len);
-
-                if let Message::GameOver(_, res) = &msg {
-                    if res == "draw" {
-                        draw_count += 1;
-                    } else {
-                        win_count += 1;
-                    }
-                    println!(
-                        "Server Stats: {} W | {} D | {} L",
-                        win_count, draw_count, loss_count
-                    );
-                    if win_count + draw_count + loss_count >= 1000 {
-                        break;
-                    }
-                    sleep(Duration::from_millis(50)).await;
+        let (buf, addr) = tokio::select! {
+            res = sock.recv_from() => match res {
+                Ok(res) => res,
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                    println!("Error: {}", e);
+                    continue;
                 }
+                Err(e) => return Err(e),
+            },
+            _ = expiry_timer.tick() => {
+                let expired = tictactoe_sessions.expire_idle().into_iter();
+                for addr in expired.chain(chess_sessions.expire_idle()) {
+                    sock.forget_peer(&addr);
+                    println!("[{}] Session expired", addr);
+                }
+                continue;
+            }
+        };
+        //println!("[{}] Received: {} bytes", addr, buf.len());
+
+        match peek_game(&buf) {
+            Ok(GameKind::TicTacToe) => {
+                handle(
+                    &mut sock,
+                    &mut tictactoe_sessions,
+                    addr,
+                    &buf,
+                    &options,
+                    &(),
+                )
+                .await?
             }
-            Message::GameOver(board, client_result) => {
-                let game_state = TTTGameState::try_from(board).expect("Game invalid");
-                if let Some(server_result) = ttt_get_game_status(&game_state, None) {
-                    if server_result.to_string() == client_result {
-                        match server_result {
-                            TTTGameResult::Draw => {
-                                println!("Draw acknowledged by server.");
-                                draw_count += 1;
-                            }
-                            _ => {
-                                println!("Win acknowledged by server.");
-                                loss_count += 1;
-                            }
-                        };
-
-                        println!(
-                            "Server Stats: {} W | {} D | {} L",
-                            win_count, draw_count, loss_count
-                        );
-                        if win_count + draw_count + loss_count >= 1000 {
-                            break;
-                        }
-
-                        println!("New Game");
-
-                        sleep(Duration::from_millis(100)).await;
-
-                        player = TTTPlayer::Circle;
-                        let msg = Message::NewGame(GameAndPlayer::TicTacToe(player));
-                        let str = msg.to_string();
-
-                        let len = sock.send_to(str.as_bytes(), addr).await?;
-                        println!("Sent: {} bytes", len);
-                    } else {
-                        println!(
-                            "Error: Result mismatch!\nClient: {}\nServer: {}\nBoard: {}",
-                            client_result, server_result, game_state
-                        );
-                    }
-                } else {
-                    println!(
-                        "Error: Result mismatch!\nClient: {}\nServer: Game not finished.\nBoard: {}",
-                        client_result, game_state
-                    );
+            Ok(GameKind::Chess) => {
+                handle(
+                    &mut sock,
+                    &mut chess_sessions,
+                    addr,
+                    &buf,
+                    &options,
+                    &tablebase,
+                )
+                .await?
+            }
+            Err(e) => println!("[{}] Error: {}", addr, e),
+        }
+    }
+}
+
+/// Handles one packet for game `G`, replying with the server's move when it is its turn
+async fn handle<G: Game>(
+    sock: &mut ReliableSocket,
+    sessions: &mut SessionManager<G>,
+    addr: SocketAddr,
+    buf: &[u8],
+    options: &Options,
+    referee: &G::Referee,
+) -> io::Result<()> {
+    let packet = match Packet::<G>::try_from(buf) {
+        Ok(packet) => packet,
+        Err(e) => {
+            println!("[{}] Error: {}", addr, e);
+            return Ok(());
+        }
+    };
+
+    sleep(Duration::from_millis(5)).await;
+
+    let session = sessions.get_or_create(addr);
+    if packet.session_id != 0 && packet.session_id != session.id {
+        println!(
+            "[{}] Ignoring packet for stale session {}",
+            addr, packet.session_id
+        );
+        return Ok(());
+    }
+    let message = match packet.message {
+        // Typed moves are checked like any other once read
+        Message::MoveText(text, hash) if session.awaiting_client => {
+            match session.game_state.parse_move_text(&text) {
+                Some(game_move) => Message::Move(game_move, hash),
+                None => {
+                    let reason = format!("Unrecognised move {:?}", text);
+                    return reject(sock, session, addr, Rejection::IllegalMove, reason, options)
+                        .await;
                 }
             }
-            Message::NewGame(GameAndPlayer::Chess(_)) => todo!(),
         }
+        message => message,
+    };
+    match message {
+        Message::NewGame(opponent, config, strategy) => {
+            let kind = strategy.unwrap_or(options.default_strategy);
+            if !session.set_strategy(kind) {
+                println!("[{}] No {} strategy for {}", addr, kind, G::KIND);
+            }
+            session.new_game(G::opponent(opponent), config);
+            println!("[{}] Playing with strategy {}", addr, session.strategy_kind);
+            if session.game_state.side_to_move() != session.player {
+                // Let the client know it should start
+                session.awaiting_client = true;
+                let msg = Message::<G>::NewGame(session.player, config, None);
+                send(sock, session.id, &msg, addr).await?;
+                return Ok(());
+            }
+        }
+        Message::GameMsg(_)
+        | Message::Move(..)
+        | Message::MoveText(..)
+        | Message::GameOver(..)
+        | Message::DrawOffer
+        | Message::Resign
+        | Message::Claim
+            if !session.awaiting_client =>
+        {
+            let reason = "Server has not received its move yet".to_string();
+            return reject(sock, session, addr, Rejection::OutOfTurn, reason, options).await;
+        }
+        Message::GameMsg(board) => {
+            let next = G::parse(&board).map_err(|e| e.to_string());
+            let valid = next.and_then(|next| {
+                session
+                    .game_state
+                    .diff(&next, G::opponent(session.player))
+                    .map(|_| next)
+                    .map_err(|e| e.to_string())
+            });
+            match valid {
+                Ok(next) => session.game_state = next,
+                Err(reason) => {
+                    return reject(sock, session, addr, Rejection::IllegalMove, reason, options)
+                        .await;
+                }
+            }
+            session.move_count += 1;
+        }
+        Message::Move(game_move, hash) => {
+            let mut next = session.game_state.clone();
+            if let Err(e) = next.apply_move(&game_move, G::opponent(session.player)) {
+                return reject(
+                    sock,
+                    session,
+                    addr,
+                    G::rejection(&e),
+                    e.to_string(),
+                    options,
+                )
+                .await;
+            }
+            if next.state_hash() != hash {
+                println!(
+                    "[{}] State mismatch after move {}, requesting resync",
+                    addr,
+                    G::format_move(&game_move)
+                );
+                send(sock, session.id, &Message::<G>::ResyncRequest, addr).await?;
+                return Ok(());
+            }
+            session.game_state = next;
+            session.move_count += 1;
+        }
+        Message::MoveText(..) => unreachable!("Read as a move above"),
+        Message::ResyncRequest => {
+            let msg = Message::<G>::GameMsg(session.game_state.to_string());
+            send(sock, session.id, &msg, addr).await?;
+            return Ok(());
+        }
+        Message::DrawOffer => {
+            if !session
+                .strategy
+                .accept_draw(&session.game_state, session.player)
+            {
+                // The client moves once it has the answer
+                println!("[{}] Declined draw offer", addr);
+                send(sock, session.id, &Message::<G>::DrawDecline, addr).await?;
+                return Ok(());
+            }
+            println!("[{}] Accepted draw offer", addr);
+            send(sock, session.id, &Message::<G>::DrawAccept, addr).await?;
+            session.stats.draws += 1;
+            if finish_game(session, addr, Ending::Agreed, options) {
+                end_session(sock, sessions, addr);
+            }
+            return Ok(());
+        }
+        Message::DrawAccept if session.awaiting_draw_answer => {
+            println!("[{}] Draw agreed", addr);
+            session.awaiting_draw_answer = false;
+            session.stats.draws += 1;
+            if finish_game(session, addr, Ending::Agreed, options) {
+                end_session(sock, sessions, addr);
+            }
+            return Ok(());
+        }
+        Message::DrawDecline if session.awaiting_draw_answer => {
+            // Still the server's turn
+            println!("[{}] Draw declined", addr);
+            session.awaiting_draw_answer = false;
+        }
+        Message::DrawAccept | Message::DrawDecline => {
+            println!("[{}] Ignoring draw answer, none was offered", addr);
+            return Ok(());
+        }
+        Message::Resign => {
+            println!("[{}] Client resigned", addr);
+            session.stats.wins += 1;
+            if finish_game(
+                session,
+                addr,
+                Ending::Resigned(G::opponent(session.player)),
+                options,
+            ) {
+                end_session(sock, sessions, addr);
+            }
+            return Ok(());
+        }
+        Message::Claim => {
+            let Some(result) = session.game_state.claim_draw() else {
+                let reason = "No draw to claim".to_string();
+                return reject(sock, session, addr, Rejection::IllegalMove, reason, options).await;
+            };
+            println!("[{}] Client claimed a draw: {}", addr, result);
+            let msg = Message::<G>::GameOver(session.game_state.to_string(), result.to_string());
+            send(sock, session.id, &msg, addr).await?;
+            session.stats.draws += 1;
+            if finish_game(session, addr, Ending::Claimed, options) {
+                end_session(sock, sessions, addr);
+            }
+            return Ok(());
+        }
+        Message::Rejected(rejection, reason) => {
+            println!(
+                "[{}] Client rejected move ({}): {}",
+                addr, rejection, reason
+            );
+            return Ok(());
+        }
+        Message::GameOver(board, client_result) => {
+            let game_state = match G::parse(&board) {
+                Ok(game_state) => game_state,
+                Err(e) => {
+                    return reject(
+                        sock,
+                        session,
+                        addr,
+                        Rejection::IllegalMove,
+                        e.to_string(),
+                        options,
+                    )
+                    .await;
+                }
+            };
+            if let Err(e) = session
+                .game_state
+                .diff(&game_state, G::opponent(session.player))
+            {
+                return reject(
+                    sock,
+                    session,
+                    addr,
+                    Rejection::IllegalMove,
+                    e.to_string(),
+                    options,
+                )
+                .await;
+            }
+            let Some(server_result) = game_state.status() else {
+                println!(
+                    "[{}] Error: Result mismatch!\nClient: {}\nServer: Game not finished.\nBoard: {}",
+                    addr, client_result, game_state
+                );
+                return Ok(());
+            };
+            if server_result.to_string() != client_result {
+                println!(
+                    "[{}] Error: Result mismatch!\nClient: {}\nServer: {}\nBoard: {}",
+                    addr, client_result, server_result, game_state
+                );
+                return Ok(());
+            }
+
+            match G::winner(&server_result) {
+                None => {
+                    println!("[{}] Draw acknowledged by server.", addr);
+                    session.stats.draws += 1;
+                }
+                Some(_) => {
+                    println!("[{}] Win acknowledged by server.", addr);
+                    session.stats.losses += 1;
+                }
+            };
+            session.game_state = game_state;
+            archive(session, addr, Ending::Finished, options);
+
+            println!(
+                "[{}] Server Stats: {} ({} moves)",
+                addr,
+                session.stats,
+                session.move_count + 1
+            );
+            if session.stats.games_played() >= GAMES_PER_SESSION {
+                end_session(sock, sessions, addr);
+                return Ok(());
+            }
+
+            println!("[{}] New Game", addr);
+
+            sleep(Duration::from_millis(100)).await;
+
+            let config = session.game_state.config();
+            session.new_game(G::Side::default(), config);
+            let msg = Message::<G>::NewGame(session.player, config, None);
+            let len = send(sock, session.id, &msg, addr).await?;
+            println!("Sent: {} bytes", len);
+            if session.game_state.side_to_move() != session.player {
+                session.awaiting_client = true;
+                return Ok(());
+            }
+        }
+    }
+
+    // The client's own move may have ended the game without it saying so
+    if let Some(outcome) = session.game_state.status() {
+        println!("[{}] Game over after the client's move: {}", addr, outcome);
+        let msg = Message::<G>::GameOver(session.game_state.to_string(), outcome.to_string());
+        send(sock, session.id, &msg, addr).await?;
+        match G::winner(&outcome) {
+            None => session.stats.draws += 1,
+            Some(side) if side == session.player => session.stats.wins += 1,
+            Some(_) => session.stats.losses += 1,
+        }
+        if finish_game(session, addr, Ending::Finished, options) {
+            end_session(sock, sessions, addr);
+        }
+        return Ok(());
+    }
+
+    // The referee may end the game before the server moves
+    if let Some(outcome) = session.game_state.adjudicate(referee) {
+        println!("[{}] Adjudicated: {}", addr, outcome);
+        let msg = Message::<G>::GameOver(session.game_state.to_string(), outcome.to_string());
+        send(sock, session.id, &msg, addr).await?;
+        match G::winner(&outcome) {
+            None => session.stats.draws += 1,
+            Some(side) if side == session.player => session.stats.wins += 1,
+            Some(_) => session.stats.losses += 1,
+        }
+        let ending = Ending::Adjudicated(G::winner(&outcome));
+        if finish_game(session, addr, ending, options) {
+            end_session(sock, sessions, addr);
+        }
+        return Ok(());
+    }
+
+    // Server's turn
+    if session.strategy.resign(&session.game_state, session.player) {
+        println!("[{}] Resigning", addr);
+        send(sock, session.id, &Message::<G>::Resign, addr).await?;
+        session.stats.losses += 1;
+        if finish_game(session, addr, Ending::Resigned(session.player), options) {
+            end_session(sock, sessions, addr);
+        }
+        return Ok(());
+    }
+    if let Some(result) = session.game_state.claim_draw()
+        && session
+            .strategy
+            .accept_draw(&session.game_state, session.player)
+    {
+        // The client checks the claim and starts the next game
+        println!("[{}] Claiming a draw: {}", addr, result);
+        send(sock, session.id, &Message::<G>::Claim, addr).await?;
+        session.stats.draws += 1;
+        if finish_game(session, addr, Ending::Claimed, options) {
+            end_session(sock, sessions, addr);
+        }
+        return Ok(());
+    }
+    if !session.draw_offered
+        && session
+            .strategy
+            .offer_draw(&session.game_state, session.player)
+    {
+        // Move once the client has answered
+        println!("[{}] Offering a draw", addr);
+        session.draw_offered = true;
+        session.awaiting_draw_answer = true;
+        send(sock, session.id, &Message::<G>::DrawOffer, addr).await?;
+        return Ok(());
+    }
+    let chosen_move =
+        session
+            .strategy
+            .choose_move(&session.game_state, session.player, options.budget);
+    let move_text = session.game_state.move_text(&chosen_move);
+    let msg = play_move(&mut session.game_state, session.player, chosen_move.clone());
+    session.move_count += 1;
+    session.awaiting_client = !matches!(msg, Message::GameOver(..));
+    let len = send(sock, session.id, &msg, addr).await?;
+
+    session.game_state.pretty_print();
+    println!("[{}] Move: {}\nSent: {} bytes", addr, move_text, len);
+
+    if let Message::GameOver(_, res) = &msg {
+        match session.game_state.status().and_then(|res| G::winner(&res)) {
+            None => session.stats.draws += 1,
+            Some(_) => session.stats.wins += 1,
+        }
+        archive(session, addr, Ending::Finished, options);
+        println!(
+            "[{}] Server Stats: {} ({} moves) - {}",
+            addr, session.stats, session.move_count, res
+        );
+        if session.stats.games_played() >= GAMES_PER_SESSION {
+            end_session(sock, sessions, addr);
+            return Ok(());
+        }
+        sleep(Duration::from_millis(50)).await;
+    }
+    Ok(())
+}
+
+/// Drops a session which has played all its games, with the peer's sequence state too
+fn end_session<G: Game>(
+    sock: &mut ReliableSocket,
+    sessions: &mut SessionManager<G>,
+    addr: SocketAddr,
+) {
+    sessions.remove(&addr);
+    sock.forget_peer(&addr);
+}
+
+/// Ends a game without a final move, returning whether the session has played all its games
+fn finish_game<G: Game>(
+    session: &mut Session<G>,
+    addr: SocketAddr,
+    ending: Ending<G::Side>,
+    options: &Options,
+) -> bool {
+    // Nothing more until the client starts the next game
+    session.awaiting_client = false;
+    archive(session, addr, ending, options);
+    println!(
+        "[{}] Server Stats: {} ({} moves)",
+        addr, session.stats, session.move_count
+    );
+    session.stats.games_played() >= GAMES_PER_SESSION
+}
+
+/// Appends a finished game to the archive file, if there is one and the game can be exported
+fn archive<G: Game>(
+    session: &Session<G>,
+    addr: SocketAddr,
+    ending: Ending<G::Side>,
+    options: &Options,
+) {
+    let Some(path) = &options.archive else {
+        return;
+    };
+    let record = GameRecord {
+        game: session.game_state.clone(),
+        ending,
+        players: vec![
+            (
+                session.player,
+                format!("Rusty Moves ({})", session.strategy_kind),
+            ),
+            (G::opponent(session.player), addr.to_string()),
+        ],
+        started: session.started,
+        move_time: options.budget.and_then(|budget| budget.max_time),
+    };
+    let Some(text) = G::export(&record) else {
+        return;
+    };
+    let written = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| writeln!(file, "{}", text));
+    if let Err(e) = written {
+        println!(
+            "[{}] Error: Failed to archive game to {}: {}",
+            addr, path, e
+        );
     }
+}
+
+/// Tells the client its message was refused, forfeiting the game once it has too many rejections
+async fn reject<G: Game>(
+    sock: &mut ReliableSocket,
+    session: &mut Session<G>,
+    addr: SocketAddr,
+    rejection: Rejection,
+    reason: String,
+    options: &Options,
+) -> io::Result<()> {
+    let max_rejected_moves = options.max_rejected_moves;
+    println!("[{}] Rejected move ({}): {}", addr, rejection, reason);
+    session.rejected_moves += 1;
 
+    if max_rejected_moves > 0 && session.rejected_moves >= max_rejected_moves {
+        println!(
+            "[{}] Client forfeits after {} rejected moves",
+            addr, session.rejected_moves
+        );
+        let msg = Message::<G>::Rejected(Rejection::Forfeit, reason);
+        send(sock, session.id, &msg, addr).await?;
+        session.stats.wins += 1;
+        archive(
+            session,
+            addr,
+            Ending::Forfeited(G::opponent(session.player)),
+            options,
+        );
+        let config = session.game_state.config();
+        session.new_game(session.player, config);
+        println!("[{}] Server Stats: {}", addr, session.stats);
+    } else {
+        send(
+            sock,
+            session.id,
+            &Message::<G>::Rejected(rejection, reason),
+            addr,
+        )
+        .await?;
+    }
     Ok(())
 }
+
+async fn send<G: Game>(
+    sock: &mut ReliableSocket,
+    session_id: u32,
+    msg: &Message<G>,
+    addr: SocketAddr,
+) -> io::Result<usize> {
+    sock.send_to(&encode_message(session_id, msg), addr).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusty_moves::tictactoe::{TTTConfig, TTTPlayer};
+
+    #[tokio::test]
+    async fn test_client_move_ends_game() {
+        let mut server = ReliableSocket::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let mut client = ReliableSocket::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let server_addr = server.get_ref().local_addr().unwrap();
+        let client_addr = client.get_ref().local_addr().unwrap();
+        let options = Options {
+            max_rejected_moves: DEFAULT_MAX_REJECTED_MOVES,
+            default_strategy: StrategyKind::Random,
+            budget: None,
+            archive: None,
+        };
+
+        // The client completes the top row with a plain move rather than GameOver
+        let mut sessions = SessionManager::<TTTGameState>::default();
+        let session = sessions.get_or_create(client_addr);
+        session.new_game(TTTPlayer::Circle, TTTConfig::CLASSIC);
+        session.awaiting_client = true;
+        for (block, side) in [
+            ((0, 0), TTTPlayer::Cross),
+            ((1, 0), TTTPlayer::Circle),
+            ((0, 1), TTTPlayer::Cross),
+            ((1, 1), TTTPlayer::Circle),
+        ] {
+            session.game_state.apply_move(block, side).unwrap();
+        }
+        let mut won = session.game_state.clone();
+        won.apply_move((0, 2), TTTPlayer::Cross).unwrap();
+        let msg = Message::<TTTGameState>::Move((0, 2), won.state_hash());
+        let buf = encode_message(session.id, &msg);
+        handle(&mut server, &mut sessions, client_addr, &buf, &options, &())
+            .await
+            .unwrap();
+
+        // The server doesn't move but ends the game
+        let (payload, from) = client.recv_from().await.unwrap();
+        assert_eq!(from, server_addr);
+        let reply = Packet::<TTTGameState>::try_from(&payload[..])
+            .unwrap()
+            .message;
+        let Message::GameOver(board, result) = reply else {
+            panic!("Expected GameOver, got {}", reply);
+        };
+        assert_eq!(board, won.to_string());
+        assert_eq!(result, won.status().unwrap().to_string());
+        let session = sessions.get_or_create(client_addr);
+        assert_eq!(session.game_state.to_string(), won.to_string());
+        assert!(!session.awaiting_client);
+        assert_eq!(session.stats.losses, 1);
+    }
+}
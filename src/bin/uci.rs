@@ -0,0 +1,303 @@
+use std::{
+    env,
+    io::{self, BufRead},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use rusty_moves::{
+    chess::{
+        BOOK_ENV_VAR, BookStrategy, ChessGame, ChessPlayer, ChessPosition, ChessVariant,
+        NativeEngine, PolyglotBook, STANDARD_CHESS960, SYZYGY_ENV_VAR, Tablebase, UciPlayer,
+        UciPlayerConfig, default_book_depth,
+    },
+    game::Game,
+    strategy::{RandomStrategy, SearchBudget, Strategy, StrategyKind},
+};
+
+// The bots GUIs can pick between with the Strategy option
+const STRATEGIES: [StrategyKind; 3] = [
+    StrategyKind::Random,
+    StrategyKind::Minimax,
+    StrategyKind::Engine,
+];
+
+// Share of the remaining clock spent on a move when the GUI doesn't say how many are left
+const MOVES_TO_GO: u32 = 30;
+
+struct Options {
+    strategy: StrategyKind,
+    player: UciPlayerConfig, // For the engine strategy
+    chess960: bool,          // Castling written as king takes rook
+    book: Option<Arc<PolyglotBook>>,
+    book_depth: u32,                   // Plies played from the book
+    tablebase: Option<Arc<Tablebase>>, // For the native engine
+}
+
+impl Options {
+    fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let invalid = || format!("Invalid value {:?} for {}", value, name);
+        match name.to_ascii_lowercase().as_str() {
+            "strategy" => {
+                let kind = value.parse().map_err(|e: anyhow::Error| e.to_string())?;
+                if !STRATEGIES.contains(&kind) {
+                    return Err(format!("No {} strategy for chess", kind));
+                }
+                self.strategy = kind;
+            }
+            "enginepath" => self.player.program = value.to_string(),
+            "topn" => self.player.top_n = value.parse().map_err(|_| invalid())?,
+            "window" => self.player.window = value.parse().map_err(|_| invalid())?,
+            "uci_chess960" => self.chess960 = value.parse().map_err(|_| invalid())?,
+            "bookfile" => {
+                self.book = match value {
+                    "" | "<empty>" => None,
+                    path => Some(Arc::new(
+                        PolyglotBook::open(path).map_err(|e| e.to_string())?,
+                    )),
+                }
+            }
+            "bookdepth" => self.book_depth = value.parse().map_err(|_| invalid())?,
+            "syzygypath" => {
+                self.tablebase = match value {
+                    "" | "<empty>" => None,
+                    dir => Some(Arc::new(Tablebase::open(dir).map_err(|e| e.to_string())?)),
+                }
+            }
+            _ => return Err(format!("Unknown option {:?}", name)),
+        }
+        Ok(())
+    }
+
+    // The native engine gives up its search once `stop` is set
+    fn strategy(&self, stop: &Arc<AtomicBool>) -> Box<dyn Strategy<ChessGame>> {
+        let strategy: Box<dyn Strategy<ChessGame>> = match self.strategy {
+            StrategyKind::Random => Box::new(RandomStrategy),
+            StrategyKind::Engine => Box::new(UciPlayer::new(self.player.clone())),
+            // Minimax
+            _ => {
+                let engine = NativeEngine::new(SearchBudget::default()).with_stop(stop.clone());
+                match &self.tablebase {
+                    Some(tablebase) => Box::new(engine.with_tablebase(tablebase.clone())),
+                    None => Box::new(engine),
+                }
+            }
+        };
+        match &self.book {
+            Some(book) => Box::new(BookStrategy::new(book.clone(), self.book_depth, strategy)),
+            None => strategy,
+        }
+    }
+}
+
+// Speaks UCI on stdin/stdout, so GUIs and tournament managers can play the library's bots
+//
+// Options:
+//   Strategy   random, minimax (the native engine, default) or engine (a random top move)
+//   EnginePath the UCI engine the engine strategy asks, `stockfish` unless UCI_ENGINE is set
+//   TopN       how many of that engine's moves to pick from
+//   Window     centipawns behind its best move a picked move may be
+//   UCI_Chess960 whether positions are Chess960 ones, with castling written king takes rook
+//   BookFile   a Polyglot opening book to play from first, POLYGLOT_BOOK if that is set
+//   BookDepth  how many plies into the game the book is used for
+//   SyzygyPath a directory of Syzygy tablebases for the native engine, SYZYGY_PATH if that is set
+fn main() {
+    let mut options = Options {
+        strategy: StrategyKind::Minimax,
+        player: UciPlayerConfig::default(),
+        chess960: false,
+        book: None,
+        book_depth: default_book_depth(),
+        tablebase: None,
+    };
+    let mut book_file = env::var(BOOK_ENV_VAR).unwrap_or_default();
+    if let Err(e) = options.set("BookFile", &book_file) {
+        println!("info string {}", e);
+        book_file.clear();
+    }
+    let mut syzygy_path = env::var(SYZYGY_ENV_VAR).unwrap_or_default();
+    if let Err(e) = options.set("SyzygyPath", &syzygy_path) {
+        println!("info string {}", e);
+        syzygy_path.clear();
+    }
+    let mut strategy: Option<Box<dyn Strategy<ChessGame>>> = None;
+    let mut game = ChessGame::with_config(Default::default());
+    // Searches run on their own thread, so `stop` and `isready` are answered meanwhile
+    let stop = Arc::new(AtomicBool::new(false));
+    let mut search: Option<JoinHandle<Box<dyn Strategy<ChessGame>>>> = None;
+
+    for line in io::stdin().lock().lines() {
+        let line = line.expect("Failed to read stdin");
+        let tokens = line.split_whitespace().collect::<Vec<_>>();
+        match tokens[..] {
+            ["uci"] => {
+                println!("id name Rusty Moves");
+                println!("id author differental");
+                let names = STRATEGIES.map(|kind| format!("var {}", kind));
+                println!(
+                    "option name Strategy type combo default {} {}",
+                    options.strategy,
+                    names.join(" ")
+                );
+                println!(
+                    "option name EnginePath type string default {}",
+                    options.player.program
+                );
+                println!(
+                    "option name TopN type spin default {} min 1 max 50",
+                    options.player.top_n
+                );
+                println!(
+                    "option name Window type spin default {} min 0 max 10000",
+                    options.player.window
+                );
+                println!(
+                    "option name UCI_Chess960 type check default {}",
+                    options.chess960
+                );
+                let book_file = match book_file.as_str() {
+                    "" => "<empty>",
+                    path => path,
+                };
+                println!("option name BookFile type string default {}", book_file);
+                println!(
+                    "option name BookDepth type spin default {} min 0 max 1000",
+                    options.book_depth
+                );
+                let syzygy_path = match syzygy_path.as_str() {
+                    "" => "<empty>",
+                    dir => dir,
+                };
+                println!("option name SyzygyPath type string default {}", syzygy_path);
+                println!("uciok");
+            }
+            ["isready"] => println!("readyok"),
+            ["setoption", "name", ..] => {
+                strategy = finish_search(&mut search, &stop).or(strategy);
+                match parse_setoption(&line) {
+                    Some((name, value)) => match options.set(&name, &value) {
+                        // Picked up on the next search
+                        Ok(()) => strategy = None,
+                        Err(e) => println!("info string {}", e),
+                    },
+                    None => println!("info string Invalid setoption {:?}", line),
+                }
+            }
+            ["ucinewgame"] => {
+                strategy = finish_search(&mut search, &stop).or(strategy);
+                if let Some(strategy) = &mut strategy {
+                    strategy.new_game();
+                }
+            }
+            ["position", ..] => match parse_position(&line, options.chess960) {
+                Ok(position) => game = position,
+                Err(e) => println!("info string Invalid position: {}", e),
+            },
+            ["go", ..] => {
+                let mut strategy = finish_search(&mut search, &stop)
+                    .or(strategy.take())
+                    .unwrap_or_else(|| {
+                        let mut strategy = options.strategy(&stop);
+                        strategy.new_game();
+                        strategy
+                    });
+                // An infinite search only answers once told to stop
+                let wait_for_stop = tokens.contains(&"infinite") || tokens.contains(&"ponder");
+                let budget = match wait_for_stop {
+                    true => Some(SearchBudget::unlimited()),
+                    false => parse_go(&tokens[1..], &game),
+                };
+                let (game, stop) = (game.clone(), stop.clone());
+                stop.store(false, Ordering::Relaxed);
+                search = Some(thread::spawn(move || {
+                    let best = match game.legal_moves().is_empty() {
+                        true => "0000".to_string(),
+                        false => {
+                            let side = game.side_to_move();
+                            strategy.choose_move(&game, side, budget).to_string()
+                        }
+                    };
+                    while wait_for_stop && !stop.load(Ordering::Relaxed) {
+                        thread::park();
+                    }
+                    println!("bestmove {}", best);
+                    strategy
+                }));
+            }
+            ["stop"] | ["ponderhit"] => strategy = finish_search(&mut search, &stop).or(strategy),
+            ["quit"] => break,
+            _ => {}
+        }
+    }
+    finish_search(&mut search, &stop);
+}
+
+/// Stops the running search, which then gives its best move, and takes back its strategy
+fn finish_search(
+    search: &mut Option<JoinHandle<Box<dyn Strategy<ChessGame>>>>,
+    stop: &AtomicBool,
+) -> Option<Box<dyn Strategy<ChessGame>>> {
+    let search = search.take()?;
+    stop.store(true, Ordering::Relaxed);
+    search.thread().unpark();
+    Some(search.join().expect("Search thread panicked"))
+}
+
+/// The name and value of a `setoption name <name> [value <value>]` command
+fn parse_setoption(line: &str) -> Option<(String, String)> {
+    let option = line.trim().strip_prefix("setoption name ")?;
+    let (name, value) = option.split_once(" value ").unwrap_or((option, ""));
+    Some((name.trim().to_string(), value.trim().to_string()))
+}
+
+/// The game from a `position <startpos|fen <fen>> [moves <moves>]` command
+fn parse_position(line: &str, chess960: bool) -> anyhow::Result<ChessGame> {
+    let position = line.trim().strip_prefix("position").unwrap_or(line).trim();
+    let (start, moves) = match position.split_once("moves") {
+        Some((start, moves)) => (start.trim(), moves.trim()),
+        None => (position, ""),
+    };
+    let start = match start.strip_prefix("fen") {
+        Some(fen) => fen.trim().parse()?,
+        None if start == "startpos" => ChessPosition::new(),
+        None => anyhow::bail!("Expected startpos or fen, got {:?}", start),
+    };
+    let variant = match chess960 {
+        true => ChessVariant::Chess960(start.chess960_index().unwrap_or(STANDARD_CHESS960)),
+        false => ChessVariant::Standard,
+    };
+    let mut game = ChessGame::from_position(variant, start);
+    for uci in moves.split_whitespace() {
+        game.play(&uci.parse()?)?;
+    }
+    Ok(game)
+}
+
+/// The budget for a `go` command, or `None` for the strategy's default
+fn parse_go(args: &[&str], game: &ChessGame) -> Option<SearchBudget> {
+    let value = |name: &str| -> Option<u64> {
+        let index = args.iter().position(|&arg| arg == name)?;
+        args.get(index + 1)?.parse().ok()
+    };
+    let ms = |name: &str| value(name).map(Duration::from_millis);
+
+    if let Some(time) = ms("movetime") {
+        return Some(SearchBudget::time(time));
+    }
+    if let Some(nodes) = value("nodes") {
+        return Some(SearchBudget::nodes(nodes));
+    }
+    let (time, increment) = match game.side_to_move() {
+        ChessPlayer::White => (ms("wtime"), ms("winc")),
+        ChessPlayer::Black => (ms("btime"), ms("binc")),
+    };
+    let time = time?;
+    let moves_to_go = value("movestogo").map_or(MOVES_TO_GO, |moves| moves.max(1) as u32);
+    let move_time = time / moves_to_go + increment.unwrap_or_default() / 2;
+    // Never risk the whole clock, whatever the increment
+    Some(SearchBudget::time(move_time.min(time / 2)))
+}
@@ -1,11 +1,65 @@
-use std::fmt;
+use anyhow::anyhow;
+use std::{fmt, str::FromStr, sync::Arc};
 
-#[derive(Clone, Copy)]
+use crate::{
+    Rejection,
+    game::{Game, GameRecord},
+    protocol::{GameKind, ProtocolError, take},
+    strategy::{RandomStrategy, SearchBudget, Strategy, StrategyKind},
+};
+
+mod chess960;
+mod movegen;
+mod notation;
+mod pgn;
+mod polyglot;
+mod search;
+mod syzygy;
+mod uci;
+mod uci_player;
+mod variants;
+
+pub use chess960::{CHESS960_POSITIONS, STANDARD_CHESS960};
+pub use movegen::{ChessMove, UciMove, perft};
+pub use pgn::PgnGame;
+pub use polyglot::{
+    BOOK_DEPTH_ENV_VAR, BOOK_ENV_VAR, BookEntry, BookStrategy, DEFAULT_BOOK_DEPTH, PolyglotBook,
+    default_book, default_book_depth, polyglot_key,
+};
+pub use search::{NativeEngine, evaluate, piece_value, zobrist};
+pub use syzygy::{SYZYGY_ENV_VAR, Tablebase, Wdl, default_tablebase};
+pub use uci::{DEFAULT_ENGINE_TIMEOUT, GoLimit, PvLine, Score, SearchResult, UciEngine, UciError};
+pub use uci_player::{
+    ENGINE_ENV_VAR, UciPlayer, UciPlayerConfig, get_best_moves, pick_within_window,
+};
+pub use variants::Rules;
+
+pub const STARTING_POSITION: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
 pub enum ChessPlayer {
+    #[default]
     White,
     Black,
 }
 
+impl ChessPlayer {
+    pub fn opponent(self) -> ChessPlayer {
+        match self {
+            ChessPlayer::White => ChessPlayer::Black,
+            ChessPlayer::Black => ChessPlayer::White,
+        }
+    }
+
+    // Rank the side's pieces start on
+    fn back_rank(self) -> u8 {
+        match self {
+            ChessPlayer::White => 0,
+            ChessPlayer::Black => 7,
+        }
+    }
+}
+
 impl fmt::Display for ChessPlayer {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let str = match self {
@@ -16,86 +70,1251 @@ impl fmt::Display for ChessPlayer {
     }
 }
 
-//pub fn get_best_moves(fen: &str, top_n: usize) -> Vec<(String, String)> {
-// Send to stockfish
-//}
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PieceKind {
+    Pawn,
+    Knight,
+    Bishop,
+    Rook,
+    Queen,
+    King,
+}
+
+impl PieceKind {
+    pub const ALL: [PieceKind; 6] = [
+        PieceKind::Pawn,
+        PieceKind::Knight,
+        PieceKind::Bishop,
+        PieceKind::Rook,
+        PieceKind::Queen,
+        PieceKind::King,
+    ];
+
+    /// Lowercase FEN letter
+    pub fn to_char(self) -> char {
+        match self {
+            PieceKind::Pawn => 'p',
+            PieceKind::Knight => 'n',
+            PieceKind::Bishop => 'b',
+            PieceKind::Rook => 'r',
+            PieceKind::Queen => 'q',
+            PieceKind::King => 'k',
+        }
+    }
+
+    /// Either case
+    pub fn from_char(c: char) -> Option<PieceKind> {
+        PieceKind::ALL
+            .into_iter()
+            .find(|kind| kind.to_char() == c.to_ascii_lowercase())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Piece {
+    pub color: ChessPlayer,
+    pub kind: PieceKind,
+}
+
+impl Piece {
+    pub fn new(color: ChessPlayer, kind: PieceKind) -> Piece {
+        Piece { color, kind }
+    }
+
+    /// FEN letter, uppercase for white
+    pub fn to_char(self) -> char {
+        match self.color {
+            ChessPlayer::White => self.kind.to_char().to_ascii_uppercase(),
+            ChessPlayer::Black => self.kind.to_char(),
+        }
+    }
+
+    pub fn from_char(c: char) -> Option<Piece> {
+        let color = match c.is_ascii_uppercase() {
+            true => ChessPlayer::White,
+            false => ChessPlayer::Black,
+        };
+        PieceKind::from_char(c).map(|kind| Piece { color, kind })
+    }
+}
+
+/// One of the 64 squares, a1 = 0 up to h8 = 63
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Square(u8);
+
+impl Square {
+    pub fn new(file: u8, rank: u8) -> Square {
+        assert!(file < 8 && rank < 8, "Square off the board");
+        Square(rank * 8 + file)
+    }
+
+    pub fn from_index(index: usize) -> Square {
+        assert!(index < 64, "Square off the board");
+        Square(index as u8)
+    }
+
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+
+    pub fn file(self) -> u8 {
+        self.0 % 8
+    }
+
+    pub fn rank(self) -> u8 {
+        self.0 / 8
+    }
+
+    /// The square `files` across and `ranks` up, if still on the board
+    pub fn offset(self, files: i8, ranks: i8) -> Option<Square> {
+        let file = self.file() as i8 + files;
+        let rank = self.rank() as i8 + ranks;
+        ((0..8).contains(&file) && (0..8).contains(&rank))
+            .then(|| Square::new(file as u8, rank as u8))
+    }
+
+    pub fn all() -> impl Iterator<Item = Square> {
+        (0..64).map(Square)
+    }
+}
+
+impl fmt::Display for Square {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", (b'a' + self.file()) as char, self.rank() + 1)
+    }
+}
+
+impl FromStr for Square {
+    type Err = anyhow::Error;
+    fn from_str(str: &str) -> Result<Self, Self::Err> {
+        match str.as_bytes() {
+            &[file @ b'a'..=b'h', rank @ b'1'..=b'8'] => Ok(Square::new(file - b'a', rank - b'1')),
+            _ => Err(anyhow!("Invalid square: '{}'", str)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CastlingSide {
+    King,
+    Queen,
+}
+
+/// Files of the rooks each side may still castle with
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct CastlingRights([[Option<u8>; 2]; 2]); // By player, then side
+
+impl CastlingRights {
+    pub const NONE: CastlingRights = CastlingRights([[None; 2]; 2]);
+
+    pub fn get(&self, player: ChessPlayer, side: CastlingSide) -> Option<u8> {
+        self.0[player as usize][side as usize]
+    }
+
+    pub fn set(&mut self, player: ChessPlayer, side: CastlingSide, rook_file: Option<u8>) {
+        self.0[player as usize][side as usize] = rook_file;
+    }
+
+    /// Drops both rights of `player`, e.g. once their king has moved
+    pub fn clear(&mut self, player: ChessPlayer) {
+        self.0[player as usize] = [None; 2];
+    }
+
+    /// Drops the right using the rook on `square`, if there is one
+    pub fn remove_rook(&mut self, square: Square) {
+        for player in [ChessPlayer::White, ChessPlayer::Black] {
+            if square.rank() != player.back_rank() {
+                continue;
+            }
+            for rook_file in &mut self.0[player as usize] {
+                if *rook_file == Some(square.file()) {
+                    *rook_file = None;
+                }
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == CastlingRights::NONE
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ChessMoveError {
+    IllegalMove(UciMove),
+    PromotionRequired(UciMove), // Legal once a piece is chosen
+    WrongSide(ChessPlayer),
+    GameOver,
+    StartMismatch,
+    HistoryMismatch,
+    MoveCount(usize),
+    ConfigMismatch(ChessVariant, ChessVariant),
+}
+
+impl fmt::Display for ChessMoveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::IllegalMove(game_move) => write!(f, "Move {} is illegal", game_move),
+            Self::PromotionRequired(game_move) => {
+                write!(f, "Move {} must say which piece to promote to", game_move)
+            }
+            Self::WrongSide(side) => write!(f, "It is not {:?}'s turn", side),
+            Self::GameOver => write!(f, "The game is already over"),
+            Self::StartMismatch => write!(f, "Game starts from a different position"),
+            Self::HistoryMismatch => write!(f, "Earlier moves were changed"),
+            Self::MoveCount(count) => write!(f, "{} new moves played, expected 1", count),
+            Self::ConfigMismatch(expected, actual) => {
+                write!(f, "Game is {}, expected {}", actual, expected)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ChessMoveError {}
+
+/// A full chess position, as described by FEN
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ChessPosition {
+    board: [Option<Piece>; 64],
+    side_to_move: ChessPlayer,
+    castling: CastlingRights,
+    en_passant: Option<Square>, // Square behind a pawn which has just moved two
+    halfmove_clock: u32,        // Moves since the last capture or pawn move
+    fullmove_number: u32,
+    chess960: bool, // Castling is written king takes rook in UCI, as UCI_Chess960 does
+    rules: Rules,
+    pockets: [[u8; 5]; 2],     // Crazyhouse pieces to drop, by player then kind
+    promoted: u64,             // Crazyhouse squares of promoted pieces, by square index
+    remaining_checks: [u8; 2], // Three-check, by player
+}
+
+impl ChessPosition {
+    pub fn new() -> ChessPosition {
+        STARTING_POSITION
+            .parse()
+            .expect("Starting position is valid")
+    }
+
+    /// A board with nothing on it, for setting up positions
+    pub fn empty() -> ChessPosition {
+        ChessPosition {
+            board: [None; 64],
+            side_to_move: ChessPlayer::White,
+            castling: CastlingRights::NONE,
+            en_passant: None,
+            halfmove_clock: 0,
+            fullmove_number: 1,
+            chess960: false,
+            rules: Rules::Standard,
+            pockets: [[0; 5]; 2],
+            promoted: 0,
+            remaining_checks: [3; 2],
+        }
+    }
+
+    pub fn piece_at(&self, square: Square) -> Option<Piece> {
+        self.board[square.index()]
+    }
+
+    pub fn set_piece(&mut self, square: Square, piece: Option<Piece>) {
+        self.board[square.index()] = piece;
+    }
+
+    pub fn side_to_move(&self) -> ChessPlayer {
+        self.side_to_move
+    }
+
+    pub fn castling(&self) -> CastlingRights {
+        self.castling
+    }
+
+    pub fn en_passant(&self) -> Option<Square> {
+        self.en_passant
+    }
+
+    pub fn halfmove_clock(&self) -> u32 {
+        self.halfmove_clock
+    }
+
+    pub fn fullmove_number(&self) -> u32 {
+        self.fullmove_number
+    }
+
+    /// Whether castling moves are written in UCI as the king taking its own rook
+    ///
+    /// Set for every Chess960 game, and for FEN whose castling rights need it.
+    pub fn is_chess960(&self) -> bool {
+        self.chess960
+    }
+
+    pub fn set_chess960(&mut self, chess960: bool) {
+        self.chess960 = chess960;
+    }
+
+    pub fn pieces(&self) -> impl Iterator<Item = (Square, Piece)> + '_ {
+        Square::all().filter_map(|square| self.piece_at(square).map(|piece| (square, piece)))
+    }
+
+    pub fn king_square(&self, player: ChessPlayer) -> Option<Square> {
+        self.pieces()
+            .find(|&(_, piece)| piece == Piece::new(player, PieceKind::King))
+            .map(|(square, _)| square)
+    }
+
+    /// Checks everything FEN can get wrong
+    fn validate(&self) -> anyhow::Result<()> {
+        for player in [ChessPlayer::White, ChessPlayer::Black] {
+            let kings = self
+                .pieces()
+                .filter(|&(_, piece)| piece == Piece::new(player, PieceKind::King))
+                .count();
+            // Atomic games end with a king blown up, rather than taken
+            if kings != 1 && !(kings == 0 && self.rules == Rules::Atomic) {
+                return Err(anyhow!("Expected one {:?} king, found {}", player, kings));
+            }
+        }
+
+        if let Some((square, _)) = self
+            .pieces()
+            .find(|&(square, piece)| piece.kind == PieceKind::Pawn && square.rank() % 7 == 0)
+        {
+            return Err(anyhow!("Pawn on back rank at {}", square));
+        }
+
+        for player in [ChessPlayer::White, ChessPlayer::Black] {
+            let back_rank = player.back_rank();
+            for side in [CastlingSide::King, CastlingSide::Queen] {
+                let Some(rook_file) = self.castling.get(player, side) else {
+                    continue;
+                };
+                let king = self.king_square(player);
+                let rook = self.piece_at(Square::new(rook_file, back_rank));
+                let rook_on_side = king.is_some_and(|king| match side {
+                    CastlingSide::King => rook_file > king.file(),
+                    CastlingSide::Queen => rook_file < king.file(),
+                });
+                if king.is_none_or(|king| king.rank() != back_rank)
+                    || rook != Some(Piece::new(player, PieceKind::Rook))
+                    || !rook_on_side
+                {
+                    return Err(anyhow!(
+                        "{:?} cannot castle {:?}side from this position",
+                        player,
+                        side
+                    ));
+                }
+            }
+        }
+
+        if let Some(square) = self.en_passant {
+            // The pawn which just moved belongs to the side not to move
+            let (ep_rank, pawn_rank) = match self.side_to_move {
+                ChessPlayer::White => (5, 4),
+                ChessPlayer::Black => (2, 3),
+            };
+            let pawn = Piece::new(self.side_to_move.opponent(), PieceKind::Pawn);
+            if square.rank() != ep_rank
+                || self.piece_at(square).is_some()
+                || self.piece_at(Square::new(square.file(), pawn_rank)) != Some(pawn)
+            {
+                return Err(anyhow!("Invalid en passant square {}", square));
+            }
+        }
+
+        if self.in_check(self.side_to_move.opponent()) {
+            return Err(anyhow!("{:?} to move can take the king", self.side_to_move));
+        }
+
+        if self.fullmove_number == 0 {
+            return Err(anyhow!("Fullmove number starts at 1"));
+        }
+        Ok(())
+    }
+
+    // The side and rook file of an X-FEN or Shredder-FEN castling right, lowercased
+    fn parse_castling_right(&self, player: ChessPlayer, c: char) -> Option<(CastlingSide, u8)> {
+        let side_of = |file: u8| match self.king_square(player) {
+            Some(king) if file < king.file() => CastlingSide::Queen,
+            _ => CastlingSide::King,
+        };
+        match c {
+            // Missing rooks are left for validation to complain about
+            'k' => Some((
+                CastlingSide::King,
+                self.outermost_rook(player, CastlingSide::King).unwrap_or(7),
+            )),
+            'q' => Some((
+                CastlingSide::Queen,
+                self.outermost_rook(player, CastlingSide::Queen)
+                    .unwrap_or(0),
+            )),
+            'a'..='h' => {
+                let file = c as u8 - b'a';
+                Some((side_of(file), file))
+            }
+            _ => None,
+        }
+    }
+
+    // File of the rook furthest from the king on that side of it, on the back rank
+    fn outermost_rook(&self, player: ChessPlayer, side: CastlingSide) -> Option<u8> {
+        let rank = player.back_rank();
+        let king = self
+            .king_square(player)
+            .filter(|king| king.rank() == rank)?;
+        let rook = Some(Piece::new(player, PieceKind::Rook));
+        let mut files = match side {
+            CastlingSide::King => (king.file() + 1..8).rev().collect::<Vec<_>>(),
+            CastlingSide::Queen => (0..king.file()).collect(),
+        }
+        .into_iter();
+        files.find(|&file| self.piece_at(Square::new(file, rank)) == rook)
+    }
+}
+
+impl Default for ChessPosition {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Parses FEN for standard chess, the clocks can be left off
+impl FromStr for ChessPosition {
+    type Err = anyhow::Error;
+    fn from_str(str: &str) -> Result<Self, Self::Err> {
+        ChessPosition::from_fen(str, Rules::Standard)
+    }
+}
+
+impl ChessPosition {
+    /// Parses FEN for a variant's rules, with a crazyhouse pocket as `[Qn]` or a ninth rank,
+    /// and three-check's remaining checks as `3+3` after the en passant square
+    pub fn from_fen(str: &str, rules: Rules) -> anyhow::Result<ChessPosition> {
+        let mut fields = str.split_whitespace();
+        let mut position = ChessPosition::empty();
+        position.rules = rules;
+
+        let placement = fields.next().ok_or_else(|| anyhow!("Empty FEN"))?;
+        let (placement, pocket) = match placement.strip_suffix(']') {
+            Some(placement) => {
+                let (placement, pocket) = placement
+                    .split_once('[')
+                    .ok_or_else(|| anyhow!("Unopened pocket in '{}'", placement))?;
+                (placement, Some(pocket))
+            }
+            None => match placement.split('/').count() {
+                9 => {
+                    let (placement, pocket) = placement.rsplit_once('/').expect("Nine ranks");
+                    (placement, Some(pocket))
+                }
+                _ => (placement, None),
+            },
+        };
+        if let Some(pocket) = pocket {
+            if rules != Rules::Crazyhouse && !pocket.is_empty() {
+                return Err(anyhow!("Only crazyhouse has pockets"));
+            }
+            for c in pocket.chars() {
+                let piece = Piece::from_char(c)
+                    .filter(|piece| piece.kind != PieceKind::King)
+                    .ok_or_else(|| anyhow!("Invalid piece in pocket '{}'", c))?;
+                position.pockets[piece.color as usize][piece.kind as usize] += 1;
+            }
+        }
+
+        let ranks = placement.split('/').collect::<Vec<_>>();
+        if ranks.len() != 8 {
+            return Err(anyhow!("Expected 8 ranks, found {}", ranks.len()));
+        }
+        for (i, rank) in ranks.iter().enumerate() {
+            let rank_index = 7 - i as u8; // FEN starts from the 8th rank
+            let mut file = 0;
+            for c in rank.chars() {
+                if let Some(empty) = c.to_digit(10).filter(|n| (1..=8).contains(n)) {
+                    file += empty as u8;
+                } else if c == '~' {
+                    // Marks the piece before as promoted, so it is a pawn again once captured
+                    let promoted = file
+                        .checked_sub(1)
+                        .map(|file| Square::new(file, rank_index));
+                    match promoted.filter(|&square| position.piece_at(square).is_some()) {
+                        Some(square) if rules == Rules::Crazyhouse => {
+                            position.set_promoted(square, true)
+                        }
+                        _ => {
+                            return Err(anyhow!(
+                                "Misplaced promoted marker on rank {}",
+                                rank_index + 1
+                            ));
+                        }
+                    }
+                } else {
+                    let piece =
+                        Piece::from_char(c).ok_or_else(|| anyhow!("Invalid piece '{}'", c))?;
+                    if file >= 8 {
+                        return Err(anyhow!("Too many squares on rank {}", rank_index + 1));
+                    }
+                    position.set_piece(Square::new(file, rank_index), Some(piece));
+                    file += 1;
+                }
+                if file > 8 {
+                    return Err(anyhow!("Too many squares on rank {}", rank_index + 1));
+                }
+            }
+            if file != 8 {
+                return Err(anyhow!("Too few squares on rank {}", rank_index + 1));
+            }
+        }
+
+        position.side_to_move = match fields.next() {
+            Some("w") => ChessPlayer::White,
+            Some("b") => ChessPlayer::Black,
+            side => return Err(anyhow!("Invalid side to move: {:?}", side)),
+        };
+
+        match fields.next() {
+            Some("-") => {}
+            Some(castling) => {
+                for c in castling.chars() {
+                    let player = match c.is_ascii_uppercase() {
+                        true => ChessPlayer::White,
+                        false => ChessPlayer::Black,
+                    };
+                    let (side, rook_file) = position
+                        .parse_castling_right(player, c.to_ascii_lowercase())
+                        .ok_or_else(|| anyhow!("Invalid castling rights '{}'", castling))?;
+                    if position.castling.get(player, side).is_some() {
+                        return Err(anyhow!("Repeated castling right '{}'", c));
+                    }
+                    position.castling.set(player, side, Some(rook_file));
+                }
+            }
+            None => return Err(anyhow!("Missing castling rights")),
+        }
+        // Only standard castling can be written with the king's destination
+        position.chess960 = [ChessPlayer::White, ChessPlayer::Black]
+            .into_iter()
+            .any(|player| {
+                let king_moved =
+                    position.king_square(player) != Some(Square::new(4, player.back_rank()));
+                let rooks = [CastlingSide::King, CastlingSide::Queen]
+                    .map(|side| position.castling.get(player, side));
+                (rooks != [None; 2] && king_moved)
+                    || !matches!(rooks[0], None | Some(7))
+                    || !matches!(rooks[1], None | Some(0))
+            });
+
+        position.en_passant = match fields.next() {
+            Some("-") => None,
+            Some(square) => Some(square.parse()?),
+            None => return Err(anyhow!("Missing en passant square")),
+        };
+
+        let mut fields = fields.peekable();
+        if let Some(checks) = fields.next_if(|field| field.contains('+')) {
+            let remaining = checks
+                .split_once('+')
+                .and_then(|(white, black)| Some([white.parse().ok()?, black.parse().ok()?]))
+                .filter(|remaining: &[u8; 2]| remaining.iter().all(|&checks| checks <= 3));
+            match remaining {
+                Some(remaining) if rules == Rules::ThreeCheck => {
+                    position.remaining_checks = remaining
+                }
+                _ => return Err(anyhow!("Invalid remaining checks '{}'", checks)),
+            }
+        }
+
+        if let Some(clock) = fields.next() {
+            position.halfmove_clock = clock
+                .parse()
+                .map_err(|_| anyhow!("Invalid halfmove clock '{}'", clock))?;
+        }
+        if let Some(number) = fields.next() {
+            position.fullmove_number = number
+                .parse()
+                .map_err(|_| anyhow!("Invalid fullmove number '{}'", number))?;
+        }
+        if let Some(extra) = fields.next() {
+            return Err(anyhow!("Unexpected field in FEN: '{}'", extra));
+        }
+
+        position.validate()?;
+        Ok(position)
+    }
+}
+
+/// Writes FEN
+impl fmt::Display for ChessPosition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for rank in (0..8).rev() {
+            let mut empty = 0;
+            for file in 0..8 {
+                let square = Square::new(file, rank);
+                match self.piece_at(square) {
+                    Some(piece) => {
+                        if empty > 0 {
+                            write!(f, "{}", empty)?;
+                            empty = 0;
+                        }
+                        write!(f, "{}", piece.to_char())?;
+                        if self.is_promoted(square) {
+                            write!(f, "~")?;
+                        }
+                    }
+                    None => empty += 1,
+                }
+            }
+            if empty > 0 {
+                write!(f, "{}", empty)?;
+            }
+            if rank > 0 {
+                write!(f, "/")?;
+            }
+        }
+        if self.rules == Rules::Crazyhouse {
+            write!(f, "[")?;
+            for player in [ChessPlayer::White, ChessPlayer::Black] {
+                for kind in PieceKind::ALL[..5].iter().rev() {
+                    let piece = Piece::new(player, *kind).to_char();
+                    for _ in 0..self.pocket(player, *kind) {
+                        write!(f, "{}", piece)?;
+                    }
+                }
+            }
+            write!(f, "]")?;
+        }
+
+        write!(f, " {} ", self.side_to_move)?;
+
+        if self.castling.is_empty() {
+            write!(f, "-")?;
+        }
+        for player in [ChessPlayer::White, ChessPlayer::Black] {
+            for side in [CastlingSide::King, CastlingSide::Queen] {
+                if let Some(rook_file) = self.castling.get(player, side) {
+                    // X-FEN, which only names the file when another rook is further out
+                    let c = match self.outermost_rook(player, side) == Some(rook_file) {
+                        true => match side {
+                            CastlingSide::King => 'k',
+                            CastlingSide::Queen => 'q',
+                        },
+                        false => (b'a' + rook_file) as char,
+                    };
+                    let c = match player {
+                        ChessPlayer::White => c.to_ascii_uppercase(),
+                        ChessPlayer::Black => c,
+                    };
+                    write!(f, "{}", c)?;
+                }
+            }
+        }
+
+        match self.en_passant {
+            Some(square) => write!(f, " {}", square)?,
+            None => write!(f, " -")?,
+        }
+        if self.rules == Rules::ThreeCheck {
+            let [white, black] = self.remaining_checks;
+            write!(f, " {}+{}", white, black)?;
+        }
+        write!(f, " {} {}", self.halfmove_clock, self.fullmove_number)
+    }
+}
+
+pub fn pretty_print_position(position: &ChessPosition) {
+    for rank in (0..8).rev() {
+        let row = (0..8)
+            .map(|file| match position.piece_at(Square::new(file, rank)) {
+                Some(piece) => piece.to_char(),
+                None => '.',
+            })
+            .collect::<String>();
+        println!("{} {}", rank + 1, row);
+    }
+    println!("  abcdefgh");
+    for player in [ChessPlayer::White, ChessPlayer::Black] {
+        match position.rules() {
+            Rules::Crazyhouse => {
+                let pocket = PieceKind::ALL
+                    .into_iter()
+                    .flat_map(|kind| {
+                        let piece = Piece::new(player, kind).to_char();
+                        std::iter::repeat_n(piece, position.pocket(player, kind) as usize)
+                    })
+                    .collect::<String>();
+                match pocket.is_empty() {
+                    true => println!("{:?} pocket: -", player),
+                    false => println!("{:?} pocket: {}", player, pocket),
+                }
+            }
+            Rules::ThreeCheck => {
+                let checks = position.remaining_checks(player);
+                println!("{:?} needs {} more checks", player, checks);
+            }
+            _ => {}
+        }
+    }
+}
+
+// Same FNV-1a as the tic-tac-toe state hash
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+impl ChessPosition {
+    /// Identifies the position for repetitions - pieces, side to move, castling and en passant
+    ///
+    /// En passant only counts when the capture is actually legal, as FIDE rules require.
+    pub fn repetition_key(&self) -> u64 {
+        let mut position = self.clone();
+        if self.en_passant.is_some()
+            && !self
+                .legal_moves()
+                .iter()
+                .any(|game_move| matches!(game_move, ChessMove::EnPassant { .. }))
+        {
+            position.en_passant = None;
+        }
+        position.halfmove_clock = 0;
+        position.fullmove_number = 1;
+        fnv1a(position.to_string().as_bytes())
+    }
+
+    /// Whether neither side can possibly checkmate - bare kings, a single minor piece,
+    /// or only bishops all on squares of one colour
+    ///
+    /// Variants which can be won without mating only count bare kings, if even that.
+    pub fn is_insufficient_material(&self) -> bool {
+        let bare_kings = || {
+            self.pieces()
+                .all(|(_, piece)| piece.kind == PieceKind::King)
+        };
+        match self.rules {
+            Rules::Standard => {}
+            Rules::KingOfTheHill => return false, // Kings can still walk to the centre
+            Rules::ThreeCheck | Rules::Atomic => return bare_kings(),
+            Rules::Crazyhouse => {
+                // A single minor piece on the board or in a pocket, as captures come back
+                let board = self.pieces().map(|(_, piece)| piece.kind);
+                let pockets = self.pockets.iter().flat_map(|pocket| {
+                    PieceKind::ALL
+                        .into_iter()
+                        .zip(pocket)
+                        .flat_map(|(kind, &count)| std::iter::repeat_n(kind, count as usize))
+                });
+                let material = board
+                    .chain(pockets)
+                    .filter(|&kind| kind != PieceKind::King)
+                    .collect::<Vec<_>>();
+                return matches!(material[..], [] | [PieceKind::Knight] | [PieceKind::Bishop]);
+            }
+        }
+        let (mut knights, mut bishops) = (0, 0);
+        let mut bishop_colours = [false; 2];
+        for (square, piece) in self.pieces() {
+            match piece.kind {
+                PieceKind::King => {}
+                PieceKind::Knight => knights += 1,
+                PieceKind::Bishop => {
+                    bishops += 1;
+                    bishop_colours[((square.file() + square.rank()) % 2) as usize] = true;
+                }
+                _ => return false,
+            }
+        }
+        let both_colours = bishop_colours[0] && bishop_colours[1];
+        knights + bishops <= 1 || (knights == 0 && !both_colours)
+    }
+}
+
+/// Which rules a chess game is played by
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ChessVariant {
+    #[default]
+    Standard,
+    Chess960(u16), // Start position number, 0 to 959
+    KingOfTheHill,
+    ThreeCheck,
+    Atomic,
+    Crazyhouse,
+}
+
+impl ChessVariant {
+    /// Where a new game of this variant starts
+    pub fn start_position(&self) -> ChessPosition {
+        let mut position = match *self {
+            ChessVariant::Chess960(index) => ChessPosition::chess960(index),
+            _ => ChessPosition::new(),
+        };
+        position.set_rules(self.rules());
+        position
+    }
+
+    /// How pieces move and games end, which is standard for Chess960
+    pub fn rules(&self) -> Rules {
+        match self {
+            ChessVariant::Standard | ChessVariant::Chess960(_) => Rules::Standard,
+            ChessVariant::KingOfTheHill => Rules::KingOfTheHill,
+            ChessVariant::ThreeCheck => Rules::ThreeCheck,
+            ChessVariant::Atomic => Rules::Atomic,
+            ChessVariant::Crazyhouse => Rules::Crazyhouse,
+        }
+    }
+}
+
+/// The name, with the start position for Chess960, e.g. "chess960:518"
+impl fmt::Display for ChessVariant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChessVariant::Standard => write!(f, "standard"),
+            ChessVariant::Chess960(index) => write!(f, "chess960:{}", index),
+            ChessVariant::KingOfTheHill => write!(f, "kingofthehill"),
+            ChessVariant::ThreeCheck => write!(f, "threecheck"),
+            ChessVariant::Atomic => write!(f, "atomic"),
+            ChessVariant::Crazyhouse => write!(f, "crazyhouse"),
+        }
+    }
+}
+
+/// Parses the name, with plain "chess960" picking a random start position
+impl FromStr for ChessVariant {
+    type Err = anyhow::Error;
+    fn from_str(str: &str) -> Result<Self, Self::Err> {
+        match str.split_once(':') {
+            None if str == "standard" => Ok(ChessVariant::Standard),
+            None if str == "kingofthehill" || str == "koth" => Ok(ChessVariant::KingOfTheHill),
+            None if str == "threecheck" || str == "3check" => Ok(ChessVariant::ThreeCheck),
+            None if str == "atomic" => Ok(ChessVariant::Atomic),
+            None if str == "crazyhouse" => Ok(ChessVariant::Crazyhouse),
+            None if str == "chess960" => Ok(ChessVariant::Chess960(rand::random_range(
+                0..CHESS960_POSITIONS,
+            ))),
+            Some(("chess960", index)) => match index.parse() {
+                Ok(index) if index < CHESS960_POSITIONS => Ok(ChessVariant::Chess960(index)),
+                _ => Err(anyhow!("Invalid Chess960 position: '{}'", index)),
+            },
+            _ => Err(anyhow!("Unknown chess variant: '{}'", str)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChessGameResult {
+    Checkmate(ChessPlayer), // Winner
+    Stalemate,
+    InsufficientMaterial,
+    FiftyMoves, // Claimed
+    SeventyFiveMoves,
+    ThreefoldRepetition, // Claimed
+    FivefoldRepetition,
+    KingOfTheHill(ChessPlayer), // Winner, whose king reached the centre
+    ThreeChecks(ChessPlayer),   // Winner
+    KingExploded(ChessPlayer),  // Winner, in atomic chess
+    TablebaseDraw,              // Adjudicated
+}
+
+impl ChessGameResult {
+    pub fn winner(&self) -> Option<ChessPlayer> {
+        match self {
+            ChessGameResult::Checkmate(winner)
+            | ChessGameResult::KingOfTheHill(winner)
+            | ChessGameResult::ThreeChecks(winner)
+            | ChessGameResult::KingExploded(winner) => Some(*winner),
+            _ => None,
+        }
+    }
+
+    /// Score as PGN writes it
+    pub fn score(&self) -> &'static str {
+        match self.winner() {
+            Some(ChessPlayer::White) => "1-0",
+            Some(ChessPlayer::Black) => "0-1",
+            None => "1/2-1/2",
+        }
+    }
+
+    pub fn reason(&self) -> &'static str {
+        match self {
+            ChessGameResult::Checkmate(_) => "checkmate",
+            ChessGameResult::Stalemate => "stalemate",
+            ChessGameResult::InsufficientMaterial => "insufficient-material",
+            ChessGameResult::FiftyMoves => "fifty-move-rule",
+            ChessGameResult::SeventyFiveMoves => "seventy-five-move-rule",
+            ChessGameResult::ThreefoldRepetition => "threefold-repetition",
+            ChessGameResult::FivefoldRepetition => "fivefold-repetition",
+            ChessGameResult::KingOfTheHill(_) => "king-of-the-hill",
+            ChessGameResult::ThreeChecks(_) => "three-checks",
+            ChessGameResult::KingExploded(_) => "king-exploded",
+            ChessGameResult::TablebaseDraw => "tablebase-draw",
+        }
+    }
+}
+
+/// Score and reason, e.g. "1-0 checkmate", so both ends agree on both
+impl fmt::Display for ChessGameResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.score(), self.reason())
+    }
+}
+
+/// A chess game from its start position, keeping every move for repetitions
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChessGame {
+    variant: ChessVariant,
+    start: ChessPosition,
+    position: ChessPosition,
+    moves: Vec<ChessMove>,
+    history: Vec<u64>, // Repetition keys of every position so far, including the current one
+}
+
+impl ChessGame {
+    pub fn new(variant: ChessVariant) -> ChessGame {
+        Self::from_position(variant, variant.start_position())
+    }
+
+    pub fn from_position(variant: ChessVariant, mut start: ChessPosition) -> ChessGame {
+        if let ChessVariant::Chess960(_) = variant {
+            start.set_chess960(true);
+        }
+        start.set_rules(variant.rules());
+        ChessGame {
+            variant,
+            history: vec![start.repetition_key()],
+            position: start.clone(),
+            start,
+            moves: vec![],
+        }
+    }
+
+    pub fn variant(&self) -> ChessVariant {
+        self.variant
+    }
+
+    pub fn start(&self) -> &ChessPosition {
+        &self.start
+    }
+
+    pub fn position(&self) -> &ChessPosition {
+        &self.position
+    }
+
+    pub fn moves(&self) -> &[ChessMove] {
+        &self.moves
+    }
+
+    /// How many times the current position has appeared, counting this one
+    pub fn repetitions(&self) -> usize {
+        let current = self.history.last().expect("History has the start position");
+        self.history.iter().filter(|&key| key == current).count()
+    }
+
+    /// Plays a move for the side to move, failing without changing anything if it is illegal
+    pub fn play(&mut self, uci: &UciMove) -> Result<ChessMove, ChessMoveError> {
+        if chess_get_game_status(self, false).is_some() {
+            return Err(ChessMoveError::GameOver);
+        }
+        let Some(game_move) = self.position.find_move(uci) else {
+            let promotes = uci.promotion.is_none()
+                && self.position.legal_moves().iter().any(|game_move| {
+                    let legal = game_move.to_uci();
+                    (legal.from, legal.to) == (uci.from, uci.to) && legal.promotion.is_some()
+                });
+            return Err(match promotes {
+                true => ChessMoveError::PromotionRequired(*uci),
+                false => ChessMoveError::IllegalMove(*uci),
+            });
+        };
+        self.position.play_unchecked(&game_move);
+        self.moves.push(game_move);
+        self.history.push(self.position.repetition_key());
+        Ok(game_move)
+    }
+}
+
+/// Start position FEN, then the moves played from it - "<fen> moves e2e4 e7e5"
+///
+/// Other variants come first, e.g. "chess960:518 <fen> moves e2e4".
+impl fmt::Display for ChessGame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.variant != ChessVariant::Standard {
+            write!(f, "{} ", self.variant)?;
+        }
+        write!(f, "{}", self.start)?;
+        if !self.moves.is_empty() {
+            write!(f, " moves")?;
+        }
+        for game_move in &self.moves {
+            write!(f, " {}", self.start.uci(game_move))?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses and replays the moves, failing on any illegal one
+impl FromStr for ChessGame {
+    type Err = anyhow::Error;
+    fn from_str(str: &str) -> Result<Self, Self::Err> {
+        let (variant, str) = match str.trim_start().split_once(' ') {
+            // FEN always starts with the ranks
+            Some((variant, rest)) if !variant.contains('/') => (variant.parse()?, rest),
+            _ => (ChessVariant::Standard, str),
+        };
+        let (fen, moves) = match str.split_once(" moves ") {
+            Some((fen, moves)) => (fen, moves),
+            None => (str, ""),
+        };
+        let start = ChessPosition::from_fen(fen, variant.rules())?;
+        let mut game = ChessGame::from_position(variant, start);
+        for uci in moves.split_whitespace() {
+            game.play(&uci.parse()?)?;
+        }
+        Ok(game)
+    }
+}
+
+/// The result if the game is over, with `claim` when the side to move asks for a
+/// fifty-move or threefold repetition draw rather than it being automatic
+pub fn chess_get_game_status(game: &ChessGame, claim: bool) -> Option<ChessGameResult> {
+    let position = &game.position;
+    if let Some(result) = position.variant_outcome() {
+        return Some(result);
+    }
+    if position.legal_moves().is_empty() {
+        return Some(match position.is_check() {
+            true => ChessGameResult::Checkmate(position.side_to_move().opponent()),
+            false => ChessGameResult::Stalemate,
+        });
+    }
+    if position.is_insufficient_material() {
+        return Some(ChessGameResult::InsufficientMaterial);
+    }
+
+    let repetitions = game.repetitions();
+    if repetitions >= 5 {
+        return Some(ChessGameResult::FivefoldRepetition);
+    }
+    if position.halfmove_clock() >= 150 {
+        return Some(ChessGameResult::SeventyFiveMoves);
+    }
+    if claim && repetitions >= 3 {
+        return Some(ChessGameResult::ThreefoldRepetition);
+    }
+    if claim && position.halfmove_clock() >= 100 {
+        return Some(ChessGameResult::FiftyMoves);
+    }
+    None
+}
+
+impl Game for ChessGame {
+    type Move = UciMove;
+    type Side = ChessPlayer;
+    type Config = ChessVariant;
+    type Outcome = ChessGameResult;
+    type Referee = Option<Arc<Tablebase>>; // Tables to adjudicate drawn endgames with
+    type MoveError = ChessMoveError;
+
+    const KIND: GameKind = GameKind::Chess;
+
+    fn with_config(variant: ChessVariant) -> Self {
+        ChessGame::new(variant)
+    }
+
+    fn config(&self) -> ChessVariant {
+        self.variant
+    }
 
-/*
-pub fn get_best_moves_chess() {
-    let mut child = Command::new("stockfish") // Make sure Stockfish is in PATH
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .spawn()
-        .expect("Failed to start Stockfish");
+    fn opponent(side: ChessPlayer) -> ChessPlayer {
+        side.opponent()
+    }
 
-    let mut stdin = child.stdin.take().expect("Failed to open stdin");
-    let stdout = child.stdout.take().expect("Failed to open stdout");
-    let mut reader = BufReader::new(stdout);
+    fn side_to_move(&self) -> ChessPlayer {
+        self.position.side_to_move()
+    }
 
-    // Send UCI command
-    writeln!(stdin, "uci").unwrap();
-    println!("Sending uci");
-    stdin.flush().unwrap();
+    fn moves_played(&self) -> usize {
+        self.moves.len()
+    }
 
-    let mut line = String::new();
-    while reader.read_line(&mut line).unwrap() > 0 {
-        println!("Stockfish: {}", line.trim());
-        if line.trim() == "uciok" {
-            break;
+    fn legal_moves(&self) -> Vec<UciMove> {
+        match self.status() {
+            Some(_) => vec![],
+            None => self
+                .position
+                .legal_moves()
+                .iter()
+                .map(|game_move| self.position.uci(game_move))
+                .collect(),
         }
-        line.clear();
     }
 
+    fn apply_move(&mut self, game_move: &UciMove, side: ChessPlayer) -> Result<(), ChessMoveError> {
+        if side != self.side_to_move() {
+            return Err(ChessMoveError::WrongSide(side));
+        }
+        self.play(game_move).map(|_| ())
+    }
 
-    writeln!(stdin, "isready").unwrap();
-    stdin.flush().unwrap();
+    fn diff(&self, next: &Self, side: ChessPlayer) -> Result<UciMove, ChessMoveError> {
+        if self.variant != next.variant {
+            return Err(ChessMoveError::ConfigMismatch(self.variant, next.variant));
+        }
+        if self.start != next.start {
+            return Err(ChessMoveError::StartMismatch);
+        }
+        if side != self.side_to_move() {
+            return Err(ChessMoveError::WrongSide(side));
+        }
+        if !next.moves.starts_with(&self.moves) {
+            return Err(ChessMoveError::HistoryMismatch);
+        }
+        match next.moves[self.moves.len()..] {
+            [game_move] => Ok(self.position.uci(&game_move)),
+            ref new_moves => Err(ChessMoveError::MoveCount(new_moves.len())),
+        }
+    }
+
+    fn status(&self) -> Option<ChessGameResult> {
+        chess_get_game_status(self, false)
+    }
+
+    fn winner(outcome: &ChessGameResult) -> Option<ChessPlayer> {
+        outcome.winner()
+    }
+
+    fn claim_draw(&self) -> Option<ChessGameResult> {
+        match self.status() {
+            Some(_) => None,
+            None => chess_get_game_status(self, true),
+        }
+    }
+
+    fn adjudicate(&self, tablebase: &Option<Arc<Tablebase>>) -> Option<ChessGameResult> {
+        // Drawn with best play, counting what the halfmove clock has used up of the fifty moves
+        let drawn = tablebase.as_ref()?.is_drawn(&self.position).ok()?;
+        drawn.then_some(ChessGameResult::TablebaseDraw)
+    }
 
-    let mut line = String::new();
-    while reader.read_line(&mut line).unwrap() > 0 {
-        println!("Stockfish: {}", line.trim());
-        if line.trim() == "readyok" {
-            break;
+    fn rejection(error: &ChessMoveError) -> Rejection {
+        match error {
+            ChessMoveError::PromotionRequired(_) => Rejection::PromotionRequired,
+            _ => Rejection::IllegalMove,
         }
-        line.clear();
     }
 
-    let pv = 5;
+    fn state_hash(&self) -> u64 {
+        fnv1a(self.to_string().as_bytes())
+    }
+
+    fn pretty_print(&self) {
+        pretty_print_position(&self.position);
+    }
+
+    fn export(record: &GameRecord<Self>) -> Option<String> {
+        Some(PgnGame::from_record(record).to_string())
+    }
+
+    fn strategy(kind: StrategyKind) -> Option<Box<dyn Strategy<Self>>> {
+        let strategy: Box<dyn Strategy<Self>> = match kind {
+            StrategyKind::Random => Box::new(RandomStrategy),
+            StrategyKind::Minimax => {
+                let engine = NativeEngine::new(SearchBudget::default());
+                match default_tablebase() {
+                    Some(tablebase) => Box::new(engine.with_tablebase(tablebase)),
+                    None => Box::new(engine),
+                }
+            }
+            StrategyKind::Engine => Box::new(UciPlayer::new(UciPlayerConfig::default())),
+            StrategyKind::Threats | StrategyKind::Mcts | StrategyKind::Human => return None,
+        };
+        // Every bot opens from the book when one is configured
+        Some(match default_book() {
+            Some(book) => Box::new(BookStrategy::new(book, default_book_depth(), strategy)),
+            None => strategy,
+        })
+    }
+
+    fn parse(str: &str) -> anyhow::Result<Self> {
+        str.parse()
+    }
+
+    fn format_move(game_move: &UciMove) -> String {
+        game_move.to_string()
+    }
+
+    fn parse_move(str: &str) -> Option<UciMove> {
+        str.parse().ok()
+    }
 
-    writeln!(stdin, "setoption name MultiPV value {pv}\nisready").unwrap();
-    stdin.flush().unwrap();
+    fn parse_move_text(&self, str: &str) -> Option<UciMove> {
+        self.position
+            .parse_move_text(str)
+            .map(|game_move| self.position.uci(&game_move))
+    }
 
-    let mut line = String::new();
-    while reader.read_line(&mut line).unwrap() > 0 {
-        println!("Stockfish: {}", line.trim());
-        if line.trim() == "readyok" {
-            break;
+    fn move_text(&self, game_move: &UciMove) -> String {
+        match self.position.find_move(game_move) {
+            Some(game_move) => self.position.to_san(&game_move),
+            None => game_move.to_string(),
         }
-        line.clear();
     }
 
+    fn parse_side(str: &str) -> Option<ChessPlayer> {
+        match str {
+            "w" => Some(ChessPlayer::White),
+            "b" => Some(ChessPlayer::Black),
+            _ => None,
+        }
+    }
 
-    let mut line = String::new();
-    while reader.read_line(&mut line).unwrap() > 0 {
-        println!("Stockfish: {}", line.trim());
-        if line.trim() == "uciok" {
-            break;
+    fn encode_side(side: ChessPlayer) -> u8 {
+        match side {
+            ChessPlayer::White => 0,
+            ChessPlayer::Black => 1,
         }
-        line.clear();
     }
 
-    // Set position and ask for best move
-    writeln!(stdin, "position startpos").unwrap();
-    writeln!(stdin, "go depth 10").unwrap();
-    stdin.flush().unwrap();
+    fn decode_side(byte: u8) -> Result<ChessPlayer, ProtocolError> {
+        match byte {
+            0 => Ok(ChessPlayer::White),
+            1 => Ok(ChessPlayer::Black),
+            _ => Err(ProtocolError::InvalidPlayer(byte)),
+        }
+    }
 
-    loop {
-        let mut output = String::new();
-        if reader.read_line(&mut output).unwrap() == 0 {
-            break;
+    fn encode_config(variant: &ChessVariant, bytes: &mut Vec<u8>) {
+        match *variant {
+            ChessVariant::Standard => bytes.push(0),
+            ChessVariant::Chess960(index) => {
+                bytes.push(1);
+                bytes.extend(index.to_be_bytes());
+            }
+            ChessVariant::KingOfTheHill => bytes.push(2),
+            ChessVariant::ThreeCheck => bytes.push(3),
+            ChessVariant::Atomic => bytes.push(4),
+            ChessVariant::Crazyhouse => bytes.push(5),
         }
-        println!("Stockfish: {}", output.trim());
-        if output.starts_with("bestmove") {
-            break;
+    }
+
+    fn decode_config(bytes: &mut &[u8]) -> Result<ChessVariant, ProtocolError> {
+        match take(bytes, 1)?[0] {
+            0 => Ok(ChessVariant::Standard),
+            1 => match u16::from_be_bytes(take(bytes, 2)?.try_into().unwrap()) {
+                index if index < CHESS960_POSITIONS => Ok(ChessVariant::Chess960(index)),
+                index => Err(ProtocolError::InvalidGameConfig(format!(
+                    "Invalid Chess960 position {}",
+                    index
+                ))),
+            },
+            2 => Ok(ChessVariant::KingOfTheHill),
+            3 => Ok(ChessVariant::ThreeCheck),
+            4 => Ok(ChessVariant::Atomic),
+            5 => Ok(ChessVariant::Crazyhouse),
+            byte => Err(ProtocolError::InvalidGameConfig(format!(
+                "Unknown chess variant {}",
+                byte
+            ))),
         }
     }
 }
@@ -105,16 +1324,262 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_response() {
-        let result = 3; //get_best_moves(STARTING_POSITION, 2);
+    fn test_fen_round_trip() {
+        let fens = [
+            STARTING_POSITION,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "r3k2r/8/8/8/8/8/8/4K2R b Kq - 12 40",
+        ];
+        for fen in fens {
+            let position = fen.parse::<ChessPosition>().unwrap();
+            assert_eq!(position.to_string(), fen);
+        }
+
+        let position = ChessPosition::new();
+        assert_eq!(
+            position.piece_at("e1".parse().unwrap()),
+            Some(Piece::new(ChessPlayer::White, PieceKind::King))
+        );
+        assert_eq!(
+            position
+                .castling()
+                .get(ChessPlayer::Black, CastlingSide::Queen),
+            Some(0)
+        );
 
-        println!("{:?}", result);
+        // Clocks are optional
+        let position = "8/8/8/4k3/8/8/8/4K3 b - -"
+            .parse::<ChessPosition>()
+            .unwrap();
+        assert_eq!(position.to_string(), "8/8/8/4k3/8/8/8/4K3 b - - 0 1");
     }
 
     #[test]
-    fn test_stockfish() {
-        get_moves();
+    fn test_invalid_fen() {
+        let fens = [
+            "",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP w KQkq - 0 1",
+            "rnbqkbnr/pppppppp/9/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "rnbqkbnr/ppppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR x KQkq - 0 1",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNX w KQkq - 0 1",
+            "rnbqqbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQ - 0 1",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBN1 w KQkq - 0 1",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq e3 0 1",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 0",
+            "rnbqkbnP/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQ - 0 1",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1 extra",
+        ];
+        for fen in fens {
+            assert!(fen.parse::<ChessPosition>().is_err(), "{}", fen);
+        }
     }
-}
 
-*/
+    #[test]
+    fn test_game_status() {
+        let status = |str: &str, claim: bool| {
+            let game = str.parse::<ChessGame>().unwrap();
+            chess_get_game_status(&game, claim).map(|result| result.to_string())
+        };
+
+        // Fool's mate
+        let fools_mate = format!("{} moves f2f3 e7e5 g2g4 d8h4", STARTING_POSITION);
+        assert_eq!(status(&fools_mate, false).as_deref(), Some("0-1 checkmate"));
+        assert_eq!(
+            status("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1", false).as_deref(),
+            Some("1/2-1/2 stalemate")
+        );
+        assert_eq!(
+            status("8/8/4k3/8/8/2B5/3K4/8 w - - 0 1", false).as_deref(),
+            Some("1/2-1/2 insufficient-material")
+        );
+        // Bishops on the same colour can't mate, on different colours they can
+        assert_eq!(
+            status("8/8/4kb2/8/8/2B5/3K4/8 w - - 0 1", false).as_deref(),
+            Some("1/2-1/2 insufficient-material")
+        );
+        assert_eq!(status("8/8/4k3/5b2/8/2B5/3K4/8 w - - 0 1", false), None);
+
+        let fifty = "8/8/4k3/8/8/2R5/3K4/8 w - - 100 80";
+        assert_eq!(status(fifty, false), None);
+        assert_eq!(
+            status(fifty, true).as_deref(),
+            Some("1/2-1/2 fifty-move-rule")
+        );
+        assert_eq!(
+            status("8/8/4k3/8/8/2R5/3K4/8 w - - 150 80", false).as_deref(),
+            Some("1/2-1/2 seventy-five-move-rule")
+        );
+
+        let shuffle = " g1f3 g8f6 f3g1 f6g8";
+        let twice = format!("{} moves{}{}", STARTING_POSITION, shuffle, shuffle);
+        assert_eq!(status(&twice, false), None);
+        assert_eq!(
+            status(&twice, true).as_deref(),
+            Some("1/2-1/2 threefold-repetition")
+        );
+        let four_times = format!("{}{}{}", twice, shuffle, shuffle);
+        assert_eq!(
+            status(&four_times, false).as_deref(),
+            Some("1/2-1/2 fivefold-repetition")
+        );
+
+        // Claims only while the game is still going
+        let claim = |str: &str| str.parse::<ChessGame>().unwrap().claim_draw();
+        assert_eq!(claim(&twice), Some(ChessGameResult::ThreefoldRepetition));
+        assert_eq!(claim(&four_times), None);
+        assert_eq!(claim(STARTING_POSITION), None);
+
+        // No more moves once it is over
+        let mut game = four_times.parse::<ChessGame>().unwrap();
+        assert_eq!(
+            game.play(&"e2e4".parse().unwrap()),
+            Err(ChessMoveError::GameOver)
+        );
+
+        let mut game = "7k/P7/8/8/8/8/8/K7 w - - 0 1".parse::<ChessGame>().unwrap();
+        let error = game.play(&"a7a8".parse().unwrap()).unwrap_err();
+        assert_eq!(ChessGame::rejection(&error), Rejection::PromotionRequired);
+        assert!(game.play(&"a7a8n".parse().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn test_game_diff() {
+        let before = format!("{} moves e2e4", STARTING_POSITION)
+            .parse::<ChessGame>()
+            .unwrap();
+        let mut after = before.clone();
+        after
+            .apply_move(&"e7e5".parse().unwrap(), ChessPlayer::Black)
+            .unwrap();
+        assert_eq!(after.to_string().parse::<ChessGame>().unwrap(), after);
+
+        assert_eq!(
+            before.diff(&after, ChessPlayer::Black),
+            Ok("e7e5".parse().unwrap())
+        );
+        assert_eq!(
+            before.diff(&after, ChessPlayer::White),
+            Err(ChessMoveError::WrongSide(ChessPlayer::White))
+        );
+        assert_eq!(
+            before.diff(&before, ChessPlayer::Black),
+            Err(ChessMoveError::MoveCount(0))
+        );
+        let other = format!("{} moves d2d4 e7e5", STARTING_POSITION)
+            .parse::<ChessGame>()
+            .unwrap();
+        assert_eq!(
+            before.diff(&other, ChessPlayer::Black),
+            Err(ChessMoveError::HistoryMismatch)
+        );
+        assert!(
+            format!("{} moves e2e5", STARTING_POSITION)
+                .parse::<ChessGame>()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_chess960_game() {
+        let variant = "chess960:0".parse::<ChessVariant>().unwrap();
+        assert_eq!(variant, ChessVariant::Chess960(0));
+        assert!("chess960:960".parse::<ChessVariant>().is_err());
+        assert!(matches!(
+            "chess960".parse::<ChessVariant>(),
+            Ok(ChessVariant::Chess960(index)) if index < CHESS960_POSITIONS
+        ));
+
+        let mut bytes = vec![];
+        ChessGame::encode_config(&variant, &mut bytes);
+        assert_eq!(ChessGame::decode_config(&mut &bytes[..]), Ok(variant));
+
+        // The variant goes first, and castling is king takes rook even from the usual start
+        let text = format!(
+            "chess960:518 {} moves e2e4 e7e5 g1f3 b8c6 f1c4 g8f6 e1h1",
+            STARTING_POSITION
+        );
+        let game = text.parse::<ChessGame>().unwrap();
+        assert_eq!(game.to_string(), text);
+        assert_eq!(game.variant(), ChessVariant::Chess960(STANDARD_CHESS960));
+        assert!(
+            ChessGame::new(ChessVariant::Standard)
+                .diff(&game, ChessPlayer::White)
+                .is_err()
+        );
+        assert!(text.replace("e1h1", "e1g1").parse::<ChessGame>().is_err());
+    }
+
+    #[test]
+    fn test_variant_games() {
+        for variant in [
+            ChessVariant::KingOfTheHill,
+            ChessVariant::ThreeCheck,
+            ChessVariant::Atomic,
+            ChessVariant::Crazyhouse,
+        ] {
+            assert_eq!(
+                variant.to_string().parse::<ChessVariant>().unwrap(),
+                variant
+            );
+            let mut bytes = vec![];
+            ChessGame::encode_config(&variant, &mut bytes);
+            assert_eq!(ChessGame::decode_config(&mut &bytes[..]), Ok(variant));
+        }
+        let variant = "koth".parse::<ChessVariant>().unwrap();
+        assert_eq!(variant, ChessVariant::KingOfTheHill);
+        let variant = "3check".parse::<ChessVariant>().unwrap();
+        assert_eq!(variant, ChessVariant::ThreeCheck);
+
+        let play = |variant: ChessVariant, moves: &str| {
+            let mut game = ChessGame::new(variant);
+            for uci in moves.split_whitespace() {
+                game.play(&uci.parse().unwrap()).unwrap();
+            }
+            // Sent as text, so it has to read back the same
+            assert_eq!(game.to_string().parse::<ChessGame>().unwrap(), game);
+            game
+        };
+        let game = play(
+            ChessVariant::KingOfTheHill,
+            "e2e4 a7a6 e1e2 a6a5 e2e3 a5a4 e3d4",
+        );
+        assert_eq!(
+            game.status(),
+            Some(ChessGameResult::KingOfTheHill(ChessPlayer::White))
+        );
+
+        let game = play(
+            ChessVariant::ThreeCheck,
+            "e2e4 e7e5 f1c4 a7a6 c4f7 e8f7 d1h5 g7g6 h5g6",
+        );
+        assert_eq!(game.position().remaining_checks(ChessPlayer::White), 0);
+        assert_eq!(
+            game.status(),
+            Some(ChessGameResult::ThreeChecks(ChessPlayer::White))
+        );
+
+        // Taking f7 blows up the king next to it
+        let game = play(ChessVariant::Atomic, "e2e4 e7e5 g1f3 a7a6 f3g5 a6a5 g5f7");
+        assert_eq!(
+            game.status(),
+            Some(ChessGameResult::KingExploded(ChessPlayer::White))
+        );
+
+        let game = play(
+            ChessVariant::Crazyhouse,
+            "e2e4 d7d5 e4d5 d8d5 b1c3 d5a5 P@d5",
+        );
+        assert_eq!(
+            game.position().pocket(ChessPlayer::White, PieceKind::Pawn),
+            0
+        );
+        assert_eq!(
+            game.position().pocket(ChessPlayer::Black, PieceKind::Pawn),
+            1
+        );
+        assert_eq!(game.status(), None);
+    }
+}
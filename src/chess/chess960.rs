@@ -0,0 +1,181 @@
+use super::{CastlingSide, ChessPlayer, ChessPosition, Piece, PieceKind, Square};
+
+pub const CHESS960_POSITIONS: u16 = 960;
+pub const STANDARD_CHESS960: u16 = 518; // Number of the standard start position
+
+// Where the two knights go among the five files left after the bishops and queen
+const KNIGHT_FILES: [(usize, usize); 10] = [
+    (0, 1),
+    (0, 2),
+    (0, 3),
+    (0, 4),
+    (1, 2),
+    (1, 3),
+    (1, 4),
+    (2, 3),
+    (2, 4),
+    (3, 4),
+];
+
+impl ChessPosition {
+    /// Chess960 start position number `index`, as Scharnagl numbers them
+    pub fn chess960(index: u16) -> ChessPosition {
+        let back_rank = chess960_back_rank(index % CHESS960_POSITIONS);
+        let mut position = ChessPosition::empty();
+        for player in [ChessPlayer::White, ChessPlayer::Black] {
+            let rank = player.back_rank();
+            let pawn_rank = (rank as i8 + (1 - 2 * player as i8)) as u8;
+            for (file, kind) in (0..8).zip(back_rank) {
+                position.set_piece(Square::new(file, rank), Some(Piece::new(player, kind)));
+                let pawn = Piece::new(player, PieceKind::Pawn);
+                position.set_piece(Square::new(file, pawn_rank), Some(pawn));
+            }
+            let rooks = (0..8).filter(|&file| back_rank[file as usize] == PieceKind::Rook);
+            let rooks = rooks.collect::<Vec<_>>();
+            let castling = &mut position.castling;
+            castling.set(player, CastlingSide::Queen, Some(rooks[0]));
+            castling.set(player, CastlingSide::King, Some(rooks[1]));
+        }
+        position.chess960 = true;
+        position
+    }
+
+    /// The Chess960 number of this position, if it is one of their starts
+    pub fn chess960_index(&self) -> Option<u16> {
+        let white = (0..8)
+            .map(|file| self.piece_at(Square::new(file, 0)).map(|piece| piece.kind))
+            .collect::<Option<Vec<_>>>()?;
+        let index =
+            (0..CHESS960_POSITIONS).find(|&index| chess960_back_rank(index) == white[..])?;
+        let mut start = ChessPosition::chess960(index);
+        start.chess960 = self.chess960;
+        (*self == start).then_some(index)
+    }
+}
+
+// Bishops on opposite colours, then the queen and knights, then rook, king and rook
+fn chess960_back_rank(index: u16) -> [PieceKind; 8] {
+    let mut files = [None; 8];
+    let index = index as usize;
+    files[(index % 4) * 2 + 1] = Some(PieceKind::Bishop);
+    files[(index / 4 % 4) * 2] = Some(PieceKind::Bishop);
+
+    let mut place = |kind: PieceKind, nth: usize| {
+        let file = (0..8)
+            .filter(|&file| files[file].is_none())
+            .nth(nth)
+            .expect("Enough empty files");
+        files[file] = Some(kind);
+    };
+    let index = index / 16;
+    place(PieceKind::Queen, index % 6);
+    let (first, second) = KNIGHT_FILES[index / 6];
+    // The second knight's file counts the first as still empty
+    place(PieceKind::Knight, second);
+    place(PieceKind::Knight, first);
+    for kind in [PieceKind::Rook, PieceKind::King, PieceKind::Rook] {
+        place(kind, 0);
+    }
+    files.map(|kind| kind.expect("Every file filled"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess::{ChessMove, STARTING_POSITION, perft};
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_start_positions() {
+        let mut standard = STARTING_POSITION.parse::<ChessPosition>().unwrap();
+        standard.set_chess960(true);
+        assert_eq!(ChessPosition::chess960(STANDARD_CHESS960), standard);
+        assert_eq!(
+            ChessPosition::chess960(0).to_string(),
+            "bbqnnrkr/pppppppp/8/8/8/8/PPPPPPPP/BBQNNRKR w KQkq - 0 1"
+        );
+
+        let positions = (0..CHESS960_POSITIONS)
+            .map(ChessPosition::chess960)
+            .collect::<Vec<_>>();
+        assert_eq!(positions.iter().collect::<HashSet<_>>().len(), 960);
+        for (index, position) in (0..).zip(&positions) {
+            assert_eq!(position.chess960_index(), Some(index));
+            // Valid, and the same again through X-FEN
+            let fen = position.to_string().parse::<ChessPosition>().unwrap();
+            assert_eq!(fen.castling(), position.castling(), "{}", index);
+        }
+        assert_eq!(
+            ChessPosition::new().chess960_index(),
+            Some(STANDARD_CHESS960)
+        );
+    }
+
+    #[test]
+    fn test_castling() {
+        // Shredder-FEN letters, written back as X-FEN where they are the outermost rook
+        let position = "1r2k1r1/8/8/8/8/8/8/R1R1K3 w Cgb - 0 1"
+            .parse::<ChessPosition>()
+            .unwrap();
+        assert!(position.is_chess960());
+        assert_eq!(
+            position.to_string(),
+            "1r2k1r1/8/8/8/8/8/8/R1R1K3 w Ckq - 0 1"
+        );
+
+        // King and rook swap places, and UCI writes it as king takes rook
+        let position = "4k3/8/8/8/8/8/8/6KR w K - 0 1"
+            .parse::<ChessPosition>()
+            .unwrap();
+        assert!(position.is_chess960());
+        let castle = position.find_move(&"g1h1".parse().unwrap()).unwrap();
+        assert!(matches!(castle, ChessMove::Castle { .. }));
+        let mut next = position.clone();
+        next.play(&castle).unwrap();
+        assert_eq!(next.to_string(), "4k3/8/8/8/8/8/8/5RK1 b - - 1 1");
+
+        // The rook in the way is the castling rook, the bishop is not
+        let position = "4k3/8/8/8/8/8/8/1RKB4 w B - 0 1"
+            .parse::<ChessPosition>()
+            .unwrap();
+        assert_eq!(position.to_string(), "4k3/8/8/8/8/8/8/1RKB4 w Q - 0 1");
+        let castle = "c1b1".parse().unwrap();
+        assert_eq!(position.find_move(&castle), None);
+        let position = "4k3/8/8/8/8/8/8/1RK5 w Q - 0 1"
+            .parse::<ChessPosition>()
+            .unwrap();
+        let castle = position.find_move(&"c1b1".parse().unwrap()).unwrap();
+        let mut next = position.clone();
+        next.play(&castle).unwrap();
+        assert_eq!(next.to_string(), "4k3/8/8/8/8/8/8/2KR4 b - - 1 1");
+    }
+
+    // Positions and counts from https://www.chessprogramming.org/Chess960_Perft_Results
+    #[test]
+    fn test_perft() {
+        let positions = [
+            (
+                "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9",
+                [21, 528, 12189],
+            ),
+            (
+                "2nnrbkr/p1qppppp/8/1ppb4/6PP/3PP3/PPP2P2/BQNNRBKR w HEhe - 1 9",
+                [21, 807, 18002],
+            ),
+            (
+                "b1q1rrkb/pppppppp/3nn3/8/P7/1PPP4/4PPPP/BQNNRKRB w GE - 1 9",
+                [20, 479, 10471],
+            ),
+            (
+                "qbbnnrkr/2pp2pp/p7/1p2pp2/8/P3PP2/1PPP1KPP/QBBNNR1R w hf - 0 9",
+                [22, 593, 13440],
+            ),
+        ];
+        for (fen, expected) in positions {
+            let position = fen.parse::<ChessPosition>().unwrap();
+            for (depth, nodes) in (1..).zip(expected) {
+                assert_eq!(perft(&position, depth), nodes, "{} at depth {}", fen, depth);
+            }
+        }
+    }
+}
@@ -0,0 +1,660 @@
+use anyhow::anyhow;
+use std::{fmt, str::FromStr};
+
+use super::{
+    CastlingSide, ChessMoveError, ChessPlayer, ChessPosition, Piece, PieceKind, Rules, Square,
+};
+
+const KNIGHT_STEPS: [(i8, i8); 8] = [
+    (1, 2),
+    (2, 1),
+    (2, -1),
+    (1, -2),
+    (-1, -2),
+    (-2, -1),
+    (-2, 1),
+    (-1, 2),
+];
+const KING_STEPS: [(i8, i8); 8] = [
+    (0, 1),
+    (1, 1),
+    (1, 0),
+    (1, -1),
+    (0, -1),
+    (-1, -1),
+    (-1, 0),
+    (-1, 1),
+];
+const BISHOP_RAYS: [(i8, i8); 4] = [(1, 1), (1, -1), (-1, -1), (-1, 1)];
+const ROOK_RAYS: [(i8, i8); 4] = [(0, 1), (1, 0), (0, -1), (-1, 0)];
+const PROMOTIONS: [PieceKind; 4] = [
+    PieceKind::Queen,
+    PieceKind::Rook,
+    PieceKind::Bishop,
+    PieceKind::Knight,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ChessMove {
+    Normal {
+        from: Square,
+        to: Square,
+        promotion: Option<PieceKind>,
+    },
+    EnPassant {
+        from: Square,
+        to: Square,
+    },
+    Castle {
+        king: Square,
+        rook: Square,
+    },
+    Drop {
+        kind: PieceKind,
+        to: Square,
+    }, // Crazyhouse, from the pocket
+}
+
+impl ChessMove {
+    /// Where the moving piece starts, the square it is put on for drops
+    pub fn from(&self) -> Square {
+        match *self {
+            ChessMove::Normal { from, .. } | ChessMove::EnPassant { from, .. } => from,
+            ChessMove::Castle { king, .. } => king,
+            ChessMove::Drop { to, .. } => to,
+        }
+    }
+
+    /// Where the moving piece ends up, the king's destination when castling
+    pub fn to(&self) -> Square {
+        match *self {
+            ChessMove::Normal { to, .. }
+            | ChessMove::EnPassant { to, .. }
+            | ChessMove::Drop { to, .. } => to,
+            ChessMove::Castle { king, rook } => {
+                let (king_to, _) = castling_files(castling_side(king, rook));
+                Square::new(king_to, king.rank())
+            }
+        }
+    }
+
+    pub fn promotion(&self) -> Option<PieceKind> {
+        match *self {
+            ChessMove::Normal { promotion, .. } => promotion,
+            _ => None,
+        }
+    }
+
+    pub fn to_uci(&self) -> UciMove {
+        UciMove {
+            from: self.from(),
+            to: self.to(),
+            promotion: self.promotion(),
+            drop: match *self {
+                ChessMove::Drop { kind, .. } => Some(kind),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl fmt::Display for ChessMove {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_uci())
+    }
+}
+
+/// A move as UCI writes it, which needs the position to tell castling and en passant apart
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct UciMove {
+    pub from: Square,
+    pub to: Square,
+    pub promotion: Option<PieceKind>,
+    pub drop: Option<PieceKind>, // Put on `to` from the pocket, `from` is then the same square
+}
+
+/// Long algebraic form, e.g. "e2e4", "e7e8q" or "e1g1", and "N@f3" for drops
+impl fmt::Display for UciMove {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(kind) = self.drop {
+            return write!(f, "{}@{}", kind.to_char().to_ascii_uppercase(), self.to);
+        }
+        write!(f, "{}{}", self.from, self.to)?;
+        match self.promotion {
+            Some(kind) => write!(f, "{}", kind.to_char()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl FromStr for UciMove {
+    type Err = anyhow::Error;
+    fn from_str(str: &str) -> Result<Self, Self::Err> {
+        if !str.is_ascii() || !(4..=5).contains(&str.len()) {
+            return Err(anyhow!("Invalid UCI move: '{}'", str));
+        }
+        if let Some(to) = str
+            .strip_prefix(|c: char| c.is_ascii_uppercase())
+            .and_then(|rest| rest.strip_prefix('@'))
+        {
+            let kind = PieceKind::from_char(str.chars().next().expect("Not empty"))
+                .filter(|&kind| kind != PieceKind::King)
+                .ok_or_else(|| anyhow!("Invalid drop in UCI move: '{}'", str))?;
+            let to = to.parse()?;
+            return Ok(UciMove {
+                from: to,
+                to,
+                promotion: None,
+                drop: Some(kind),
+            });
+        }
+        let promotion = match str[4..].chars().next() {
+            Some(c) => match PieceKind::from_char(c) {
+                Some(kind)
+                    if c.is_ascii_lowercase()
+                        && !matches!(kind, PieceKind::Pawn | PieceKind::King) =>
+                {
+                    Some(kind)
+                }
+                _ => return Err(anyhow!("Invalid promotion in UCI move: '{}'", str)),
+            },
+            None => None,
+        };
+        Ok(UciMove {
+            from: str[..2].parse()?,
+            to: str[2..4].parse()?,
+            promotion,
+            drop: None,
+        })
+    }
+}
+
+fn castling_side(king: Square, rook: Square) -> CastlingSide {
+    match rook.file() > king.file() {
+        true => CastlingSide::King,
+        false => CastlingSide::Queen,
+    }
+}
+
+// Files the king and rook land on, the same in every start position
+fn castling_files(side: CastlingSide) -> (u8, u8) {
+    match side {
+        CastlingSide::King => (6, 5),
+        CastlingSide::Queen => (2, 3),
+    }
+}
+
+fn pawn_direction(player: ChessPlayer) -> i8 {
+    match player {
+        ChessPlayer::White => 1,
+        ChessPlayer::Black => -1,
+    }
+}
+
+impl ChessPosition {
+    /// Whether any piece of `by` attacks `square`, whatever is standing on it
+    pub fn is_attacked(&self, square: Square, by: ChessPlayer) -> bool {
+        let is = |target: Option<Square>, kinds: &[PieceKind]| {
+            target
+                .and_then(|target| self.piece_at(target))
+                .is_some_and(|piece| piece.color == by && kinds.contains(&piece.kind))
+        };
+
+        let behind = -pawn_direction(by);
+        if is(square.offset(-1, behind), &[PieceKind::Pawn])
+            || is(square.offset(1, behind), &[PieceKind::Pawn])
+        {
+            return true;
+        }
+        if KNIGHT_STEPS
+            .iter()
+            .any(|&(df, dr)| is(square.offset(df, dr), &[PieceKind::Knight]))
+        {
+            return true;
+        }
+        if KING_STEPS
+            .iter()
+            .any(|&(df, dr)| is(square.offset(df, dr), &[PieceKind::King]))
+        {
+            return true;
+        }
+
+        let slides = |rays: &[(i8, i8)], kinds: &[PieceKind]| {
+            rays.iter().any(|&(df, dr)| {
+                let mut target = square.offset(df, dr);
+                while let Some(next) = target {
+                    if self.piece_at(next).is_some() {
+                        return is(target, kinds);
+                    }
+                    target = next.offset(df, dr);
+                }
+                false
+            })
+        };
+        slides(&BISHOP_RAYS, &[PieceKind::Bishop, PieceKind::Queen])
+            || slides(&ROOK_RAYS, &[PieceKind::Rook, PieceKind::Queen])
+    }
+
+    pub fn is_check(&self) -> bool {
+        self.in_check(self.side_to_move)
+    }
+
+    pub(crate) fn in_check(&self, player: ChessPlayer) -> bool {
+        self.king_square(player)
+            .is_some_and(|king| self.king_attacked_at(king, player.opponent()))
+    }
+
+    // Whether a king standing on `square` would be in check, where in atomic chess
+    // a king next to the other one is safe, as capturing it would blow up both
+    fn king_attacked_at(&self, square: Square, by: ChessPlayer) -> bool {
+        if self.rules == Rules::Atomic {
+            match self.king_square(by) {
+                Some(king) if king.file().abs_diff(square.file()) > 1 => {}
+                Some(king) if king.rank().abs_diff(square.rank()) > 1 => {}
+                _ => return false,
+            }
+        }
+        self.is_attacked(square, by)
+    }
+
+    pub fn legal_moves(&self) -> Vec<ChessMove> {
+        // Variants which end on the board leave nothing to play
+        if self.variant_outcome().is_some() {
+            return vec![];
+        }
+        let us = self.side_to_move;
+        let mut moves = vec![];
+        self.pseudo_legal_moves(&mut moves);
+        moves.retain(|game_move| {
+            let mut next = self.clone();
+            next.play_unchecked(game_move);
+            // The king can only go missing by blowing up in atomic chess
+            next.king_square(us).is_some() && !next.in_check(us)
+        });
+        self.castling_moves(&mut moves);
+        moves
+    }
+
+    pub fn is_legal(&self, game_move: &ChessMove) -> bool {
+        self.legal_moves().contains(game_move)
+    }
+
+    /// The legal move written as `uci`, if there is one
+    pub fn find_move(&self, uci: &UciMove) -> Option<ChessMove> {
+        self.legal_moves()
+            .into_iter()
+            .find(|game_move| self.uci(game_move) == *uci)
+    }
+
+    /// A move as UCI writes it here, castling as king takes rook in Chess960
+    pub fn uci(&self, game_move: &ChessMove) -> UciMove {
+        match *game_move {
+            ChessMove::Castle { king, rook } if self.chess960 => UciMove {
+                from: king,
+                to: rook,
+                promotion: None,
+                drop: None,
+            },
+            _ => game_move.to_uci(),
+        }
+    }
+
+    /// Plays a move for the side to move, failing without changing anything if it is illegal
+    pub fn play(&mut self, game_move: &ChessMove) -> Result<(), ChessMoveError> {
+        if !self.is_legal(game_move) {
+            return Err(ChessMoveError::IllegalMove(self.uci(game_move)));
+        }
+        self.play_unchecked(game_move);
+        Ok(())
+    }
+
+    /// Plays a move which must have come from `legal_moves`
+    pub(crate) fn play_unchecked(&mut self, game_move: &ChessMove) {
+        let us = self.side_to_move;
+        let moved = match *game_move {
+            ChessMove::Drop { kind, .. } => Piece::new(us, kind),
+            _ => self.piece_at(game_move.from()).expect("No piece to move"),
+        };
+        let captured = match *game_move {
+            ChessMove::Normal { to, .. } => self.piece_at(to).map(|piece| (to, piece.kind)),
+            ChessMove::EnPassant { from, to } => {
+                Some((Square::new(to.file(), from.rank()), PieceKind::Pawn))
+            }
+            ChessMove::Castle { .. } | ChessMove::Drop { .. } => None,
+        };
+        let reset_clock = moved.kind == PieceKind::Pawn || captured.is_some();
+        self.en_passant = None;
+
+        if let Some((square, kind)) = captured
+            && self.rules == Rules::Crazyhouse
+        {
+            // Promoted pieces go back to being pawns
+            let kind = match self.is_promoted(square) {
+                true => PieceKind::Pawn,
+                false => kind,
+            };
+            self.pockets[us as usize][kind as usize] += 1;
+        }
+
+        match *game_move {
+            ChessMove::Normal {
+                from,
+                to,
+                promotion,
+            } => {
+                self.set_piece(from, None);
+                let kind = promotion.unwrap_or(moved.kind);
+                self.set_piece(to, Some(Piece::new(us, kind)));
+                if self.rules == Rules::Crazyhouse {
+                    let promoted = self.is_promoted(from) || promotion.is_some();
+                    self.set_promoted(from, false);
+                    self.set_promoted(to, promoted);
+                }
+
+                if moved.kind == PieceKind::King {
+                    self.castling.clear(us);
+                }
+                self.castling.remove_rook(from);
+                self.castling.remove_rook(to);
+
+                if moved.kind == PieceKind::Pawn && from.rank().abs_diff(to.rank()) == 2 {
+                    // Only worth recording if a pawn could take it
+                    let behind = Square::new(from.file(), (from.rank() + to.rank()) / 2);
+                    let enemy_pawn = Some(Piece::new(us.opponent(), PieceKind::Pawn));
+                    if [to.offset(-1, 0), to.offset(1, 0)]
+                        .into_iter()
+                        .flatten()
+                        .any(|square| self.piece_at(square) == enemy_pawn)
+                    {
+                        self.en_passant = Some(behind);
+                    }
+                }
+            }
+            ChessMove::EnPassant { from, to } => {
+                self.set_piece(from, None);
+                self.set_piece(Square::new(to.file(), from.rank()), None);
+                self.set_piece(to, Some(moved));
+            }
+            ChessMove::Castle { king, rook } => {
+                let (king_to, rook_to) = castling_files(castling_side(king, rook));
+                let rank = king.rank();
+                self.set_piece(king, None);
+                self.set_piece(rook, None);
+                self.set_piece(Square::new(king_to, rank), Some(moved));
+                self.set_piece(
+                    Square::new(rook_to, rank),
+                    Some(Piece::new(us, PieceKind::Rook)),
+                );
+                self.castling.clear(us);
+            }
+            ChessMove::Drop { kind, to } => {
+                self.pockets[us as usize][kind as usize] -= 1;
+                self.set_piece(to, Some(moved));
+            }
+        }
+        if captured.is_some() && self.rules == Rules::Atomic {
+            self.explode(game_move.to());
+        }
+
+        self.halfmove_clock = match reset_clock {
+            true => 0,
+            false => self.halfmove_clock + 1,
+        };
+        if us == ChessPlayer::Black {
+            self.fullmove_number += 1;
+        }
+        self.side_to_move = us.opponent();
+
+        if self.rules == Rules::ThreeCheck && self.is_check() {
+            let checks = &mut self.remaining_checks[us as usize];
+            *checks = checks.saturating_sub(1);
+        }
+    }
+
+    // Every move ignoring checks on our own king, except castling
+    fn pseudo_legal_moves(&self, moves: &mut Vec<ChessMove>) {
+        let us = self.side_to_move;
+        let is_free = |square: Square| self.piece_at(square).is_none();
+        let is_enemy =
+            |square: Square| self.piece_at(square).is_some_and(|piece| piece.color != us);
+
+        for (from, piece) in self.pieces().filter(|(_, piece)| piece.color == us) {
+            match piece.kind {
+                PieceKind::Pawn => {
+                    let forward = pawn_direction(us);
+                    let promotes = |to: Square| to.rank() == us.opponent().back_rank();
+                    let push = |moves: &mut Vec<ChessMove>, to: Square| match promotes(to) {
+                        true => moves.extend(PROMOTIONS.map(|kind| ChessMove::Normal {
+                            from,
+                            to,
+                            promotion: Some(kind),
+                        })),
+                        false => moves.push(ChessMove::Normal {
+                            from,
+                            to,
+                            promotion: None,
+                        }),
+                    };
+
+                    if let Some(to) = from.offset(0, forward).filter(|&to| is_free(to)) {
+                        push(moves, to);
+                        let start_rank = (us.back_rank() as i8 + forward) as u8;
+                        if let Some(to) = to
+                            .offset(0, forward)
+                            .filter(|&to| from.rank() == start_rank && is_free(to))
+                        {
+                            push(moves, to);
+                        }
+                    }
+                    for to in [from.offset(-1, forward), from.offset(1, forward)]
+                        .into_iter()
+                        .flatten()
+                    {
+                        if is_enemy(to) {
+                            push(moves, to);
+                        } else if self.en_passant == Some(to) {
+                            moves.push(ChessMove::EnPassant { from, to });
+                        }
+                    }
+                }
+                PieceKind::Knight | PieceKind::King => {
+                    let steps = match piece.kind {
+                        PieceKind::Knight => &KNIGHT_STEPS,
+                        _ => &KING_STEPS,
+                    };
+                    // Atomic kings can't capture, as they would blow themselves up
+                    let captures = piece.kind == PieceKind::Knight || self.rules != Rules::Atomic;
+                    for to in steps.iter().filter_map(|&(df, dr)| from.offset(df, dr)) {
+                        if is_free(to) || (captures && is_enemy(to)) {
+                            moves.push(ChessMove::Normal {
+                                from,
+                                to,
+                                promotion: None,
+                            });
+                        }
+                    }
+                }
+                PieceKind::Bishop | PieceKind::Rook | PieceKind::Queen => {
+                    let rays = match piece.kind {
+                        PieceKind::Bishop => &BISHOP_RAYS[..],
+                        PieceKind::Rook => &ROOK_RAYS[..],
+                        _ => &[BISHOP_RAYS, ROOK_RAYS].concat(),
+                    };
+                    for &(df, dr) in rays {
+                        let mut target = from.offset(df, dr);
+                        while let Some(to) = target {
+                            if is_free(to) || is_enemy(to) {
+                                moves.push(ChessMove::Normal {
+                                    from,
+                                    to,
+                                    promotion: None,
+                                });
+                            }
+                            if !is_free(to) {
+                                break;
+                            }
+                            target = to.offset(df, dr);
+                        }
+                    }
+                }
+            }
+        }
+        self.drop_moves(moves);
+    }
+
+    // Legal castling moves, with the king and rook anywhere on the back rank
+    fn castling_moves(&self, moves: &mut Vec<ChessMove>) {
+        let us = self.side_to_move;
+        let Some(king) = self.king_square(us) else {
+            return;
+        };
+        let rank = us.back_rank();
+
+        for side in [CastlingSide::King, CastlingSide::Queen] {
+            let Some(rook_file) = self.castling.get(us, side) else {
+                continue;
+            };
+            let rook = Square::new(rook_file, rank);
+            let (king_to, rook_to) = castling_files(side);
+
+            // Every square either piece crosses must be empty apart from the two of them
+            let files = [king.file(), rook_file, king_to, rook_to];
+            let (min, max) = (*files.iter().min().unwrap(), *files.iter().max().unwrap());
+            let blocked = (min..=max)
+                .map(|file| Square::new(file, rank))
+                .any(|square| square != king && square != rook && self.piece_at(square).is_some());
+            if blocked {
+                continue;
+            }
+
+            // The king may not castle out of or through check, seeing past where it stood,
+            // which only matters when an atomic king is safe next to the other one
+            let (from, to) = (king.file().min(king_to), king.file().max(king_to));
+            let mut kingless = self.clone();
+            kingless.set_piece(king, None);
+            if (from..=to)
+                .filter(|&file| file != king_to)
+                .any(|file| kingless.king_attacked_at(Square::new(file, rank), us.opponent()))
+            {
+                continue;
+            }
+
+            // Nor into it, once the rook is out of the way
+            let game_move = ChessMove::Castle { king, rook };
+            let mut next = self.clone();
+            next.play_unchecked(&game_move);
+            if !next.in_check(us) {
+                moves.push(game_move);
+            }
+        }
+    }
+}
+
+/// Counts the leaf nodes of the legal move tree, to compare against published results
+pub fn perft(position: &ChessPosition, depth: u32) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+    let moves = position.legal_moves();
+    if depth == 1 {
+        return moves.len() as u64;
+    }
+    moves
+        .iter()
+        .map(|game_move| {
+            let mut next = position.clone();
+            next.play_unchecked(game_move);
+            perft(&next, depth - 1)
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess::STARTING_POSITION;
+
+    fn check_perft(fen: &str, expected: &[u64]) {
+        let position = fen.parse::<ChessPosition>().unwrap();
+        for (depth, &nodes) in expected.iter().enumerate() {
+            assert_eq!(
+                perft(&position, depth as u32 + 1),
+                nodes,
+                "{} at depth {}",
+                fen,
+                depth + 1
+            );
+        }
+    }
+
+    // Positions and counts from https://www.chessprogramming.org/Perft_Results
+    #[test]
+    fn test_perft() {
+        check_perft(STARTING_POSITION, &[20, 400, 8902, 197281]);
+        // Kiwipete
+        check_perft(
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            &[48, 2039, 97862],
+        );
+        check_perft(
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            &[14, 191, 2812, 43238],
+        );
+        check_perft(
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            &[6, 264, 9467],
+        );
+        check_perft(
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            &[44, 1486, 62379],
+        );
+        check_perft(
+            "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+            &[46, 2079, 89890],
+        );
+    }
+
+    #[test]
+    fn test_special_moves() {
+        // Castling is blocked by the attacked f1, but not by the attacked b1
+        let position = "1r2kr2/8/8/8/8/8/8/R3K2R w KQ - 0 1".parse::<ChessPosition>();
+        let position = position.unwrap();
+        let castles = position
+            .legal_moves()
+            .into_iter()
+            .filter(|game_move| matches!(game_move, ChessMove::Castle { .. }))
+            .map(|game_move| game_move.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(castles, ["e1c1"]);
+
+        // En passant is illegal when it exposes the king along the rank
+        let mut position = "8/8/8/KPp4r/8/8/8/7k w - c6 0 1"
+            .parse::<ChessPosition>()
+            .unwrap();
+        let en_passant = ChessMove::EnPassant {
+            from: "b5".parse().unwrap(),
+            to: "c6".parse().unwrap(),
+        };
+        assert!(!position.is_legal(&en_passant));
+        assert_eq!(
+            position.play(&en_passant),
+            Err(ChessMoveError::IllegalMove(en_passant.to_uci()))
+        );
+
+        // Promotion, and the clocks
+        let mut position = "8/P6k/8/8/8/8/8/K7 w - - 5 30"
+            .parse::<ChessPosition>()
+            .unwrap();
+        let promotion = ChessMove::Normal {
+            from: "a7".parse().unwrap(),
+            to: "a8".parse().unwrap(),
+            promotion: Some(PieceKind::Knight),
+        };
+        position.play(&promotion).unwrap();
+        assert_eq!(promotion.to_string(), "a7a8n");
+        assert_eq!("a7a8n".parse::<UciMove>().unwrap(), promotion.to_uci());
+        assert!("a7a8k".parse::<UciMove>().is_err());
+        assert!("a7a9".parse::<UciMove>().is_err());
+        assert_eq!(position.to_string(), "N7/7k/8/8/8/8/8/K7 b - - 0 30");
+    }
+}
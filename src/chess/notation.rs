@@ -0,0 +1,326 @@
+use super::{ChessMove, ChessPosition, PieceKind, Square, UciMove};
+
+impl ChessPosition {
+    /// Standard Algebraic Notation for a legal move, e.g. `Nbd7`, `exd6`, `O-O`, `e8=Q#` or `N@f3`
+    pub fn to_san(&self, game_move: &ChessMove) -> String {
+        let san = match *game_move {
+            ChessMove::Castle { king, rook } if rook.file() > king.file() => "O-O".to_string(),
+            ChessMove::Castle { .. } => "O-O-O".to_string(),
+            ChessMove::Drop { kind, to } => drop_text(kind, to),
+            _ => self.to_san_without_check(game_move),
+        };
+        san + self.check_suffix(game_move)
+    }
+
+    /// Long algebraic notation for a legal move, with both squares, e.g. `Ng1-f3` or `e7xd8=Q+`
+    pub fn to_lan(&self, game_move: &ChessMove) -> String {
+        let (from, to) = (game_move.from(), game_move.to());
+        let mut lan = match *game_move {
+            ChessMove::Castle { king, rook } if rook.file() > king.file() => "O-O".to_string(),
+            ChessMove::Castle { .. } => "O-O-O".to_string(),
+            ChessMove::Drop { kind, to } => drop_text(kind, to),
+            _ => {
+                let kind = self.piece_at(from).expect("No piece to move").kind;
+                let capture =
+                    matches!(game_move, ChessMove::EnPassant { .. }) || self.piece_at(to).is_some();
+                let letter = match kind {
+                    PieceKind::Pawn => String::new(),
+                    kind => kind.to_char().to_ascii_uppercase().to_string(),
+                };
+                let separator = if capture { 'x' } else { '-' };
+                format!("{}{}{}{}", letter, from, separator, to)
+            }
+        };
+        if let Some(promotion) = game_move.promotion() {
+            lan.push('=');
+            lan.push(promotion.to_char().to_ascii_uppercase());
+        }
+        lan + self.check_suffix(game_move)
+    }
+
+    /// A legal move typed in UCI, SAN or long algebraic notation, e.g. `e7e8q`, `Nbd7` or `Ng1-f3`
+    pub fn parse_move_text(&self, text: &str) -> Option<ChessMove> {
+        let text = text.trim();
+        // UCI first, as it is the only way to write some moves, e.g. castling as `e1g1`
+        text.parse::<UciMove>()
+            .ok()
+            .and_then(|uci| self.find_move(&uci))
+            .or_else(|| self.parse_san(text))
+    }
+
+    fn check_suffix(&self, game_move: &ChessMove) -> &'static str {
+        let mut next = self.clone();
+        next.play_unchecked(game_move);
+        match (next.is_check(), next.legal_moves().is_empty()) {
+            (true, true) => "#",
+            (true, false) => "+",
+            (false, _) => "",
+        }
+    }
+
+    fn to_san_without_check(&self, game_move: &ChessMove) -> String {
+        let (from, to) = (game_move.from(), game_move.to());
+        let kind = self.piece_at(from).expect("No piece to move").kind;
+        let capture =
+            matches!(game_move, ChessMove::EnPassant { .. }) || self.piece_at(to).is_some();
+
+        let mut san = String::new();
+        if kind == PieceKind::Pawn {
+            if capture {
+                san.push(file_char(from));
+            }
+        } else {
+            san.push(kind.to_char().to_ascii_uppercase());
+            // Other pieces of the same kind which could also go there
+            let rivals = self
+                .legal_moves()
+                .into_iter()
+                .filter(|other| {
+                    !matches!(other, ChessMove::Castle { .. })
+                        && other.to() == to
+                        && other.from() != from
+                        && self.piece_at(other.from()).map(|piece| piece.kind) == Some(kind)
+                })
+                .collect::<Vec<_>>();
+            if !rivals.is_empty() {
+                if rivals
+                    .iter()
+                    .all(|other| other.from().file() != from.file())
+                {
+                    san.push(file_char(from));
+                } else if rivals
+                    .iter()
+                    .all(|other| other.from().rank() != from.rank())
+                {
+                    san.push(rank_char(from));
+                } else {
+                    san.push(file_char(from));
+                    san.push(rank_char(from));
+                }
+            }
+        }
+        if capture {
+            san.push('x');
+        }
+        san.push_str(&to.to_string());
+        if let Some(promotion) = game_move.promotion() {
+            san.push('=');
+            san.push(promotion.to_char().to_ascii_uppercase());
+        }
+        san
+    }
+
+    /// The legal move a SAN string stands for
+    ///
+    /// Lenient about what doesn't change the meaning: check and annotation suffixes, a
+    /// missing `x` or `=`, zeros for castling and more disambiguation than needed, so long
+    /// algebraic moves like `Ng1-f3` are read too. Pawn drops can be `@e4` or `P@e4`.
+    pub fn parse_san(&self, san: &str) -> Option<ChessMove> {
+        let san = san.trim_end_matches(['+', '#', '!', '?']);
+        if let Some((letter, to)) = san.split_once('@') {
+            let kind = match letter {
+                "" => PieceKind::Pawn,
+                letter if letter.len() == 1 && letter.chars().all(|c| c.is_ascii_uppercase()) => {
+                    PieceKind::from_char(letter.to_ascii_lowercase().chars().next()?)?
+                }
+                _ => return None,
+            };
+            let drop = ChessMove::Drop {
+                kind,
+                to: to.parse().ok()?,
+            };
+            return self.is_legal(&drop).then_some(drop);
+        }
+        let castle_kingside = match san {
+            "O-O" | "0-0" => Some(true),
+            "O-O-O" | "0-0-0" => Some(false),
+            _ => None,
+        };
+        if let Some(kingside) = castle_kingside {
+            return self.legal_moves().into_iter().find(|game_move| {
+                matches!(*game_move, ChessMove::Castle { king, rook } if (rook.file() > king.file()) == kingside)
+            });
+        }
+
+        let mut chars = san
+            .chars()
+            .filter(|&c| !matches!(c, 'x' | '=' | '-'))
+            .collect::<Vec<_>>();
+        let kind = match chars.first() {
+            Some(&c) if c.is_ascii_uppercase() => {
+                chars.remove(0);
+                PieceKind::from_char(c.to_ascii_lowercase())
+                    .filter(|&kind| kind != PieceKind::Pawn)?
+            }
+            _ => PieceKind::Pawn,
+        };
+        let promotion = match chars.last() {
+            Some(&c) if kind == PieceKind::Pawn && c.is_ascii_alphabetic() => {
+                chars.pop();
+                Some(PieceKind::from_char(c.to_ascii_lowercase())?)
+            }
+            _ => None,
+        };
+        if chars.len() < 2 {
+            return None;
+        }
+        let to = chars[chars.len() - 2..]
+            .iter()
+            .collect::<String>()
+            .parse::<Square>()
+            .ok()?;
+        let (mut file, mut rank) = (None, None);
+        for &c in &chars[..chars.len() - 2] {
+            match c {
+                'a'..='h' if file.is_none() => file = Some(c as u8 - b'a'),
+                '1'..='8' if rank.is_none() => rank = Some(c as u8 - b'1'),
+                _ => return None,
+            }
+        }
+
+        let mut candidates = self.legal_moves().into_iter().filter(|game_move| {
+            let from = game_move.from();
+            !matches!(game_move, ChessMove::Castle { .. })
+                && game_move.to() == to
+                && game_move.promotion() == promotion
+                && self.piece_at(from).map(|piece| piece.kind) == Some(kind)
+                && file.is_none_or(|file| from.file() == file)
+                && rank.is_none_or(|rank| from.rank() == rank)
+        });
+        let game_move = candidates.next()?;
+        match candidates.next() {
+            Some(_) => None, // Ambiguous
+            None => Some(game_move),
+        }
+    }
+}
+
+// A crazyhouse drop, leaving out the letter for pawns as SAN does for moves
+fn drop_text(kind: PieceKind, to: Square) -> String {
+    match kind {
+        PieceKind::Pawn => format!("@{}", to),
+        kind => format!("{}@{}", kind.to_char().to_ascii_uppercase(), to),
+    }
+}
+
+fn file_char(square: Square) -> char {
+    (b'a' + square.file()) as char
+}
+
+fn rank_char(square: Square) -> char {
+    (b'1' + square.rank()) as char
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess::Rules;
+
+    #[test]
+    fn test_san() {
+        let san = |fen: &str, uci: &str| {
+            let position = fen.parse::<ChessPosition>().unwrap();
+            let game_move = position.find_move(&uci.parse().unwrap()).unwrap();
+            let san = position.to_san(&game_move);
+            assert_eq!(position.parse_san(&san), Some(game_move), "{}", san);
+            san
+        };
+
+        let start = ChessPosition::new().to_string();
+        assert_eq!(san(&start, "e2e4"), "e4");
+        assert_eq!(san(&start, "g1f3"), "Nf3");
+
+        // Rooks on a1 and a5 can both reach a3
+        let fen = "r3k2r/pppq1ppp/2n2n2/R3p3/3P4/8/1PP2PPP/R3K2R w KQkq - 0 1";
+        assert_eq!(san(fen, "d4e5"), "dxe5");
+        assert_eq!(san(fen, "a1a3"), "R1a3");
+        assert_eq!(san(fen, "e1g1"), "O-O");
+        assert_eq!(san(fen, "e1c1"), "O-O-O");
+        let fen = "r1bqkb1r/pppp1ppp/2n2n2/4p2Q/2B1P3/8/PPPP1PPP/RNB1K1NR w KQkq - 0 1";
+        assert_eq!(san(fen, "h5f7"), "Qxf7#");
+        assert_eq!(san("3k4/1P6/8/8/8/8/8/4K3 w - - 0 1", "b7b8q"), "b8=Q+");
+        assert_eq!(san("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "e5d6"), "exd6");
+        // Three queens, so only the full square tells them apart
+        let fen = "7k/8/8/8/Q1Q5/8/Q7/7K w - - 0 1";
+        assert_eq!(san(fen, "a4b3"), "Qa4b3");
+
+        let position = "r3k2r/pppq1ppp/2n2n2/R3p3/3P4/8/1PP2PPP/R3K2R w KQkq - 0 1"
+            .parse::<ChessPosition>()
+            .unwrap();
+        assert_eq!(position.parse_san("Ra3"), None, "Ambiguous");
+        assert_eq!(position.parse_san("Ra1a3"), position.parse_san("R1a3"));
+        assert_eq!(position.parse_san("de5"), position.parse_san("dxe5"));
+        assert_eq!(position.parse_san("0-0"), position.parse_san("O-O+"));
+        assert_eq!(position.parse_san("Ke3"), None);
+        assert_eq!(position.parse_san("Ra1-a3"), position.parse_san("R1a3"));
+
+        // Every move reads back as itself
+        let kiwipete = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1"
+            .parse::<ChessPosition>()
+            .unwrap();
+        for game_move in kiwipete.legal_moves() {
+            let san = kiwipete.to_san(&game_move);
+            assert_eq!(kiwipete.parse_san(&san), Some(game_move), "{}", san);
+            let lan = kiwipete.to_lan(&game_move);
+            assert_eq!(kiwipete.parse_move_text(&lan), Some(game_move), "{}", lan);
+            let uci = game_move.to_string();
+            assert_eq!(kiwipete.parse_move_text(&uci), Some(game_move), "{}", uci);
+        }
+    }
+
+    #[test]
+    fn test_move_text() {
+        let position = "r3k2r/pppq1ppp/2n2n2/R3p3/3P4/8/1PP2PPP/R3K2R w KQkq - 0 1"
+            .parse::<ChessPosition>()
+            .unwrap();
+        let lan = |uci: &str| position.to_lan(&position.find_move(&uci.parse().unwrap()).unwrap());
+        assert_eq!(lan("d4e5"), "d4xe5");
+        assert_eq!(lan("a1a3"), "Ra1-a3");
+        assert_eq!(lan("e1g1"), "O-O");
+        assert_eq!(lan("a5a7"), "Ra5xa7");
+
+        let text = |text: &str| {
+            position
+                .parse_move_text(text)
+                .map(|game_move| game_move.to_string())
+        };
+        assert_eq!(text("e1g1").as_deref(), Some("e1g1"));
+        assert_eq!(text("O-O").as_deref(), Some("e1g1"));
+        assert_eq!(text(" Nbd7 "), None, "Black's move");
+        assert_eq!(text("dxe5").as_deref(), Some("d4e5"));
+        // A capture, but close enough
+        assert_eq!(text("d4-e5").as_deref(), Some("d4e5"));
+        assert_eq!(text("e4e5"), None);
+
+        let position = "3k4/1P6/8/8/8/8/8/4K3 w - - 0 1"
+            .parse::<ChessPosition>()
+            .unwrap();
+        let text = |text: &str| {
+            position
+                .parse_move_text(text)
+                .map(|game_move| game_move.to_string())
+        };
+        assert_eq!(text("b7b8q").as_deref(), Some("b7b8q"));
+        assert_eq!(text("b8=N").as_deref(), Some("b7b8n"));
+        assert_eq!(text("b7-b8=R+").as_deref(), Some("b7b8r"));
+        assert_eq!(text("b8"), None, "Must say what it promotes to");
+
+        // Crazyhouse drops, which UCI writes the same way
+        let position =
+            ChessPosition::from_fen("4k3/8/8/8/8/8/8/4K3[NPp] w - - 0 1", Rules::Crazyhouse)
+                .unwrap();
+        let drop = position.parse_move_text("N@f3").unwrap();
+        assert_eq!(position.to_san(&drop), "N@f3");
+        assert_eq!(drop.to_string(), "N@f3");
+        let drop = position.parse_move_text("P@d7").unwrap();
+        assert_eq!(position.to_san(&drop), "@d7+");
+        assert_eq!(position.to_lan(&drop), "@d7+");
+        assert_eq!(position.parse_san("@d7+"), Some(drop));
+        assert_eq!(
+            position.parse_move_text("@e8"),
+            None,
+            "Pawns can't go on the last rank"
+        );
+        assert_eq!(position.parse_move_text("B@f3"), None, "Not in the pocket");
+    }
+}
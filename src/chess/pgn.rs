@@ -0,0 +1,512 @@
+use anyhow::{anyhow, bail};
+use std::{
+    fmt,
+    iter::Peekable,
+    str::{Chars, FromStr},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::{ChessGame, ChessPlayer, ChessPosition, ChessVariant, STANDARD_CHESS960};
+use crate::game::{Ending, Game, GameRecord};
+
+// Always written, in this order, before any other tags
+const SEVEN_TAG_ROSTER: [&str; 7] = ["Event", "Site", "Date", "Round", "White", "Black", "Result"];
+const LINE_WIDTH: usize = 80;
+
+/// A chess game in Portable Game Notation, its tags together with the game they describe
+#[derive(Clone, Debug, PartialEq)]
+pub struct PgnGame {
+    tags: Vec<(String, String)>, // In the order they are written
+    pub game: ChessGame,
+}
+
+impl PgnGame {
+    /// The game with the seven tag roster, unknown values as `?`, and its result if it is over
+    pub fn new(game: ChessGame) -> PgnGame {
+        let tags = SEVEN_TAG_ROSTER.map(|name| (name.to_string(), "?".to_string()));
+        let mut pgn = PgnGame {
+            tags: tags.to_vec(),
+            game,
+        };
+        let result = pgn.game.status().map_or("*", |result| result.score());
+        pgn.set_tag("Result", result);
+        let variant = pgn.game.variant();
+        if let Some(tag) = variant_tag(variant) {
+            pgn.set_tag("Variant", tag);
+        }
+        // Chess960 is numbered by its start position, so always gives it
+        if matches!(variant, ChessVariant::Chess960(_))
+            || *pgn.game.start() != variant.start_position()
+        {
+            pgn.set_tag("SetUp", "1");
+            pgn.set_tag("FEN", &pgn.game.start().to_string());
+        }
+        pgn
+    }
+
+    /// A game played through the server, with how it ended
+    pub fn from_record(record: &GameRecord<ChessGame>) -> PgnGame {
+        let mut pgn = PgnGame::new(record.game.clone());
+        pgn.set_tag("Event", "Rusty Moves game");
+        pgn.set_tag("Date", &pgn_date(record.started));
+        pgn.set_tag("Round", "-");
+        for (side, name) in &record.players {
+            let tag = match side {
+                ChessPlayer::White => "White",
+                ChessPlayer::Black => "Black",
+            };
+            pgn.set_tag(tag, name);
+        }
+
+        let game = &record.game;
+        let lost_by = |side: ChessPlayer| match side {
+            ChessPlayer::White => "0-1",
+            ChessPlayer::Black => "1-0",
+        };
+        let (result, termination) = match record.ending {
+            Ending::Finished => match game.status() {
+                Some(result) => (result.score(), result.reason()),
+                None => ("*", "unterminated"),
+            },
+            Ending::Claimed => match game.claim_draw() {
+                Some(result) => (result.score(), result.reason()),
+                None => ("*", "unterminated"),
+            },
+            Ending::Adjudicated(Some(winner)) => (lost_by(winner.opponent()), "adjudication"),
+            Ending::Adjudicated(None) => ("1/2-1/2", "adjudication"),
+            Ending::Agreed => ("1/2-1/2", "agreement"),
+            Ending::Resigned(side) => (lost_by(side), "resignation"),
+            Ending::Forfeited(side) => (lost_by(side), "forfeit"),
+        };
+        pgn.set_tag("Result", result);
+        pgn.set_tag("Termination", termination);
+        let time_control = match record.move_time {
+            // One move per that many seconds, which has to be a whole number
+            Some(move_time) if move_time.as_secs() > 0 => format!("1/{}", move_time.as_secs()),
+            _ => "-".to_string(),
+        };
+        pgn.set_tag("TimeControl", &time_control);
+        pgn
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(tag, _)| tag == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Replaces the tag's value, adding it after the others if it is new
+    pub fn set_tag(&mut self, name: &str, value: &str) {
+        match self.tags.iter_mut().find(|(tag, _)| tag == name) {
+            Some((_, old)) => *old = value.to_string(),
+            None => self.tags.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    /// `1-0`, `0-1`, `1/2-1/2`, or `*` while the game is still going
+    pub fn result(&self) -> &str {
+        self.tag("Result").unwrap_or("*")
+    }
+
+    /// Every game in a PGN file, replaying each one to check its moves are legal
+    pub fn parse_all(str: &str) -> anyhow::Result<Vec<PgnGame>> {
+        let mut tokens = Tokens {
+            chars: str.chars().peekable(),
+            pending: None,
+        };
+        let mut games = vec![];
+        loop {
+            let (mut tags, mut moves, mut result) = (vec![], vec![], None);
+            while let Some(token) = tokens.next()? {
+                match token {
+                    // Tags after moves without a result start the next game
+                    Token::Tag(..) if !moves.is_empty() => {
+                        tokens.pending = Some(token);
+                        break;
+                    }
+                    Token::Tag(name, value) => tags.push((name, value)),
+                    Token::Move(san) => moves.push(san),
+                    Token::Result(token) => {
+                        result = Some(token);
+                        break;
+                    }
+                }
+            }
+            if tags.is_empty() && moves.is_empty() && result.is_none() {
+                return Ok(games);
+            }
+            games.push(
+                replay(tags, &moves, result)
+                    .map_err(|e| anyhow!("Invalid PGN game {}: {}", games.len() + 1, e))?,
+            );
+        }
+    }
+}
+
+/// Reads the one game in `str`
+impl FromStr for PgnGame {
+    type Err = anyhow::Error;
+    fn from_str(str: &str) -> Result<Self, Self::Err> {
+        let mut games = PgnGame::parse_all(str)?;
+        match games.len() {
+            1 => Ok(games.remove(0)),
+            count => bail!("Expected one PGN game, found {}", count),
+        }
+    }
+}
+
+impl fmt::Display for PgnGame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, value) in &self.tags {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+            writeln!(f, "[{} \"{}\"]", name, value)?;
+        }
+        writeln!(f)?;
+
+        let mut position = self.game.start().clone();
+        let mut tokens = vec![];
+        for (i, game_move) in self.game.moves().iter().enumerate() {
+            match position.side_to_move() {
+                ChessPlayer::White => tokens.push(format!("{}.", position.fullmove_number())),
+                ChessPlayer::Black if i == 0 => {
+                    tokens.push(format!("{}...", position.fullmove_number()))
+                }
+                ChessPlayer::Black => {}
+            }
+            tokens.push(position.to_san(game_move));
+            position.play_unchecked(game_move);
+        }
+        tokens.push(self.result().to_string());
+
+        let mut line = String::new();
+        for token in tokens {
+            if !line.is_empty() && line.len() + 1 + token.len() > LINE_WIDTH {
+                writeln!(f, "{}", line)?;
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&token);
+        }
+        writeln!(f, "{}", line)
+    }
+}
+
+// The Variant tag, as other PGN tools write it
+fn variant_tag(variant: ChessVariant) -> Option<&'static str> {
+    match variant {
+        ChessVariant::Standard => None,
+        ChessVariant::Chess960(_) => Some("Chess960"),
+        ChessVariant::KingOfTheHill => Some("King of the Hill"),
+        ChessVariant::ThreeCheck => Some("Three-check"),
+        ChessVariant::Atomic => Some("Atomic"),
+        ChessVariant::Crazyhouse => Some("Crazyhouse"),
+    }
+}
+
+// Chess960 is numbered from the FEN once that has been read
+fn parse_variant_tag(tag: &str) -> anyhow::Result<ChessVariant> {
+    let name = tag.to_ascii_lowercase().replace([' ', '-'], "");
+    match name.as_str() {
+        "standard" => Ok(ChessVariant::Standard),
+        "chess960" | "fischerandom" | "fischerrandom" => {
+            Ok(ChessVariant::Chess960(STANDARD_CHESS960))
+        }
+        "kingofthehill" => Ok(ChessVariant::KingOfTheHill),
+        "threecheck" => Ok(ChessVariant::ThreeCheck),
+        "atomic" => Ok(ChessVariant::Atomic),
+        "crazyhouse" => Ok(ChessVariant::Crazyhouse),
+        _ => bail!("Unknown variant {:?}", tag),
+    }
+}
+
+/// Builds the game from its tags and SAN moves, checking the result agrees with the moves
+fn replay(
+    tags: Vec<(String, String)>,
+    moves: &[String],
+    result: Option<String>,
+) -> anyhow::Result<PgnGame> {
+    let tag = |name: &str| {
+        tags.iter()
+            .find(|(tag, _)| tag == name)
+            .map(|(_, value)| value.clone())
+    };
+    let variant = match tag("Variant") {
+        Some(variant) => parse_variant_tag(&variant)?,
+        None => ChessVariant::Standard,
+    };
+    let start = match tag("FEN") {
+        Some(fen) => ChessPosition::from_fen(&fen, variant.rules())?,
+        None => variant.start_position(),
+    };
+    let variant = match variant {
+        // A position set up partway through leaves the number unused
+        ChessVariant::Chess960(_) => {
+            ChessVariant::Chess960(start.chess960_index().unwrap_or(STANDARD_CHESS960))
+        }
+        variant => variant,
+    };
+
+    let mut game = ChessGame::from_position(variant, start);
+    for san in moves {
+        let game_move = game
+            .position()
+            .parse_san(san)
+            .ok_or_else(|| anyhow!("Illegal move {} after {} moves", san, game.moves().len()))?;
+        game.play(&game.position().uci(&game_move))?;
+    }
+
+    let result = match (result, tag("Result")) {
+        (Some(result), Some(tag)) if result != tag => {
+            bail!("Result {} does not match the Result tag {}", result, tag)
+        }
+        (result, tag) => result.or(tag).unwrap_or_else(|| "*".to_string()),
+    };
+    if let Some(status) = game.status()
+        && status.score() != result
+    {
+        bail!("Result {} but the game ended in {}", result, status);
+    }
+
+    let mut pgn = PgnGame { tags, game };
+    pgn.set_tag("Result", &result);
+    Ok(pgn)
+}
+
+enum Token {
+    Tag(String, String),
+    Move(String), // SAN, without its move number
+    Result(String),
+}
+
+/// Splits PGN text into tags, moves and results, skipping comments, variations and NAGs
+struct Tokens<'a> {
+    chars: Peekable<Chars<'a>>,
+    pending: Option<Token>, // Read but belonging to the next game
+}
+
+impl Tokens<'_> {
+    fn next(&mut self) -> anyhow::Result<Option<Token>> {
+        if let Some(token) = self.pending.take() {
+            return Ok(Some(token));
+        }
+        while let Some(c) = self.chars.next() {
+            match c {
+                c if c.is_whitespace() => {}
+                '[' => return self.tag().map(Some),
+                '{' => self.skip_until('}')?,
+                ';' | '%' => while self.chars.next_if(|&c| c != '\n').is_some() {},
+                '(' => self.skip_variation()?,
+                '$' => while self.chars.next_if(char::is_ascii_digit).is_some() {},
+                c => {
+                    let mut symbol = c.to_string();
+                    while let Some(c) = self
+                        .chars
+                        .next_if(|&c| !c.is_whitespace() && !"[]{}();$".contains(c))
+                    {
+                        symbol.push(c);
+                    }
+                    if matches!(symbol.as_str(), "1-0" | "0-1" | "1/2-1/2" | "*") {
+                        return Ok(Some(Token::Result(symbol)));
+                    }
+                    // Move numbers, possibly run together with the move as in `1.e4`
+                    let san = match symbol.starts_with(|c: char| c.is_ascii_digit()) {
+                        true => symbol.rsplit('.').next().unwrap_or_default(),
+                        false => &symbol,
+                    };
+                    if !san.is_empty() {
+                        return Ok(Some(Token::Move(san.to_string())));
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    // After the opening `[`
+    fn tag(&mut self) -> anyhow::Result<Token> {
+        let mut name = String::new();
+        while let Some(c) = self
+            .chars
+            .next_if(|&c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            name.push(c);
+        }
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if name.is_empty() || self.chars.next() != Some('"') {
+            bail!("Invalid tag {:?}", name);
+        }
+        let mut value = String::new();
+        loop {
+            match self.chars.next() {
+                Some('\\') => value.extend(self.chars.next()),
+                Some('"') => break,
+                Some(c) => value.push(c),
+                None => bail!("Unterminated tag {}", name),
+            }
+        }
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+        match self.chars.next() {
+            Some(']') => Ok(Token::Tag(name, value)),
+            _ => bail!("Unterminated tag {}", name),
+        }
+    }
+
+    fn skip_until(&mut self, end: char) -> anyhow::Result<()> {
+        match self.chars.by_ref().any(|c| c == end) {
+            true => Ok(()),
+            false => bail!("Missing {:?}", end),
+        }
+    }
+
+    // After the opening `(`, variations can nest and hold comments with brackets in
+    fn skip_variation(&mut self) -> anyhow::Result<()> {
+        let mut depth = 1;
+        while depth > 0 {
+            match self.chars.next() {
+                Some('(') => depth += 1,
+                Some(')') => depth -= 1,
+                Some('{') => self.skip_until('}')?,
+                Some(_) => {}
+                None => bail!("Unterminated variation"),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `YYYY.MM.DD` in UTC, as the Date tag wants
+fn pgn_date(time: SystemTime) -> String {
+    let days = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs() / 86_400) as i64;
+    // Civil from days, counting from 0000-03-01 so leap days come last
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153; // From March
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = era * 400 + year_of_era + i64::from(month <= 2);
+    format!("{:04}.{:02}.{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess::STARTING_POSITION;
+    use std::time::Duration;
+
+    #[test]
+    fn test_pgn_round_trip() {
+        let moves = "f2f3 e7e5 g2g4 d8h4";
+        let game = format!("{} moves {}", STARTING_POSITION, moves)
+            .parse::<ChessGame>()
+            .unwrap();
+        let record = GameRecord {
+            game: game.clone(),
+            ending: Ending::Finished,
+            players: vec![
+                (ChessPlayer::White, "client".to_string()),
+                (ChessPlayer::Black, "Rusty Moves (random)".to_string()),
+            ],
+            started: UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+            move_time: Some(Duration::from_millis(500)),
+        };
+        let pgn = PgnGame::from_record(&record);
+        let text = pgn.to_string();
+        assert!(
+            text.starts_with("[Event \"Rusty Moves game\"]\n[Site \"?\"]\n[Date \"2023.11.14\"]")
+        );
+        assert!(text.contains("[Termination \"checkmate\"]\n[TimeControl \"-\"]\n"));
+        assert!(text.ends_with("\n\n1. f3 e5 2. g4 Qh4# 0-1\n"));
+
+        let parsed = text.parse::<PgnGame>().unwrap();
+        assert_eq!(parsed, pgn);
+        assert_eq!(parsed.tag("Black"), Some("Rusty Moves (random)"));
+
+        // Seconds per move are rounded down
+        let record = GameRecord {
+            move_time: Some(Duration::from_millis(2_500)),
+            ..record
+        };
+        let pgn = PgnGame::from_record(&record);
+        assert_eq!(pgn.tag("TimeControl"), Some("1/2"));
+
+        // Starting elsewhere, with black to move
+        let position = "4k3/8/8/8/8/8/4P3/4K3 b - - 0 30".parse().unwrap();
+        let mut game = ChessGame::from_position(ChessVariant::Standard, position);
+        game.play(&"e8d7".parse().unwrap()).unwrap();
+        let pgn = PgnGame::new(game);
+        assert!(pgn.to_string().contains(
+            "[SetUp \"1\"]\n[FEN \"4k3/8/8/8/8/8/4P3/4K3 b - - 0 30\"]\n\n30... Kd7 *\n"
+        ));
+        assert_eq!(pgn.to_string().parse::<PgnGame>().unwrap(), pgn);
+
+        // Chess960, castling king takes rook
+        let mut game = ChessGame::new(ChessVariant::Chess960(STANDARD_CHESS960));
+        for uci in ["e2e4", "e7e5", "g1f3", "b8c6", "f1c4", "g8f6", "e1h1"] {
+            game.play(&uci.parse().unwrap()).unwrap();
+        }
+        let pgn = PgnGame::new(game);
+        let text = pgn.to_string();
+        assert!(text.contains("[Variant \"Chess960\"]\n[SetUp \"1\"]\n[FEN \"rnbqkbnr/"));
+        assert!(text.contains("4. O-O *"));
+        assert_eq!(text.parse::<PgnGame>().unwrap(), pgn);
+
+        // Crazyhouse, from its usual start and with drops
+        let mut game = ChessGame::new(ChessVariant::Crazyhouse);
+        for uci in ["e2e4", "d7d5", "e4d5", "d8d5", "b1c3", "d5a5", "P@d5"] {
+            game.play(&uci.parse().unwrap()).unwrap();
+        }
+        let pgn = PgnGame::new(game);
+        let text = pgn.to_string();
+        assert!(text.contains("[Variant \"Crazyhouse\"]\n\n"));
+        assert!(text.contains("4. @d5 *"));
+        assert_eq!(text.parse::<PgnGame>().unwrap(), pgn);
+    }
+
+    #[test]
+    fn test_pgn_parse() {
+        let pgn = r#"
+[Event "F/S Return Match"]
+[White "Fischer, Robert J."]
+[Black "Spassky, Boris V."]
+[Annotator "Someone \"quoted\""]
+[Result "1/2-1/2"]
+
+1. e4 e5 2. Nf3 Nc6 3. Bb5 {This opening is called the Ruy Lopez.} 3... a6
+4. Ba4 Nf6 5. O-O Be7 (5... Nxe4 6. d4 {open (Ruy) Lopez}) 6. Re1 $1 b5 7.Bb3 d6
+; rest of the line
+8. c3 O-O 1/2-1/2
+
+[Event "Second"]
+1. f3 e5 2. g4 Qh4# 0-1
+"#;
+        let games = PgnGame::parse_all(pgn).unwrap();
+        assert_eq!(games.len(), 2);
+        assert_eq!(games[0].game.moves().len(), 16);
+        assert_eq!(games[0].tag("Annotator"), Some("Someone \"quoted\""));
+        assert_eq!(games[0].result(), "1/2-1/2");
+        assert_eq!(games[1].result(), "0-1");
+        assert!(games[1].game.status().is_some());
+
+        // Resumable from where it was left
+        let mut game = games[0].game.clone();
+        assert!(game.play(&"h2h3".parse().unwrap()).is_ok());
+
+        assert!("1. e4 e5 2. Ke3 *".parse::<PgnGame>().is_err());
+        assert!("1. f3 e5 2. g4 Qh4# 1-0".parse::<PgnGame>().is_err());
+        assert!("[Result \"1-0\"] 1. e4 0-1".parse::<PgnGame>().is_err());
+        assert!("[White \"unterminated] 1. e4 *".parse::<PgnGame>().is_err());
+    }
+}
@@ -0,0 +1,713 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Instant,
+};
+
+use super::{
+    CastlingSide, ChessGame, ChessMove, ChessPlayer, ChessPosition, PieceKind, Rules, Square,
+    Tablebase, UciMove, Wdl,
+};
+use crate::strategy::{SearchBudget, Strategy};
+
+const MATE: i32 = 30_000;
+const TABLEBASE_WIN: i32 = MATE - 2 * MAX_PLY as i32; // Below any mate, above any evaluation
+const MAX_DEPTH: i32 = 64;
+const MAX_PLY: usize = 128; // Quiescence can go past the nominal depth
+const TT_SIZE: usize = 1 << 16;
+const DRAW_OFFER_PLIES: usize = 100; // Before then, level positions are still played out
+
+// Simplified evaluation function tables (chessprogramming.org), from white's side with a8 first
+#[rustfmt::skip]
+const PAWN_TABLE: [i32; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  0,
+    50, 50, 50, 50, 50, 50, 50, 50,
+    10, 10, 20, 30, 30, 20, 10, 10,
+     5,  5, 10, 25, 25, 10,  5,  5,
+     0,  0,  0, 20, 20,  0,  0,  0,
+     5, -5,-10,  0,  0,-10, -5,  5,
+     5, 10, 10,-20,-20, 10, 10,  5,
+     0,  0,  0,  0,  0,  0,  0,  0,
+];
+#[rustfmt::skip]
+const KNIGHT_TABLE: [i32; 64] = [
+    -50,-40,-30,-30,-30,-30,-40,-50,
+    -40,-20,  0,  0,  0,  0,-20,-40,
+    -30,  0, 10, 15, 15, 10,  0,-30,
+    -30,  5, 15, 20, 20, 15,  5,-30,
+    -30,  0, 15, 20, 20, 15,  0,-30,
+    -30,  5, 10, 15, 15, 10,  5,-30,
+    -40,-20,  0,  5,  5,  0,-20,-40,
+    -50,-40,-30,-30,-30,-30,-40,-50,
+];
+#[rustfmt::skip]
+const BISHOP_TABLE: [i32; 64] = [
+    -20,-10,-10,-10,-10,-10,-10,-20,
+    -10,  0,  0,  0,  0,  0,  0,-10,
+    -10,  0,  5, 10, 10,  5,  0,-10,
+    -10,  5,  5, 10, 10,  5,  5,-10,
+    -10,  0, 10, 10, 10, 10,  0,-10,
+    -10, 10, 10, 10, 10, 10, 10,-10,
+    -10,  5,  0,  0,  0,  0,  5,-10,
+    -20,-10,-10,-10,-10,-10,-10,-20,
+];
+#[rustfmt::skip]
+const ROOK_TABLE: [i32; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  0,
+     5, 10, 10, 10, 10, 10, 10,  5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+     0,  0,  0,  5,  5,  0,  0,  0,
+];
+#[rustfmt::skip]
+const QUEEN_TABLE: [i32; 64] = [
+    -20,-10,-10, -5, -5,-10,-10,-20,
+    -10,  0,  0,  0,  0,  0,  0,-10,
+    -10,  0,  5,  5,  5,  5,  0,-10,
+     -5,  0,  5,  5,  5,  5,  0, -5,
+      0,  0,  5,  5,  5,  5,  0, -5,
+    -10,  5,  5,  5,  5,  5,  0,-10,
+    -10,  0,  5,  0,  0,  0,  0,-10,
+    -20,-10,-10, -5, -5,-10,-10,-20,
+];
+#[rustfmt::skip]
+const KING_MIDDLE_TABLE: [i32; 64] = [
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -20,-30,-30,-40,-40,-30,-30,-20,
+    -10,-20,-20,-20,-20,-20,-20,-10,
+     20, 20,  0,  0,  0,  0, 20, 20,
+     20, 30, 10,  0,  0, 10, 30, 20,
+];
+#[rustfmt::skip]
+const KING_END_TABLE: [i32; 64] = [
+    -50,-40,-30,-20,-20,-30,-40,-50,
+    -30,-20,-10,  0,  0,-10,-20,-30,
+    -30,-10, 20, 30, 30, 20,-10,-30,
+    -30,-10, 30, 40, 40, 30,-10,-30,
+    -30,-10, 30, 40, 40, 30,-10,-30,
+    -30,-10, 20, 30, 30, 20,-10,-30,
+    -30,-30,  0,  0,  0,  0,-30,-30,
+    -50,-30,-30,-30,-30,-30,-30,-50,
+];
+
+// Non-pawn material left, per side, below which kings should come out
+const ENDGAME_MATERIAL: i32 = 1300;
+
+pub fn piece_value(kind: PieceKind) -> i32 {
+    match kind {
+        PieceKind::Pawn => 100,
+        PieceKind::Knight => 320,
+        PieceKind::Bishop => 330,
+        PieceKind::Rook => 500,
+        PieceKind::Queen => 900,
+        PieceKind::King => 0,
+    }
+}
+
+/// Material and piece-square tables, in centipawns from the side to move's point of view
+pub fn evaluate(position: &ChessPosition) -> i32 {
+    let mut material = [0; 2];
+    for (_, piece) in position.pieces() {
+        if !matches!(piece.kind, PieceKind::Pawn | PieceKind::King) {
+            material[piece.color as usize] += piece_value(piece.kind);
+        }
+    }
+    let endgame = material
+        .iter()
+        .all(|&material| material <= ENDGAME_MATERIAL);
+
+    let mut score = 0;
+    for (square, piece) in position.pieces() {
+        let table = match piece.kind {
+            PieceKind::Pawn => &PAWN_TABLE,
+            PieceKind::Knight => &KNIGHT_TABLE,
+            PieceKind::Bishop => &BISHOP_TABLE,
+            PieceKind::Rook => &ROOK_TABLE,
+            PieceKind::Queen => &QUEEN_TABLE,
+            PieceKind::King if endgame => &KING_END_TABLE,
+            PieceKind::King => &KING_MIDDLE_TABLE,
+        };
+        // Flip the board for black, so both read the table from their own side
+        let row = match piece.color {
+            ChessPlayer::White => 7 - square.rank(),
+            ChessPlayer::Black => square.rank(),
+        };
+        let value = piece_value(piece.kind) + table[row as usize * 8 + square.file() as usize];
+        score += match piece.color == position.side_to_move() {
+            true => value,
+            false => -value,
+        };
+    }
+    // Crazyhouse pieces in hand are worth as much as on the board
+    for player in [ChessPlayer::White, ChessPlayer::Black] {
+        let value = PieceKind::ALL
+            .into_iter()
+            .map(|kind| position.pocket(player, kind) as i32 * piece_value(kind))
+            .sum::<i32>();
+        score += match player == position.side_to_move() {
+            true => value,
+            false => -value,
+        };
+    }
+    score
+}
+
+// Keys after the standard 781 for crazyhouse pockets, promoted pieces and three-check
+const POCKET_KEYS: usize = 781;
+const MAX_POCKET: u8 = 15; // Counts beyond this share a key
+const PROMOTED_KEYS: usize = POCKET_KEYS + 2 * 5 * (MAX_POCKET as usize + 1);
+const CHECK_KEYS: usize = PROMOTED_KEYS + 64;
+
+// Deterministic keys, so hashes are the same in every process
+const ZOBRIST_KEYS: [u64; CHECK_KEYS + 2 * 4] = {
+    let mut keys = [0; CHECK_KEYS + 2 * 4];
+    let mut state: u64 = 0x9e3779b97f4a7c15;
+    let mut i = 0;
+    while i < keys.len() {
+        // SplitMix64
+        state = state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        keys[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    keys
+};
+
+/// Zobrist hash of everything that makes two positions the same for searching
+pub fn zobrist(position: &ChessPosition) -> u64 {
+    let mut hash = 0;
+    for (square, piece) in position.pieces() {
+        let piece_index = piece.color as usize * 6 + piece.kind as usize;
+        hash ^= ZOBRIST_KEYS[piece_index * 64 + square.index()];
+    }
+    if position.side_to_move() == ChessPlayer::Black {
+        hash ^= ZOBRIST_KEYS[768];
+    }
+    let castling = position.castling();
+    for (i, player) in [ChessPlayer::White, ChessPlayer::Black]
+        .into_iter()
+        .enumerate()
+    {
+        for (j, side) in [CastlingSide::King, CastlingSide::Queen]
+            .into_iter()
+            .enumerate()
+        {
+            if castling.get(player, side).is_some() {
+                hash ^= ZOBRIST_KEYS[769 + i * 2 + j];
+            }
+        }
+    }
+    if let Some(square) = position.en_passant() {
+        hash ^= ZOBRIST_KEYS[773 + square.file() as usize];
+    }
+
+    match position.rules() {
+        Rules::Crazyhouse => {
+            for (i, player) in [ChessPlayer::White, ChessPlayer::Black]
+                .into_iter()
+                .enumerate()
+            {
+                for kind in &PieceKind::ALL[..5] {
+                    let count = position.pocket(player, *kind).min(MAX_POCKET) as usize;
+                    let index = (i * 5 + *kind as usize) * (MAX_POCKET as usize + 1) + count;
+                    hash ^= ZOBRIST_KEYS[POCKET_KEYS + index];
+                }
+            }
+            for (square, _) in position.pieces() {
+                if position.is_promoted(square) {
+                    hash ^= ZOBRIST_KEYS[PROMOTED_KEYS + square.index()];
+                }
+            }
+        }
+        Rules::ThreeCheck => {
+            for (i, player) in [ChessPlayer::White, ChessPlayer::Black]
+                .into_iter()
+                .enumerate()
+            {
+                let remaining = position.remaining_checks(player) as usize;
+                hash ^= ZOBRIST_KEYS[CHECK_KEYS + i * 4 + remaining];
+            }
+        }
+        _ => {}
+    }
+    hash
+}
+
+// Mate scores for games the variant has ended, won by whoever just moved
+fn variant_score(position: &ChessPosition, ply: usize) -> Option<i32> {
+    let result = position.variant_outcome()?;
+    Some(match result.winner() == Some(position.side_to_move()) {
+        true => MATE - ply as i32,
+        false => -MATE + ply as i32,
+    })
+}
+
+// Wins the fifty-move rule doesn't spoil, sooner being better, and everything else a draw
+fn tablebase_score(wdl: Wdl, ply: usize) -> i32 {
+    match wdl {
+        Wdl::Win => TABLEBASE_WIN - ply as i32,
+        Wdl::Loss => -TABLEBASE_WIN + ply as i32,
+        _ => 0,
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Bound {
+    Exact,
+    Lower, // Failed high, the score is at least this
+    Upper, // Failed low, the score is at most this
+}
+
+#[derive(Clone, Copy)]
+struct TtEntry {
+    key: u64,
+    depth: i32,
+    score: i32,
+    bound: Bound,
+    best_move: Option<ChessMove>,
+}
+
+// Mate scores are stored relative to the node, not the root
+fn score_to_tt(score: i32, ply: usize) -> i32 {
+    match score {
+        score if score > MATE - MAX_PLY as i32 => score + ply as i32,
+        score if score < -MATE + MAX_PLY as i32 => score - ply as i32,
+        score => score,
+    }
+}
+
+fn score_from_tt(score: i32, ply: usize) -> i32 {
+    match score {
+        score if score > MATE - MAX_PLY as i32 => score - ply as i32,
+        score if score < -MATE + MAX_PLY as i32 => score + ply as i32,
+        score => score,
+    }
+}
+
+/// Built-in chess engine, so chess can be played without a UCI engine installed
+///
+/// Iterative deepening alpha-beta with quiescence search and a transposition table.
+pub struct NativeEngine {
+    budget: SearchBudget,
+    tt: Vec<Option<TtEntry>>,
+    path: Vec<u64>, // Keys of every position before the current node, for repetitions
+    nodes: u64,
+    deadline: Option<Instant>,
+    max_nodes: Option<u64>,
+    aborted: bool,
+    last_score: Option<i32>, // Of the move it last chose, for deciding on draws and resigning
+    tablebase: Option<Arc<Tablebase>>,
+    stop: Option<Arc<AtomicBool>>,
+}
+
+impl NativeEngine {
+    pub fn new(budget: SearchBudget) -> NativeEngine {
+        NativeEngine {
+            budget,
+            tt: vec![None; TT_SIZE],
+            path: vec![],
+            nodes: 0,
+            deadline: None,
+            max_nodes: None,
+            aborted: false,
+            last_score: None,
+            tablebase: None,
+            stop: None,
+        }
+    }
+
+    /// Plays perfectly from positions in the tablebase, and probes it during searches
+    pub fn with_tablebase(mut self, tablebase: Arc<Tablebase>) -> NativeEngine {
+        self.tablebase = Some(tablebase);
+        self
+    }
+
+    /// Ends searches early once `stop` is set, with the best move found so far
+    pub fn with_stop(mut self, stop: Arc<AtomicBool>) -> NativeEngine {
+        self.stop = Some(stop);
+        self
+    }
+
+    /// Nodes searched by the last `search`
+    pub fn nodes(&self) -> u64 {
+        self.nodes
+    }
+
+    /// How well `side` is doing, from the last search or a static evaluation before one
+    fn outlook(&self, game: &ChessGame, side: ChessPlayer) -> i32 {
+        self.last_score.unwrap_or_else(|| {
+            let score = evaluate(game.position());
+            match game.position().side_to_move() == side {
+                true => score,
+                false => -score,
+            }
+        })
+    }
+
+    /// The best move and its score for the side to move, or `None` if there are no moves
+    pub fn search(&mut self, game: &ChessGame, budget: SearchBudget) -> Option<(ChessMove, i32)> {
+        let mut position = game.start().clone();
+        self.path = vec![zobrist(&position)];
+        for game_move in game.moves() {
+            position.play_unchecked(game_move);
+            self.path.push(zobrist(&position));
+        }
+        self.search_position(game.position(), budget)
+    }
+
+    fn search_position(
+        &mut self,
+        root: &ChessPosition,
+        budget: SearchBudget,
+    ) -> Option<(ChessMove, i32)> {
+        let mut moves = root.legal_moves();
+        if moves.is_empty() {
+            return None;
+        }
+        if let Some(tablebase) = self.tablebase.as_ref().filter(|tb| tb.covers(root))
+            && let Ok(Some((game_move, wdl))) = tablebase.best_move(root)
+        {
+            return Some((game_move, tablebase_score(wdl, 0)));
+        }
+        self.nodes = 0;
+        self.aborted = false;
+        self.deadline = budget.max_time.map(|time| Instant::now() + time);
+        self.max_nodes = budget.max_nodes;
+
+        let mut best = (moves[0], 0);
+        for depth in 1..=MAX_DEPTH {
+            // Best move so far first, as it is the likeliest to stay best
+            let index = moves.iter().position(|&game_move| game_move == best.0);
+            moves.swap(0, index.expect("Best move is legal"));
+
+            let mut alpha = -MATE - 1;
+            let mut depth_best = best;
+            for &game_move in &moves {
+                let mut next = root.clone();
+                next.play_unchecked(&game_move);
+                let score = -self.negamax(&next, depth - 1, -MATE - 1, -alpha, 1);
+                if self.aborted {
+                    break;
+                }
+                if score > alpha {
+                    alpha = score;
+                    depth_best = (game_move, score);
+                }
+            }
+            if self.aborted {
+                // A better move found before time ran out still beats the last depth's
+                if depth_best.0 != best.0 && depth_best.1 > best.1 {
+                    best = depth_best;
+                }
+                break;
+            }
+            best = depth_best;
+            if best.1.abs() > MATE - MAX_PLY as i32 {
+                break;
+            }
+        }
+        Some(best)
+    }
+
+    fn out_of_budget(&mut self) -> bool {
+        if !self.aborted && self.nodes.is_multiple_of(1024) {
+            let out_of_time = self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline);
+            let out_of_nodes = self.max_nodes.is_some_and(|max| self.nodes >= max);
+            let stopped = self
+                .stop
+                .as_ref()
+                .is_some_and(|stop| stop.load(Ordering::Relaxed));
+            self.aborted = out_of_time || out_of_nodes || stopped;
+        }
+        self.aborted
+    }
+
+    fn is_draw(&self, position: &ChessPosition, key: u64) -> bool {
+        // A single repetition is enough, as it could be repeated again
+        let reversible = position.halfmove_clock() as usize;
+        position.halfmove_clock() >= 100
+            || self
+                .path
+                .iter()
+                .rev()
+                .take(reversible)
+                .any(|&old| old == key)
+            || position.is_insufficient_material()
+    }
+
+    // Tablebase result, only just after a capture or pawn move as tables ignore the move clock
+    fn probe(&self, position: &ChessPosition, ply: usize) -> Option<i32> {
+        let tablebase = self.tablebase.as_ref()?;
+        if position.halfmove_clock() != 0 || !tablebase.covers(position) {
+            return None;
+        }
+        let wdl = tablebase.probe_wdl(position).ok()?;
+        Some(tablebase_score(wdl, ply))
+    }
+
+    fn negamax(
+        &mut self,
+        position: &ChessPosition,
+        depth: i32,
+        mut alpha: i32,
+        beta: i32,
+        ply: usize,
+    ) -> i32 {
+        self.nodes += 1;
+        if self.out_of_budget() {
+            return 0;
+        }
+        let key = zobrist(position);
+        if self.is_draw(position, key) {
+            return 0;
+        }
+        if let Some(score) = variant_score(position, ply) {
+            return score;
+        }
+        if let Some(score) = self.probe(position, ply) {
+            return score;
+        }
+
+        let in_check = position.is_check();
+        let depth = depth + in_check as i32; // Look further into checks
+        if depth <= 0 || ply >= MAX_PLY {
+            return self.quiesce(position, alpha, beta, ply);
+        }
+
+        let index = key as usize % TT_SIZE;
+        let mut tt_move = None;
+        if let Some(entry) = self.tt[index].filter(|entry| entry.key == key) {
+            tt_move = entry.best_move;
+            let score = score_from_tt(entry.score, ply);
+            let usable = match entry.bound {
+                Bound::Exact => true,
+                Bound::Lower => score >= beta,
+                Bound::Upper => score <= alpha,
+            };
+            if entry.depth >= depth && usable {
+                return score;
+            }
+        }
+
+        let mut moves = position.legal_moves();
+        if moves.is_empty() {
+            return match in_check {
+                true => -MATE + ply as i32,
+                false => 0,
+            };
+        }
+        order_moves(position, &mut moves, tt_move);
+
+        let original_alpha = alpha;
+        let mut best = (-MATE - 1, None);
+        self.path.push(key);
+        for game_move in moves {
+            let mut next = position.clone();
+            next.play_unchecked(&game_move);
+            let score = -self.negamax(&next, depth - 1, -beta, -alpha, ply + 1);
+            if self.aborted {
+                break;
+            }
+            if score > best.0 {
+                best = (score, Some(game_move));
+            }
+            alpha = alpha.max(score);
+            if alpha >= beta {
+                break;
+            }
+        }
+        self.path.pop();
+        if self.aborted {
+            return 0;
+        }
+
+        let bound = match best.0 {
+            score if score <= original_alpha => Bound::Upper,
+            score if score >= beta => Bound::Lower,
+            _ => Bound::Exact,
+        };
+        self.tt[index] = Some(TtEntry {
+            key,
+            depth,
+            score: score_to_tt(best.0, ply),
+            bound,
+            best_move: best.1,
+        });
+        best.0
+    }
+
+    // Only captures and promotions, unless in check, until the position is quiet
+    fn quiesce(&mut self, position: &ChessPosition, mut alpha: i32, beta: i32, ply: usize) -> i32 {
+        self.nodes += 1;
+        if self.out_of_budget() {
+            return 0;
+        }
+        if let Some(score) = variant_score(position, ply) {
+            return score;
+        }
+        let in_check = position.is_check();
+        if ply >= MAX_PLY {
+            return evaluate(position);
+        }
+        if !in_check {
+            let stand_pat = evaluate(position);
+            if stand_pat >= beta {
+                return stand_pat;
+            }
+            alpha = alpha.max(stand_pat);
+        }
+
+        let mut moves = position.legal_moves();
+        if moves.is_empty() && in_check {
+            return -MATE + ply as i32;
+        }
+        if !in_check {
+            moves.retain(|game_move| is_tactical(position, game_move));
+        }
+        order_moves(position, &mut moves, None);
+
+        for game_move in moves {
+            let mut next = position.clone();
+            next.play_unchecked(&game_move);
+            let score = -self.quiesce(&next, -beta, -alpha, ply + 1);
+            if self.aborted {
+                return 0;
+            }
+            if score >= beta {
+                return score;
+            }
+            alpha = alpha.max(score);
+        }
+        alpha
+    }
+}
+
+fn captured(position: &ChessPosition, game_move: &ChessMove) -> Option<PieceKind> {
+    match *game_move {
+        ChessMove::Normal { to, .. } => position.piece_at(to).map(|piece| piece.kind),
+        ChessMove::EnPassant { .. } => Some(PieceKind::Pawn),
+        ChessMove::Castle { .. } | ChessMove::Drop { .. } => None,
+    }
+}
+
+fn is_tactical(position: &ChessPosition, game_move: &ChessMove) -> bool {
+    captured(position, game_move).is_some() || game_move.promotion().is_some()
+}
+
+// Transposition table move, then captures of the most valuable piece by the least valuable
+fn order_moves(position: &ChessPosition, moves: &mut [ChessMove], tt_move: Option<ChessMove>) {
+    let attacker = |from: Square| {
+        position
+            .piece_at(from)
+            .map_or(0, |piece| piece_value(piece.kind))
+    };
+    moves.sort_by_cached_key(|game_move| {
+        let mut score = 0;
+        if Some(*game_move) == tt_move {
+            score += 1_000_000;
+        }
+        if let Some(victim) = captured(position, game_move) {
+            score += 10_000 + 10 * piece_value(victim) - attacker(game_move.from());
+        }
+        if let Some(promotion) = game_move.promotion() {
+            score += 5_000 + piece_value(promotion);
+        }
+        -score
+    });
+}
+
+impl Strategy<ChessGame> for NativeEngine {
+    fn choose_move(
+        &mut self,
+        game: &ChessGame,
+        _side: ChessPlayer,
+        budget: Option<SearchBudget>,
+    ) -> UciMove {
+        let (game_move, score) = self
+            .search(game, budget.unwrap_or(self.budget))
+            .expect("No legal moves left");
+        self.last_score = Some(score);
+        game.position().uci(&game_move)
+    }
+
+    fn new_game(&mut self) {
+        self.tt.fill(None);
+        self.last_score = None;
+    }
+
+    fn accept_draw(&mut self, game: &ChessGame, side: ChessPlayer) -> bool {
+        self.outlook(game, side) <= 0
+    }
+
+    fn offer_draw(&mut self, game: &ChessGame, side: ChessPlayer) -> bool {
+        game.moves().len() >= DRAW_OFFER_PLIES && self.outlook(game, side) == 0
+    }
+
+    fn resign(&mut self, game: &ChessGame, side: ChessPlayer) -> bool {
+        // Only once it sees itself getting mated
+        self.outlook(game, side) <= -MATE + MAX_PLY as i32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess::ChessVariant;
+
+    fn best(fen: &str) -> (String, i32) {
+        let position = fen.parse().unwrap();
+        let game = ChessGame::from_position(ChessVariant::Standard, position);
+        let mut engine = NativeEngine::new(SearchBudget::nodes(30_000));
+        let (game_move, score) = engine.search(&game, SearchBudget::nodes(30_000)).unwrap();
+        (game_move.to_string(), score)
+    }
+
+    #[test]
+    fn test_search() {
+        // Back rank mate
+        let (game_move, score) = best("6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1");
+        assert_eq!(game_move, "a1a8");
+        assert_eq!(score, MATE - 1);
+
+        // Take the undefended queen, not the defended pawn
+        let (game_move, _) = best("4k3/8/2p5/1p1q4/8/8/3R4/3RK3 w - - 0 1");
+        assert_eq!(game_move, "d2d5");
+
+        // Ladder mate in two
+        let (_, score) = best("7k/8/8/8/8/8/R7/1R5K w - - 0 1");
+        assert_eq!(score, MATE - 3);
+
+        // Gives up once it sees the mate coming, and won't agree to a draw when winning
+        let position = "k7/8/1Q6/7p/8/8/8/K6R b - - 0 1".parse().unwrap();
+        let game = ChessGame::from_position(ChessVariant::Standard, position);
+        let mut engine = NativeEngine::new(SearchBudget::nodes(30_000));
+        assert!(!engine.accept_draw(&game, ChessPlayer::White));
+        assert!(!engine.resign(&game, ChessPlayer::Black));
+        engine.choose_move(&game, ChessPlayer::Black, None);
+        assert!(engine.resign(&game, ChessPlayer::Black));
+        assert!(engine.accept_draw(&game, ChessPlayer::Black));
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let position = ChessPosition::new();
+        assert_eq!(evaluate(&position), 0);
+
+        let mut up_a_knight = position.clone();
+        up_a_knight.set_piece("g8".parse().unwrap(), None);
+        assert!(evaluate(&up_a_knight) > 250);
+        assert_ne!(zobrist(&position), zobrist(&up_a_knight));
+    }
+}
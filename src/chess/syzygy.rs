@@ -0,0 +1,1576 @@
+use anyhow::{anyhow, bail};
+use std::{
+    collections::HashMap,
+    env, fmt,
+    fs::{self, File},
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    ops::Neg,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use super::{ChessMove, ChessPlayer, ChessPosition, Piece, PieceKind, Rules};
+
+// A directory of Syzygy tables the native engine and the referee probe, read once
+pub const SYZYGY_ENV_VAR: &str = "SYZYGY_PATH";
+
+const WDL_MAGIC: [u8; 4] = [0x71, 0xe8, 0x23, 0x5d];
+const DTZ_MAGIC: [u8; 4] = [0xd7, 0x66, 0x0c, 0xa5];
+
+// Table names list each side's pieces in this order, e.g. KRPvKR
+const NAME_ORDER: [PieceKind; 6] = [
+    PieceKind::King,
+    PieceKind::Queen,
+    PieceKind::Rook,
+    PieceKind::Bishop,
+    PieceKind::Knight,
+    PieceKind::Pawn,
+];
+
+// Flags in the first byte of a table
+const SPLIT: u8 = 1; // A WDL subtable for each side to move
+const HAS_PAWNS: u8 = 2;
+
+// Flags of each subtable
+const STM: u8 = 1; // Which side to move a DTZ table is for, black if set
+const MAPPED: u8 = 2; // DTZ values go through a map
+const WIN_PLIES: u8 = 4; // Wins are stored in plies rather than moves
+const LOSS_PLIES: u8 = 8;
+const WIDE: u8 = 16; // The map has 16 bit values
+const SINGLE_VALUE: u8 = 128; // Every position has the same value
+
+/// Win, draw or loss for the side to move. Cursed wins and blessed losses take more than
+/// fifty moves without a capture or pawn move, so are draws once the fifty-move rule is claimed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Wdl {
+    Loss,
+    BlessedLoss,
+    Draw,
+    CursedWin,
+    Win,
+}
+
+impl Wdl {
+    fn from_stored(value: u16) -> anyhow::Result<Wdl> {
+        Ok(match value {
+            0 => Wdl::Loss,
+            1 => Wdl::BlessedLoss,
+            2 => Wdl::Draw,
+            3 => Wdl::CursedWin,
+            4 => Wdl::Win,
+            _ => bail!("Corrupt WDL value {}", value),
+        })
+    }
+
+    /// Whether it is a draw with the fifty-move rule
+    pub fn is_draw(self) -> bool {
+        matches!(self, Wdl::BlessedLoss | Wdl::Draw | Wdl::CursedWin)
+    }
+
+    fn signum(self) -> i32 {
+        (self as i32 - 2).signum()
+    }
+}
+
+/// The same result for the other side
+impl Neg for Wdl {
+    type Output = Wdl;
+
+    fn neg(self) -> Wdl {
+        match self {
+            Wdl::Loss => Wdl::Win,
+            Wdl::BlessedLoss => Wdl::CursedWin,
+            Wdl::Draw => Wdl::Draw,
+            Wdl::CursedWin => Wdl::BlessedLoss,
+            Wdl::Win => Wdl::Loss,
+        }
+    }
+}
+
+// DTZ of a position whose best move is a capture or pawn move
+fn dtz_before_zeroing(wdl: Wdl) -> i32 {
+    match wdl {
+        Wdl::Loss => -1,
+        Wdl::BlessedLoss => -101,
+        Wdl::Draw => 0,
+        Wdl::CursedWin => 101,
+        Wdl::Win => 1,
+    }
+}
+
+// Squares are 0 to 63 from a1, as in `Square::index`
+fn off_diagonal(square: usize) -> i32 {
+    (square / 8) as i32 - (square % 8) as i32
+}
+
+// Mirrored in the a1-h8 diagonal
+fn flip_diagonal(square: usize) -> usize {
+    ((square >> 3) | (square << 3)) & 63
+}
+
+fn edge_distance(file: usize) -> usize {
+    file.min(7 - file)
+}
+
+// White pawn is 1 up to king 6, black ones 8 more
+fn piece_code(piece: Piece) -> u8 {
+    piece.kind as u8 + 1 + (piece.color as u8) * 8
+}
+
+fn is_capture(position: &ChessPosition, game_move: &ChessMove) -> bool {
+    match *game_move {
+        ChessMove::Normal { to, .. } => position.piece_at(to).is_some(),
+        ChessMove::EnPassant { .. } => true,
+        ChessMove::Castle { .. } | ChessMove::Drop { .. } => false,
+    }
+}
+
+// Captures and pawn moves reset the fifty-move counter
+fn is_zeroing(position: &ChessPosition, game_move: &ChessMove) -> bool {
+    let pawn_move = match *game_move {
+        ChessMove::Normal { from, .. } => position
+            .piece_at(from)
+            .is_some_and(|piece| piece.kind == PieceKind::Pawn),
+        _ => false,
+    };
+    pawn_move || is_capture(position, game_move)
+}
+
+// Tables for turning squares into table indices, the same for every table
+struct Indices {
+    binomial: [[u64; 64]; 7], // Ways to choose k of n
+    map_pawns: [usize; 64],   // a2 to h7, highest nearest the edge and the back rank
+    lead_pawn_idx: [[u64; 64]; 6],
+    lead_pawns_size: [[u64; 4]; 6], // By the leading pawn's file
+    map_b1h1h7: [usize; 64],        // Below the a1-h8 diagonal, 0 to 27
+    map_a1d1d4: [usize; 64],        // The a1-d1-d4 triangle, diagonal last, 0 to 9
+    map_kk: [[u64; 64]; 10],        // The 462 ways to place both kings, the first in the triangle
+}
+
+impl Indices {
+    fn new() -> Indices {
+        let mut binomial = [[0; 64]; 7];
+        binomial[0][0] = 1;
+        for n in 1..64 {
+            for k in 0..7.min(n + 1) {
+                binomial[k][n] = match k {
+                    0 => 0,
+                    _ => binomial[k - 1][n - 1],
+                } + match k < n {
+                    true => binomial[k][n - 1],
+                    false => 0,
+                };
+            }
+        }
+
+        let mut map_pawns = [0; 64];
+        let mut lead_pawn_idx = [[0; 64]; 6];
+        let mut lead_pawns_size = [[0; 4]; 6];
+        let mut available = 48;
+        for lead_pawns in 1..6 {
+            for (file, size) in lead_pawns_size[lead_pawns].iter_mut().enumerate() {
+                let mut idx = 0;
+                for rank in 1..7 {
+                    let square = rank * 8 + file;
+                    if lead_pawns == 1 {
+                        available -= 1;
+                        map_pawns[square] = available;
+                        available -= 1;
+                        map_pawns[square ^ 7] = available;
+                    }
+                    lead_pawn_idx[lead_pawns][square] = idx;
+                    idx += binomial[lead_pawns - 1][map_pawns[square]];
+                }
+                *size = idx;
+            }
+        }
+
+        let mut map_b1h1h7 = [0; 64];
+        let below = (0..64).filter(|&square| off_diagonal(square) < 0);
+        for (code, square) in below.enumerate() {
+            map_b1h1h7[square] = code;
+        }
+
+        let mut map_a1d1d4 = [0; 64];
+        let triangle = (0..28).filter(|&square| square % 8 <= 3);
+        let below = triangle.clone().filter(|&square| off_diagonal(square) < 0);
+        let diagonal = triangle.filter(|&square| off_diagonal(square) == 0);
+        for (code, square) in below.chain(diagonal).enumerate() {
+            map_a1d1d4[square] = code;
+        }
+
+        let mut map_kk = [[0; 64]; 10];
+        let mut both_on_diagonal = vec![];
+        let mut code = 0;
+        for (idx, kings) in map_kk.iter_mut().enumerate() {
+            // b1 is the only square mapped to 0, the others just aren't in the triangle
+            let first =
+                (0..28).filter(|&square| map_a1d1d4[square] == idx && (idx > 0 || square == 1));
+            for king in first {
+                for (other, kk) in kings.iter_mut().enumerate() {
+                    let distance = (king % 8)
+                        .abs_diff(other % 8)
+                        .max((king / 8).abs_diff(other / 8));
+                    if distance <= 1 || off_diagonal(king) == 0 && off_diagonal(other) > 0 {
+                        continue;
+                    }
+                    if off_diagonal(king) == 0 && off_diagonal(other) == 0 {
+                        both_on_diagonal.push((idx, other));
+                        continue;
+                    }
+                    *kk = code;
+                    code += 1;
+                }
+            }
+        }
+        for (idx, other) in both_on_diagonal {
+            map_kk[idx][other] = code;
+            code += 1;
+        }
+
+        Indices {
+            binomial,
+            map_pawns,
+            lead_pawn_idx,
+            lead_pawns_size,
+            map_b1h1h7,
+            map_a1d1d4,
+            map_kk,
+        }
+    }
+}
+
+fn indices() -> &'static Indices {
+    static INDICES: OnceLock<Indices> = OnceLock::new();
+    INDICES.get_or_init(Indices::new)
+}
+
+/// Pieces on the board by color, then kind, which is what a table is for
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct Material([[u8; 6]; 2]);
+
+impl Material {
+    fn of(position: &ChessPosition) -> Material {
+        let mut counts = [[0; 6]; 2];
+        for (_, piece) in position.pieces() {
+            counts[piece.color as usize][piece.kind as usize] += 1;
+        }
+        Material(counts)
+    }
+
+    // A table name, e.g. KRvK
+    fn parse(name: &str) -> Option<Material> {
+        let (white, black) = name.split_once('v')?;
+        let mut counts = [[0; 6]; 2];
+        for (side, pieces) in [white, black].into_iter().enumerate() {
+            if !pieces.starts_with('K') {
+                return None;
+            }
+            for c in pieces.chars() {
+                counts[side][PieceKind::from_char(c)? as usize] += 1;
+            }
+        }
+        let material = Material(counts);
+        (material.count() <= 7
+            && material
+                .0
+                .iter()
+                .all(|side| side[PieceKind::King as usize] == 1))
+        .then_some(material)
+    }
+
+    fn flipped(self) -> Material {
+        Material([self.0[1], self.0[0]])
+    }
+
+    fn count(&self) -> usize {
+        self.0.iter().flatten().map(|&count| count as usize).sum()
+    }
+
+    fn pawns(&self, player: ChessPlayer) -> u8 {
+        self.0[player as usize][PieceKind::Pawn as usize]
+    }
+
+    fn has_pawns(&self) -> bool {
+        self.pawns(ChessPlayer::White) + self.pawns(ChessPlayer::Black) > 0
+    }
+
+    // Both sides have the same pieces, so only white to move is stored
+    fn is_symmetric(&self) -> bool {
+        self.0[0] == self.0[1]
+    }
+
+    // Any single piece but a king, which pawnless tables encode along with both kings
+    fn has_unique_pieces(&self) -> bool {
+        self.0
+            .iter()
+            .any(|side| side[..PieceKind::King as usize].contains(&1))
+    }
+
+    // Pawns of the leading side and then the other, the side with fewer pawns leading
+    // unless it has none
+    fn pawn_counts(&self) -> [u8; 2] {
+        let white = self.pawns(ChessPlayer::White);
+        let black = self.pawns(ChessPlayer::Black);
+        match black == 0 || (white > 0 && black >= white) {
+            true => [white, black],
+            false => [black, white],
+        }
+    }
+}
+
+impl fmt::Display for Material {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (side, counts) in self.0.iter().enumerate() {
+            if side > 0 {
+                write!(f, "v")?;
+            }
+            for kind in NAME_ORDER {
+                for _ in 0..counts[kind as usize] {
+                    write!(f, "{}", kind.to_char().to_ascii_uppercase())?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TableKind {
+    Wdl,
+    Dtz,
+}
+
+impl TableKind {
+    fn magic(self) -> [u8; 4] {
+        match self {
+            TableKind::Wdl => WDL_MAGIC,
+            TableKind::Dtz => DTZ_MAGIC,
+        }
+    }
+}
+
+// Reads a table's header front to back, keeping track of where it is in the file
+struct HeaderReader {
+    reader: BufReader<File>,
+    offset: u64,
+}
+
+impl HeaderReader {
+    fn bytes(&mut self, len: usize) -> io::Result<Vec<u8>> {
+        let mut bytes = vec![0; len];
+        self.reader.read_exact(&mut bytes)?;
+        self.offset += len as u64;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    // Skips to the next multiple of `to`, returning the padding
+    fn align(&mut self, to: u64) -> io::Result<Vec<u8>> {
+        self.bytes((self.offset.next_multiple_of(to) - self.offset) as usize)
+    }
+}
+
+fn read_at(file: &Mutex<File>, offset: u64, len: usize) -> anyhow::Result<Vec<u8>> {
+    let mut file = file.lock().unwrap();
+    let mut bytes = vec![0; len];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+// A pair of 12 bit symbols a symbol expands into, or for a single value its value and 0xfff
+fn left(node: [u8; 3]) -> usize {
+    ((node[1] as usize & 0xf) << 8) | node[0] as usize
+}
+
+fn right(node: [u8; 3]) -> usize {
+    ((node[2] as usize) << 4) | (node[1] as usize >> 4)
+}
+
+/// One subtable: the positions for one side to move, and for pawns one leading pawn file.
+/// Values are compressed by recursive pairing, then Huffman coded in blocks.
+#[derive(Debug, Default)]
+struct PairsData {
+    pieces: Vec<u8>,       // In the order the position is encoded
+    group_len: Vec<usize>, // Pieces encoded together, the first group being the leading pieces
+    group_idx: Vec<u64>,   // What each group's index is multiplied by, then the table size
+    flags: u8,
+    block_size: u64,
+    span: u64, // Values between entries of the sparse index
+    num_blocks: u64,
+    min_sym_len: u8, // The value itself, for a single value table
+    lowest_sym: Vec<u16>,
+    base64: Vec<u64>, // Lowest code of each length, left-aligned
+    symlen: Vec<u8>,  // Values each symbol expands to, less one
+    btree: Vec<[u8; 3]>,
+    sparse_index_size: u64,
+    block_lengths_size: u64,
+    // Where the rest is in the file
+    sparse_index: u64,
+    block_lengths: u64,
+    data: u64,
+    map_idx: [usize; 4], // Start of the DTZ map for a win, loss, cursed win and blessed loss
+}
+
+impl PairsData {
+    fn new(material: &Material, pieces: Vec<u8>, order: [u8; 2], file: usize) -> PairsData {
+        let indices = indices();
+        let has_pawns = material.has_pawns();
+        let unique = material.has_unique_pieces();
+
+        // Pawnless tables encode the kings and maybe a unique piece together
+        let mut first_len: i32 = match (has_pawns, unique) {
+            (true, _) => 0,
+            (false, true) => 3,
+            (false, false) => 2,
+        };
+        let mut group_len = vec![1];
+        for i in 1..pieces.len() {
+            first_len -= 1;
+            match first_len > 0 || pieces[i] == pieces[i - 1] {
+                true => *group_len.last_mut().unwrap() += 1,
+                false => group_len.push(1),
+            }
+        }
+
+        // Groups are multiplied in the table's own order, which the leading group and
+        // the other side's pawns have a place in
+        let both_pawns = has_pawns && material.pawn_counts()[1] > 0;
+        let groups = group_len.len();
+        let mut next = 1 + both_pawns as usize;
+        let mut free_squares = 64 - group_len[0] - if both_pawns { group_len[1] } else { 0 };
+        let mut group_idx = vec![0; groups + 1];
+        let mut idx = 1;
+        let mut k = 0;
+        while next < groups || k == order[0] || k == order[1] {
+            if k == order[0] {
+                group_idx[0] = idx;
+                idx *= match (has_pawns, unique) {
+                    (true, _) => indices.lead_pawns_size[group_len[0]][file],
+                    (false, true) => 31332,
+                    (false, false) => 462,
+                };
+            } else if k == order[1] {
+                group_idx[1] = idx;
+                idx *= indices.binomial[group_len[1]][48 - group_len[0]];
+            } else {
+                group_idx[next] = idx;
+                idx *= indices.binomial[group_len[next]][free_squares];
+                free_squares -= group_len[next];
+                next += 1;
+            }
+            k += 1;
+        }
+        group_idx[groups] = idx;
+
+        PairsData {
+            pieces,
+            group_len,
+            group_idx,
+            ..PairsData::default()
+        }
+    }
+
+    // Positions in the subtable
+    fn size(&self) -> u64 {
+        *self.group_idx.last().unwrap()
+    }
+
+    fn read_sizes(&mut self, header: &mut HeaderReader) -> anyhow::Result<()> {
+        self.flags = header.u8()?;
+        if self.flags & SINGLE_VALUE != 0 {
+            self.min_sym_len = header.u8()?;
+            return Ok(());
+        }
+        let shift = |bits: u8| {
+            1u64.checked_shl(bits as u32)
+                .ok_or(anyhow!("Corrupt sizes"))
+        };
+        self.block_size = shift(header.u8()?)?;
+        self.span = shift(header.u8()?)?;
+        self.sparse_index_size = self.size().div_ceil(self.span);
+        let padding = header.u8()? as u64; // Block lengths past the end, so the index can't overrun
+        self.num_blocks = header.u32()? as u64;
+        self.block_lengths_size = self.num_blocks + padding;
+        let max_sym_len = header.u8()?;
+        self.min_sym_len = header.u8()?;
+        if self.min_sym_len == 0 || max_sym_len < self.min_sym_len {
+            bail!("Corrupt symbol lengths");
+        }
+
+        // Canonical Huffman codes, longer codes being lower, so each length starts from
+        // half of where the next longer one ends
+        let lengths = (max_sym_len - self.min_sym_len + 1) as usize;
+        self.lowest_sym = (0..lengths)
+            .map(|_| header.u16())
+            .collect::<io::Result<_>>()?;
+        self.base64 = vec![0; lengths];
+        for i in (0..lengths - 1).rev() {
+            self.base64[i] = self.base64[i + 1]
+                .wrapping_add(self.lowest_sym[i] as u64)
+                .wrapping_sub(self.lowest_sym[i + 1] as u64)
+                / 2;
+        }
+        for (i, base) in self.base64.iter_mut().enumerate() {
+            *base = base
+                .checked_shl(64 - i as u32 - self.min_sym_len as u32)
+                .unwrap_or(0);
+        }
+
+        let symbols = header.u16()? as usize;
+        self.btree = (0..symbols)
+            .map(|_| header.bytes(3).map(|bytes| [bytes[0], bytes[1], bytes[2]]))
+            .collect::<io::Result<_>>()?;
+        if symbols % 2 == 1 {
+            header.u8()?;
+        }
+        self.symlen = vec![0; symbols];
+        let mut visited = vec![false; symbols];
+        for symbol in 0..symbols {
+            if !visited[symbol] {
+                self.symlen[symbol] = self.set_symlen(symbol, &mut visited)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn set_symlen(&mut self, symbol: usize, visited: &mut [bool]) -> anyhow::Result<u8> {
+        visited[symbol] = true;
+        let node = self.btree[symbol];
+        if right(node) == 0xfff {
+            return Ok(0);
+        }
+        let mut len = 1u8;
+        for child in [left(node), right(node)] {
+            if child >= self.btree.len() {
+                bail!("Corrupt symbol tree");
+            }
+            if !visited[child] {
+                self.symlen[child] = self.set_symlen(child, visited)?;
+            }
+            len = len.wrapping_add(self.symlen[child]);
+        }
+        Ok(len)
+    }
+
+    /// The value at `idx`
+    fn decompress(&self, file: &Mutex<File>, idx: u64) -> anyhow::Result<u16> {
+        if self.flags & SINGLE_VALUE != 0 {
+            return Ok(self.min_sym_len as u16);
+        }
+        if idx >= self.size() {
+            bail!("Index {} out of range", idx);
+        }
+
+        // The sparse index has the block and offset in it of every span's middle value,
+        // from which the blocks are walked to `idx`
+        let k = idx / self.span;
+        let entry = read_at(file, self.sparse_index + k * 6, 6)?;
+        let mut block = u32::from_le_bytes([entry[0], entry[1], entry[2], entry[3]]) as u64;
+        let mut offset = u16::from_le_bytes([entry[4], entry[5]]) as i64;
+        offset += (idx % self.span) as i64 - (self.span / 2) as i64;
+
+        let block_length = |block: u64| -> anyhow::Result<i64> {
+            if block >= self.block_lengths_size {
+                bail!("Corrupt sparse index");
+            }
+            let bytes = read_at(file, self.block_lengths + block * 2, 2)?;
+            Ok(u16::from_le_bytes([bytes[0], bytes[1]]) as i64)
+        };
+        while offset < 0 {
+            if block == 0 {
+                bail!("Corrupt sparse index");
+            }
+            block -= 1;
+            offset += block_length(block)? + 1;
+        }
+        loop {
+            let length = block_length(block)?;
+            if offset <= length {
+                break;
+            }
+            offset -= length + 1;
+            block += 1;
+        }
+        if block >= self.num_blocks {
+            bail!("Corrupt sparse index");
+        }
+
+        let bytes = read_at(
+            file,
+            self.data + block * self.block_size,
+            self.block_size as usize,
+        )?;
+        let word = |at: usize| match bytes.get(at..at + 4) {
+            Some(word) => u32::from_be_bytes([word[0], word[1], word[2], word[3]]) as u64,
+            None => 0,
+        };
+        let mut buf64 = word(0) << 32 | word(4);
+        let mut next = 8;
+        let mut buf_size = 64;
+        let mut symbol = loop {
+            let mut len = 0;
+            while buf64 < self.base64[len] {
+                len += 1;
+            }
+            let bits = len as u32 + self.min_sym_len as u32;
+            let symbol = ((buf64 - self.base64[len])
+                .checked_shr(64 - bits)
+                .unwrap_or(0)
+                + self.lowest_sym[len] as u64) as usize;
+            let Some(&symlen) = self.symlen.get(symbol) else {
+                bail!("Corrupt block {}", block);
+            };
+            if offset < symlen as i64 + 1 {
+                break symbol;
+            }
+            offset -= symlen as i64 + 1;
+            buf64 = buf64.checked_shl(bits).unwrap_or(0);
+            buf_size -= bits as i32;
+            if buf_size <= 32 {
+                buf_size += 32;
+                buf64 |= word(next) << (64 - buf_size);
+                next += 4;
+            }
+        };
+
+        // Down the pairs the symbol expands to, to the single value at the offset
+        while self.symlen[symbol] != 0 {
+            let node = self.btree[symbol];
+            let left_len = self.symlen[left(node)] as i64 + 1;
+            if offset < left_len {
+                symbol = left(node);
+            } else {
+                offset -= left_len;
+                symbol = right(node);
+            }
+        }
+        Ok(left(self.btree[symbol]) as u16)
+    }
+}
+
+// Index of a position in a subtable, from its squares in the subtable's piece order.
+// Mirror images share an index, so the squares are first turned to a standard one.
+fn encode(squares: &mut [usize], lead_pawns: usize, pairs: &PairsData, material: &Material) -> u64 {
+    let indices = indices();
+    // The first piece on files a to d
+    if squares[0] % 8 > 3 {
+        for square in squares.iter_mut() {
+            *square ^= 7;
+        }
+    }
+
+    let mut idx;
+    if material.has_pawns() {
+        idx = indices.lead_pawn_idx[lead_pawns][squares[0]];
+        squares[1..lead_pawns].sort_by_key(|&square| indices.map_pawns[square]);
+        for (i, &square) in squares[..lead_pawns].iter().enumerate().skip(1) {
+            idx += indices.binomial[i][indices.map_pawns[square]];
+        }
+    } else {
+        // On ranks 1 to 4, and the first leading piece off the a1-h8 diagonal below it
+        if squares[0] / 8 > 3 {
+            for square in squares.iter_mut() {
+                *square ^= 56;
+            }
+        }
+        for i in 0..pairs.group_len[0] {
+            match off_diagonal(squares[i]) {
+                0 => continue,
+                off if off > 0 => {
+                    for square in &mut squares[i..] {
+                        *square = flip_diagonal(*square);
+                    }
+                }
+                _ => {}
+            }
+            break;
+        }
+
+        idx = match material.has_unique_pieces() {
+            true => {
+                let rank = |square: usize| (square / 8) as u64;
+                let [first, second, third] = [squares[0], squares[1], squares[2]];
+                let adjust1 = (second > first) as usize;
+                let adjust2 = (third > first) as usize + (third > second) as usize;
+                if off_diagonal(first) != 0 {
+                    ((indices.map_a1d1d4[first] * 63 + second - adjust1) * 62 + third - adjust2)
+                        as u64
+                } else if off_diagonal(second) != 0 {
+                    (6 * 63 + rank(first) * 28 + indices.map_b1h1h7[second] as u64) * 62
+                        + (third - adjust2) as u64
+                } else if off_diagonal(third) != 0 {
+                    6 * 63 * 62
+                        + 4 * 28 * 62
+                        + rank(first) * 7 * 28
+                        + (rank(second) - adjust1 as u64) * 28
+                        + indices.map_b1h1h7[third] as u64
+                } else {
+                    6 * 63 * 62
+                        + 4 * 28 * 62
+                        + 4 * 7 * 28
+                        + rank(first) * 7 * 6
+                        + (rank(second) - adjust1 as u64) * 6
+                        + (rank(third) - adjust2 as u64)
+                }
+            }
+            false => indices.map_kk[indices.map_a1d1d4[squares[0]]][squares[1]],
+        };
+    }
+    idx *= pairs.group_idx[0];
+
+    // Every other group among the squares the groups before it left, pawns off the back ranks
+    let mut start = pairs.group_len[0];
+    let mut remaining_pawns = material.has_pawns() && material.pawn_counts()[1] > 0;
+    for (&len, &factor) in pairs.group_len.iter().zip(&pairs.group_idx).skip(1) {
+        squares[start..start + len].sort_unstable();
+        let mut n = 0;
+        for i in 0..len {
+            let square = squares[start + i];
+            let adjust = squares[..start]
+                .iter()
+                .filter(|&&other| square > other)
+                .count();
+            n += indices.binomial[i + 1][square - adjust - 8 * remaining_pawns as usize];
+        }
+        remaining_pawns = false;
+        idx += n * factor;
+        start += len;
+    }
+    idx
+}
+
+// An opened table file
+struct TableData {
+    material: Material,
+    kind: TableKind,
+    file: Mutex<File>,
+    subtables: Vec<Vec<PairsData>>, // By leading pawn file, then side to move for WDL
+    map: Vec<u8>,                   // DTZ value maps
+}
+
+impl TableData {
+    fn open(path: &Path, material: Material, kind: TableKind) -> anyhow::Result<TableData> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        if len % 64 != 16 {
+            bail!(
+                "{} is {} bytes, which no Syzygy table is",
+                path.display(),
+                len
+            );
+        }
+        let mut header = HeaderReader {
+            reader: BufReader::new(file),
+            offset: 0,
+        };
+        if header.bytes(4)? != kind.magic() {
+            bail!("{} is not a Syzygy {:?} table", path.display(), kind);
+        }
+        let flags = header.u8()?;
+        if (flags & HAS_PAWNS != 0) != material.has_pawns()
+            || (flags & SPLIT != 0) == material.is_symmetric()
+        {
+            bail!("{} is not the table its name says", path.display());
+        }
+
+        let sides = match kind == TableKind::Wdl && !material.is_symmetric() {
+            true => 2,
+            false => 1,
+        };
+        let files = match material.has_pawns() {
+            true => 4,
+            false => 1,
+        };
+        let both_pawns = material.has_pawns() && material.pawn_counts()[1] > 0;
+        let mut subtables = vec![];
+        for file in 0..files {
+            // Each side in a nibble
+            let first = header.u8()?;
+            let second = match both_pawns {
+                true => header.u8()?,
+                false => 0xff,
+            };
+            let orders = [[first & 0xf, second & 0xf], [first >> 4, second >> 4]];
+            let mut pieces = vec![vec![]; sides];
+            for _ in 0..material.count() {
+                let byte = header.u8()?;
+                for (side, pieces) in pieces.iter_mut().enumerate() {
+                    pieces.push(byte >> (side * 4) & 0xf);
+                }
+            }
+            subtables.push(
+                pieces
+                    .into_iter()
+                    .zip(orders)
+                    .map(|(pieces, order)| PairsData::new(&material, pieces, order, file))
+                    .collect::<Vec<_>>(),
+            );
+        }
+        header.align(2)?;
+        for pairs in subtables.iter_mut().flatten() {
+            pairs.read_sizes(&mut header)?;
+        }
+
+        let mut map = vec![];
+        if kind == TableKind::Dtz {
+            for pairs in subtables.iter_mut().map(|sides| &mut sides[0]) {
+                if pairs.flags & MAPPED == 0 {
+                    continue;
+                }
+                // Each map is its length and then its values
+                if pairs.flags & WIDE != 0 {
+                    map.extend(header.align(2)?);
+                    for map_idx in &mut pairs.map_idx {
+                        *map_idx = map.len() / 2 + 1;
+                        let len = header.u16()?;
+                        map.extend(len.to_le_bytes());
+                        map.extend(header.bytes(len as usize * 2)?);
+                    }
+                } else {
+                    for map_idx in &mut pairs.map_idx {
+                        *map_idx = map.len() + 1;
+                        let len = header.u8()?;
+                        map.push(len);
+                        map.extend(header.bytes(len as usize)?);
+                    }
+                }
+            }
+            header.align(2)?;
+        }
+
+        let mut offset = header.offset;
+        for pairs in subtables.iter_mut().flatten() {
+            pairs.sparse_index = offset;
+            offset += pairs.sparse_index_size * 6;
+        }
+        for pairs in subtables.iter_mut().flatten() {
+            pairs.block_lengths = offset;
+            offset += pairs.block_lengths_size * 2;
+        }
+        // Blocks start on 64 bytes, which single value tables with none can end before
+        for pairs in subtables
+            .iter_mut()
+            .flatten()
+            .filter(|pairs| pairs.num_blocks > 0)
+        {
+            offset = offset.next_multiple_of(64);
+            pairs.data = offset;
+            offset += pairs.num_blocks * pairs.block_size;
+        }
+        if offset > len {
+            bail!("{} is cut short", path.display());
+        }
+
+        Ok(TableData {
+            material,
+            kind,
+            file: Mutex::new(header.reader.into_inner()),
+            subtables,
+            map,
+        })
+    }
+
+    /// The stored value for `position` and the subtable it is in, or `None` if this is a DTZ
+    /// table for the other side to move
+    fn probe(
+        &self,
+        position: &ChessPosition,
+        black_stronger: bool,
+    ) -> anyhow::Result<Option<(u16, &PairsData)>> {
+        let indices = indices();
+        let material = &self.material;
+        // Tables have white as the first side in their name, and symmetric ones white to move,
+        // so anything else is looked up with the colors swapped and the board turned over
+        let black_to_move = position.side_to_move() == ChessPlayer::Black;
+        let flip = black_stronger || (material.is_symmetric() && black_to_move);
+        let (flip_color, flip_squares) = match flip {
+            true => (8, 56),
+            false => (0, 0),
+        };
+        let side = (flip != black_to_move) as usize;
+        let code = |piece: Piece| piece_code(piece) ^ flip_color;
+
+        let mut squares = vec![];
+        let mut pieces = vec![];
+        // Pawn tables start with the leading side's pawns, split by the file of the one
+        // nearest the edge
+        let lead = material.has_pawns().then(|| self.subtables[0][0].pieces[0]);
+        let mut file = 0;
+        if let Some(lead) = lead {
+            for (square, piece) in position.pieces() {
+                if code(piece) == lead {
+                    squares.push(square.index() ^ flip_squares);
+                    pieces.push(lead);
+                }
+            }
+            let leader = (0..squares.len())
+                .max_by_key(|&i| indices.map_pawns[squares[i]])
+                .ok_or(anyhow!("Position has none of the table's leading pawns"))?;
+            squares.swap(0, leader);
+            file = edge_distance(squares[0] % 8);
+        }
+        let lead_pawns = squares.len();
+
+        let sides = &self.subtables[file];
+        // Symmetric pawnless DTZ tables do for either side
+        let one_side = material.has_pawns() || !material.is_symmetric();
+        if self.kind == TableKind::Dtz && one_side && (sides[0].flags & STM) as usize != side {
+            return Ok(None);
+        }
+        for (square, piece) in position.pieces() {
+            if Some(code(piece)) != lead {
+                squares.push(square.index() ^ flip_squares);
+                pieces.push(code(piece));
+            }
+        }
+        let pairs = match self.kind {
+            TableKind::Wdl => &sides[side],
+            TableKind::Dtz => &sides[0],
+        };
+        if squares.len() != pairs.pieces.len() {
+            bail!("Position is not {}", material);
+        }
+
+        // Into the subtable's order
+        for i in lead_pawns..squares.len() - 1 {
+            if let Some(j) = (i + 1..squares.len()).find(|&j| pieces[j] == pairs.pieces[i]) {
+                pieces.swap(i, j);
+                squares.swap(i, j);
+            }
+        }
+        let idx = encode(&mut squares, lead_pawns, pairs, material);
+        Ok(Some((pairs.decompress(&self.file, idx)?, pairs)))
+    }
+
+    /// A stored DTZ value as plies, the result of the position being `wdl`
+    fn map_dtz(&self, pairs: &PairsData, value: u16, wdl: Wdl) -> anyhow::Result<i32> {
+        let mut value = value as usize;
+        if pairs.flags & MAPPED != 0 {
+            let map_idx = pairs.map_idx[match wdl {
+                Wdl::Win | Wdl::Draw => 0,
+                Wdl::Loss => 1,
+                Wdl::CursedWin => 2,
+                Wdl::BlessedLoss => 3,
+            }];
+            value = match pairs.flags & WIDE != 0 {
+                true => self
+                    .map
+                    .get(2 * (map_idx + value)..2 * (map_idx + value) + 2)
+                    .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]) as usize),
+                false => self.map.get(map_idx + value).map(|&byte| byte as usize),
+            }
+            .ok_or(anyhow!("Corrupt DTZ map"))?;
+        }
+        let plies = match wdl {
+            Wdl::Win => pairs.flags & WIN_PLIES != 0,
+            Wdl::Loss => pairs.flags & LOSS_PLIES != 0,
+            _ => false,
+        };
+        let value = value as i32;
+        Ok(match plies {
+            true => value + 1,
+            false => value * 2 + 1,
+        })
+    }
+}
+
+// A table found in the directory, opened the first time it is probed
+struct Table {
+    path: PathBuf,
+    material: Material,
+    kind: TableKind,
+    data: OnceLock<Result<TableData, String>>,
+}
+
+impl Table {
+    fn data(&self) -> anyhow::Result<&TableData> {
+        self.data
+            .get_or_init(|| {
+                TableData::open(&self.path, self.material, self.kind).map_err(|e| e.to_string())
+            })
+            .as_ref()
+            .map_err(|e| anyhow!("Failed to open {}: {}", self.path.display(), e))
+    }
+}
+
+/// Syzygy endgame tablebases: perfect results (WDL) and distances to the next capture or
+/// pawn move (DTZ) for every standard chess position with few enough pieces and no castling
+pub struct Tablebase {
+    wdl: HashMap<Material, Table>,
+    dtz: HashMap<Material, Table>,
+    max_pieces: usize,
+}
+
+impl Tablebase {
+    /// Every table in the directory `dir`, e.g. KRvK.rtbw and KRvK.rtbz
+    pub fn open(dir: impl AsRef<Path>) -> anyhow::Result<Tablebase> {
+        let dir = dir.as_ref();
+        let entries = fs::read_dir(dir)
+            .map_err(|e| anyhow!("Failed to read tablebases in {}: {}", dir.display(), e))?;
+        let mut tablebase = Tablebase {
+            wdl: HashMap::new(),
+            dtz: HashMap::new(),
+            max_pieces: 0,
+        };
+        for entry in entries {
+            let path = entry?.path();
+            let name = path.file_stem().and_then(|name| name.to_str());
+            let Some(material) = name.and_then(Material::parse) else {
+                continue;
+            };
+            let (kind, tables) = match path.extension().and_then(|ext| ext.to_str()) {
+                Some("rtbw") => (TableKind::Wdl, &mut tablebase.wdl),
+                Some("rtbz") => (TableKind::Dtz, &mut tablebase.dtz),
+                _ => continue,
+            };
+            if kind == TableKind::Wdl {
+                tablebase.max_pieces = tablebase.max_pieces.max(material.count());
+            }
+            let data = OnceLock::new();
+            tables.insert(
+                material,
+                Table {
+                    path,
+                    material,
+                    kind,
+                    data,
+                },
+            );
+        }
+        if tablebase.wdl.is_empty() {
+            bail!("No Syzygy tables in {}", dir.display());
+        }
+        Ok(tablebase)
+    }
+
+    /// Pieces, kings included, in the biggest table
+    pub fn max_pieces(&self) -> usize {
+        self.max_pieces
+    }
+
+    /// Whether `position` can be probed, which captures leading to missing tables can still fail
+    pub fn covers(&self, position: &ChessPosition) -> bool {
+        let material = Material::of(position);
+        position.rules() == Rules::Standard
+            && position.castling().is_empty()
+            && (material.count() == 2 || Self::find(&self.wdl, material).is_some())
+    }
+
+    // The table for `material`, and whether it is for black as the first side
+    fn find(tables: &HashMap<Material, Table>, material: Material) -> Option<(&Table, bool)> {
+        match tables.get(&material) {
+            Some(table) => Some((table, false)),
+            None => tables.get(&material.flipped()).map(|table| (table, true)),
+        }
+    }
+
+    fn check(&self, position: &ChessPosition) -> anyhow::Result<()> {
+        if position.rules() != Rules::Standard {
+            bail!("Syzygy tables are only for standard chess");
+        }
+        if !position.castling().is_empty() {
+            bail!("Syzygy tables have no positions with castling rights");
+        }
+        if Material::of(position).count() > self.max_pieces {
+            bail!("The tables only go up to {} pieces", self.max_pieces);
+        }
+        Ok(())
+    }
+
+    /// The result for the side to move with best play, counting the fifty-move rule
+    pub fn probe_wdl(&self, position: &ChessPosition) -> anyhow::Result<Wdl> {
+        self.check(position)?;
+        Ok(self.search(position, false)?.0)
+    }
+
+    /// Whether `position` is drawn with best play, the fifty-move rule counting the plies its
+    /// halfmove clock says have already gone by
+    pub fn is_drawn(&self, position: &ChessPosition) -> anyhow::Result<bool> {
+        if self.probe_wdl(position)?.is_draw() {
+            return Ok(true);
+        }
+        if position.halfmove_clock() == 0 {
+            return Ok(false);
+        }
+        // The ply before the capture or pawn move has to come before the clock reaches 100,
+        // and DTZ may be one too many
+        let dtz = self.probe_dtz(position)?;
+        Ok(position.halfmove_clock() as i32 + dtz.abs() - 2 >= 100)
+    }
+
+    /// Plies to the next capture or pawn move on the way to the result: positive when winning,
+    /// negative when losing and 0 for draws. It can be one too many, but never too few.
+    pub fn probe_dtz(&self, position: &ChessPosition) -> anyhow::Result<i32> {
+        self.check(position)?;
+        self.dtz(position)
+    }
+
+    /// The legal move keeping the best result, winning by the fewest plies to the next capture
+    /// or pawn move and losing by the most, or `None` if there are no moves
+    pub fn best_move(&self, position: &ChessPosition) -> anyhow::Result<Option<(ChessMove, Wdl)>> {
+        self.check(position)?;
+        let mut best: Option<(ChessMove, Wdl, i32)> = None;
+        for game_move in position.legal_moves() {
+            let mut next = position.clone();
+            next.play_unchecked(&game_move);
+            let wdl = -self.search(&next, false)?.0;
+            let dtz = if next.is_check() && next.legal_moves().is_empty() {
+                1 // Mate is as short as it gets
+            } else if is_zeroing(position, &game_move) {
+                dtz_before_zeroing(wdl)
+            } else {
+                let dtz = -self.dtz(&next)?;
+                dtz + dtz.signum()
+            };
+            if best.is_none_or(|(_, best_wdl, best_dtz)| (wdl, -dtz) > (best_wdl, -best_dtz)) {
+                best = Some((game_move, wdl, dtz));
+            }
+        }
+        Ok(best.map(|(game_move, wdl, _)| (game_move, wdl)))
+    }
+
+    fn probe_wdl_table(&self, position: &ChessPosition) -> anyhow::Result<Wdl> {
+        let material = Material::of(position);
+        if material.count() == 2 {
+            return Ok(Wdl::Draw);
+        }
+        let (table, black_stronger) = Self::find(&self.wdl, material)
+            .ok_or(anyhow!("No Syzygy WDL table for {}", material))?;
+        match table.data()?.probe(position, black_stronger)? {
+            Some((value, _)) => Wdl::from_stored(value),
+            None => unreachable!("WDL tables have both sides to move"),
+        }
+    }
+
+    // `None` if the table is for the other side to move
+    fn probe_dtz_table(&self, position: &ChessPosition, wdl: Wdl) -> anyhow::Result<Option<i32>> {
+        let material = Material::of(position);
+        let (table, black_stronger) = Self::find(&self.dtz, material)
+            .ok_or(anyhow!("No Syzygy DTZ table for {}", material))?;
+        let data = table.data()?;
+        match data.probe(position, black_stronger)? {
+            Some((value, pairs)) => data.map_dtz(pairs, value, wdl).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    // Tables may store anything where a capture (or for DTZ, a pawn move) is best, so those
+    // are searched first. Also returns whether such a move is best.
+    fn search(&self, position: &ChessPosition, pawn_moves: bool) -> anyhow::Result<(Wdl, bool)> {
+        let moves = position.legal_moves();
+        let mut best = Wdl::Loss;
+        let mut searched = 0;
+        for game_move in &moves {
+            let searching = match pawn_moves {
+                true => is_zeroing(position, game_move),
+                false => is_capture(position, game_move),
+            };
+            if !searching {
+                continue;
+            }
+            searched += 1;
+            let mut next = position.clone();
+            next.play_unchecked(game_move);
+            let value = -self.search(&next, false)?.0;
+            if value > best {
+                best = value;
+                if value == Wdl::Win {
+                    return Ok((value, true));
+                }
+            }
+        }
+
+        // Tables know nothing of en passant, so once every move is searched they aren't needed
+        let all_searched = searched > 0 && searched == moves.len();
+        let value = match all_searched {
+            true => best,
+            false => self.probe_wdl_table(position)?,
+        };
+        Ok(match best >= value {
+            true => (best, best > Wdl::Draw || all_searched),
+            false => (value, false),
+        })
+    }
+
+    fn dtz(&self, position: &ChessPosition) -> anyhow::Result<i32> {
+        let (wdl, zeroing_best) = self.search(position, true)?;
+        if wdl == Wdl::Draw {
+            return Ok(0);
+        }
+        if zeroing_best {
+            return Ok(dtz_before_zeroing(wdl));
+        }
+        if let Some(dtz) = self.probe_dtz_table(position, wdl)? {
+            let cursed = matches!(wdl, Wdl::CursedWin | Wdl::BlessedLoss);
+            return Ok((dtz + 100 * cursed as i32) * wdl.signum());
+        }
+
+        // The table is for the other side to move, so this is one more than its best reply
+        let mut best: Option<i32> = None;
+        for game_move in position.legal_moves() {
+            let zeroing = is_zeroing(position, &game_move);
+            let mut next = position.clone();
+            next.play_unchecked(&game_move);
+            let mut dtz = match zeroing {
+                true => -dtz_before_zeroing(self.search(&next, false)?.0),
+                false => -self.dtz(&next)?,
+            };
+            if dtz == 1 && next.is_check() && next.legal_moves().is_empty() {
+                best = Some(1);
+            }
+            if !zeroing {
+                dtz += dtz.signum();
+            }
+            if dtz.signum() == wdl.signum() && best.is_none_or(|best| dtz < best) {
+                best = Some(dtz);
+            }
+        }
+        // With no moves the side to move is mated
+        Ok(best.unwrap_or(-1))
+    }
+}
+
+/// The tables in `SYZYGY_PATH`, if that is set, shared by every bot and the referee
+pub fn default_tablebase() -> Option<Arc<Tablebase>> {
+    static TABLEBASE: OnceLock<Option<Arc<Tablebase>>> = OnceLock::new();
+    TABLEBASE
+        .get_or_init(|| {
+            let path = env::var(SYZYGY_ENV_VAR).ok()?;
+            match Tablebase::open(&path) {
+                Ok(tablebase) => Some(Arc::new(tablebase)),
+                Err(e) => {
+                    eprintln!("{}, playing without tablebases", e);
+                    None
+                }
+            }
+        })
+        .clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chess::{ChessGame, ChessGameResult, ChessVariant, NativeEngine},
+        game::Game,
+        strategy::SearchBudget,
+    };
+
+    // A fresh directory for one test's tables
+    fn table_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!(
+            "rusty_moves_syzygy_{}_{}",
+            std::process::id(),
+            name
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // Padded to a length real tables have
+    fn write_table(dir: &Path, name: &str, mut bytes: Vec<u8>) {
+        while bytes.len() % 64 != 16 {
+            bytes.push(0);
+        }
+        fs::write(dir.join(name), bytes).unwrap();
+    }
+
+    // A pawnless table with the same value for every position, one per side to move
+    fn single_value_table(kind: TableKind, pieces: [[u8; 2]; 3], values: &[u8]) -> Vec<u8> {
+        let mut bytes = kind.magic().to_vec();
+        bytes.extend([SPLIT, 0x00]);
+        bytes.extend(pieces.map(|[side0, side1]| side1 << 4 | side0));
+        bytes.push(0);
+        for &value in values {
+            bytes.extend([SINGLE_VALUE, value]);
+        }
+        bytes
+    }
+
+    // KQvK, won for white to move and lost for black to move, which real tables only
+    // get wrong for stalemates, and the directory to remove afterwards
+    fn kqvk(name: &str) -> (Tablebase, PathBuf) {
+        let dir = table_dir(name);
+        let pieces = [[6, 14], [5, 6], [14, 5]];
+        write_table(
+            &dir,
+            "KQvK.rtbw",
+            single_value_table(TableKind::Wdl, pieces, &[4, 0]),
+        );
+        write_table(
+            &dir,
+            "KQvK.rtbz",
+            single_value_table(TableKind::Dtz, pieces, &[3]),
+        );
+        (Tablebase::open(&dir).unwrap(), dir)
+    }
+
+    #[test]
+    fn test_indices() {
+        let indices = indices();
+        let max = indices.map_kk.iter().flatten().max();
+        assert_eq!(max, Some(&461));
+        assert_eq!(indices.map_pawns[8], 47); // a2
+        assert_eq!(indices.map_pawns[15], 46); // h2
+        assert_eq!(indices.lead_pawns_size[1], [6; 4]);
+        assert_eq!(indices.binomial[3][10], 120);
+
+        let material = Material::parse("KQRvKP").unwrap();
+        assert_eq!(material.to_string(), "KQRvKP");
+        assert_eq!(material.flipped().to_string(), "KPvKQR");
+        assert_eq!(Material::parse("KRvR"), None);
+    }
+
+    #[test]
+    fn test_encoding() {
+        // Every placement of KRvK gets an index in the table, shared by its mirror images only
+        let material = Material::parse("KRvK").unwrap();
+        let pairs = PairsData::new(&material, vec![6, 4, 14], [0, 15], 0);
+        assert_eq!(pairs.size(), 31332);
+        let mirrors: [fn(usize) -> usize; 8] = [
+            |square| square,
+            |square| square ^ 7,
+            |square| square ^ 56,
+            |square| square ^ 63,
+            flip_diagonal,
+            |square| flip_diagonal(square) ^ 7,
+            |square| flip_diagonal(square) ^ 56,
+            |square| flip_diagonal(square) ^ 63,
+        ];
+        let mut by_placement = HashMap::new();
+        for placement in 0..64 * 64 * 64 {
+            let squares = [placement / 4096, placement / 64 % 64, placement % 64];
+            if squares[0] == squares[1] || squares[1] == squares[2] || squares[0] == squares[2] {
+                continue;
+            }
+            let canonical = mirrors
+                .iter()
+                .map(|mirror| squares.map(mirror))
+                .min()
+                .unwrap();
+            let idx = encode(&mut squares.clone(), 0, &pairs, &material);
+            assert!(idx < pairs.size(), "{:?} has index {}", squares, idx);
+            let placement = *by_placement.entry(idx).or_insert(canonical);
+            assert_eq!(
+                placement, canonical,
+                "{:?} has the index of {:?}",
+                squares, placement
+            );
+        }
+
+        // With pawns only the files are mirrored
+        let material = Material::parse("KPvK").unwrap();
+        for file in 0..4 {
+            let pairs = PairsData::new(&material, vec![1, 6, 14], [0, 15], file);
+            assert_eq!(pairs.size(), 6 * 63 * 62);
+            for pawn in [8 + file, 48 + (7 - file)] {
+                for king in (0..64).filter(|&king| king != pawn) {
+                    let other = (king + 9) % 64;
+                    if other == pawn {
+                        continue;
+                    }
+                    let squares = [pawn, king, other];
+                    let idx = encode(&mut squares.clone(), 1, &pairs, &material);
+                    assert!(idx < pairs.size());
+                    let mirrored =
+                        encode(&mut squares.map(|square| square ^ 7), 1, &pairs, &material);
+                    assert_eq!(idx, mirrored);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_decompress() {
+        // KRvK with white to move compressed: wins, draws and losses in a repeating pattern,
+        // 3 bit codes for them and for a pair of wins, 64 byte blocks and spans of 1024
+        let value = |idx: u64| match idx % 7 {
+            n @ 0..4 => n as u8,
+            _ => 4,
+        };
+        let size = 31332;
+        let mut blocks: Vec<Vec<u8>> = vec![];
+        let mut starts = vec![]; // First value in each block
+        let mut idx = 0;
+        while idx < size {
+            if blocks.last().is_none_or(|block| block.len() == 170) {
+                blocks.push(vec![]);
+                starts.push(idx);
+            }
+            let symbol = match value(idx) == 4 && idx + 1 < size && value(idx + 1) == 4 {
+                true => 5,
+                false => value(idx),
+            };
+            blocks.last_mut().unwrap().push(symbol);
+            idx += 1 + (symbol == 5) as u64;
+        }
+
+        let mut bytes = TableKind::Wdl.magic().to_vec();
+        bytes.extend([SPLIT, 0x00, 0xe6, 0x64, 0x4e, 0]);
+        bytes.extend([0, 6, 10, 0]);
+        bytes.extend((blocks.len() as u32).to_le_bytes());
+        bytes.extend([3, 3, 0, 0, 6, 0]);
+        for leaf in 0..5 {
+            bytes.extend([leaf, 0xf0, 0xff]);
+        }
+        bytes.extend([4, 0x40, 0x00]); // Two wins
+        bytes.extend([SINGLE_VALUE, 0]); // Lost for black to move
+        for k in 0..size.div_ceil(1024) {
+            let middle = k * 1024 + 512;
+            let block = starts.iter().rposition(|&start| start <= middle).unwrap();
+            bytes.extend((block as u32).to_le_bytes());
+            bytes.extend(((middle - starts[block]) as u16).to_le_bytes());
+        }
+        for block in 0..blocks.len() {
+            let end = starts.get(block + 1).copied().unwrap_or(size);
+            bytes.extend(((end - starts[block] - 1) as u16).to_le_bytes());
+        }
+        bytes.resize(bytes.len().next_multiple_of(64), 0);
+        for block in &blocks {
+            let mut data = [0u8; 64];
+            for (i, &symbol) in block.iter().enumerate() {
+                for bit in 0..3 {
+                    let at = i * 3 + bit;
+                    data[at / 8] |= (symbol >> (2 - bit) & 1) << (7 - at % 8);
+                }
+            }
+            bytes.extend(data);
+        }
+
+        let dir = table_dir("decompress");
+        write_table(&dir, "KRvK.rtbw", bytes);
+        let material = Material::parse("KRvK").unwrap();
+        let table = TableData::open(&dir.join("KRvK.rtbw"), material, TableKind::Wdl).unwrap();
+        let [white, black] = &table.subtables[0][..] else {
+            panic!("KRvK has a subtable for each side to move");
+        };
+        for idx in 0..size {
+            assert_eq!(
+                white.decompress(&table.file, idx).unwrap(),
+                value(idx) as u16
+            );
+        }
+        assert!(white.decompress(&table.file, size).is_err());
+        assert_eq!(black.decompress(&table.file, 0).unwrap(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_probe() {
+        let (tablebase, dir) = kqvk("probe");
+        assert_eq!(tablebase.max_pieces(), 3);
+        let position = |fen: &str| fen.parse::<ChessPosition>().unwrap();
+
+        // Black can take the queen, so it is only a draw
+        let hanging = position("8/8/8/4k3/3Q4/8/8/7K b - - 0 1");
+        assert!(tablebase.covers(&hanging));
+        assert_eq!(tablebase.probe_wdl(&hanging).unwrap(), Wdl::Draw);
+        assert_eq!(tablebase.probe_dtz(&hanging).unwrap(), 0);
+        let flipped = position("8/8/8/4K3/3q4/8/8/7k w - - 0 1");
+        assert_eq!(tablebase.probe_wdl(&flipped).unwrap(), Wdl::Draw);
+
+        // The table is for white to move, so black's DTZ is one more than after its move
+        let won = position("7k/8/8/8/8/8/8/1Q5K w - - 0 1");
+        assert_eq!(tablebase.probe_wdl(&won).unwrap(), Wdl::Win);
+        assert_eq!(tablebase.probe_dtz(&won).unwrap(), 7);
+        let lost = position("7k/8/8/8/8/8/8/1Q5K b - - 0 1");
+        assert_eq!(tablebase.probe_wdl(&lost).unwrap(), Wdl::Loss);
+        assert_eq!(tablebase.probe_dtz(&lost).unwrap(), -8);
+        let flipped = position("7K/8/8/8/8/8/8/1q5k b - - 0 1");
+        assert_eq!(tablebase.probe_wdl(&flipped).unwrap(), Wdl::Win);
+
+        // Missing tables and castling rights
+        let rook = position("7k/8/8/8/8/8/8/R6K w - - 0 1");
+        assert!(!tablebase.covers(&rook));
+        assert!(tablebase.probe_wdl(&rook).is_err());
+        let castling = position("4k3/8/8/8/8/8/8/4K2R w K - 0 1");
+        assert!(!tablebase.covers(&castling));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_is_drawn() {
+        let (tablebase, dir) = kqvk("is_drawn");
+        let drawn = |fen: &str| tablebase.is_drawn(&fen.parse().unwrap()).unwrap();
+        assert!(drawn("8/8/8/4k3/3Q4/8/8/7K b - - 0 1"));
+
+        // 7 plies to mate, maybe 6, so the win is only safe until the clock passes 94
+        assert!(!drawn("7k/8/8/8/8/8/8/1Q5K w - - 0 1"));
+        assert!(!drawn("7k/8/8/8/8/8/8/1Q5K w - - 94 60"));
+        assert!(drawn("7k/8/8/8/8/8/8/1Q5K w - - 95 60"));
+        assert!(!drawn("7k/8/8/8/8/8/8/1Q5K b - - 93 60"));
+        assert!(drawn("7k/8/8/8/8/8/8/1Q5K b - - 94 60"));
+
+        let game =
+            |fen: &str| ChessGame::from_position(ChessVariant::Standard, fen.parse().unwrap());
+        let referee = Some(Arc::new(tablebase));
+        let late = game("7k/8/8/8/8/8/8/1Q5K w - - 99 60");
+        assert_eq!(
+            late.adjudicate(&referee),
+            Some(ChessGameResult::TablebaseDraw)
+        );
+        assert_eq!(late.adjudicate(&None), None);
+        assert_eq!(
+            game("7k/8/8/8/8/8/8/1Q5K w - - 10 60").adjudicate(&referee),
+            None
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[ignore = "needs real Syzygy tables, run with SYZYGY_PATH set and --ignored"]
+    fn test_real_tables() {
+        // Known answers from real KQvK tables
+        let tablebase = default_tablebase()
+            .filter(|tablebase| tablebase.max_pieces() >= 3)
+            .expect("SYZYGY_PATH should have KQvK tables");
+        let position = |fen: &str| fen.parse::<ChessPosition>().unwrap();
+        let mate_in_one = position("k7/8/1K6/8/8/8/8/7Q w - - 0 1");
+        assert_eq!(tablebase.probe_wdl(&mate_in_one).unwrap(), Wdl::Win);
+        assert_eq!(tablebase.probe_dtz(&mate_in_one).unwrap(), 1);
+        let hanging = position("8/8/8/4k3/3Q4/8/8/7K b - - 0 1");
+        assert_eq!(tablebase.probe_wdl(&hanging).unwrap(), Wdl::Draw);
+        let stalemate = position("k7/2Q5/1K6/8/8/8/8/8 b - - 0 1");
+        assert_eq!(tablebase.probe_wdl(&stalemate).unwrap(), Wdl::Draw);
+        let lost = position("7k/8/8/8/8/8/8/1Q5K b - - 0 1");
+        assert_eq!(tablebase.probe_wdl(&lost).unwrap(), Wdl::Loss);
+    }
+
+    #[test]
+    fn test_best_move() {
+        // Keeps the queen out of the black king's reach
+        let (tablebase, dir) = kqvk("best_move");
+        let tablebase = Arc::new(tablebase);
+        let position: ChessPosition = "8/8/8/4k3/8/8/Q7/7K w - - 0 1".parse().unwrap();
+        let (game_move, wdl) = tablebase.best_move(&position).unwrap().unwrap();
+        assert_eq!(wdl, Wdl::Win);
+        let mut next = position.clone();
+        next.play(&game_move).unwrap();
+        assert_eq!(tablebase.probe_wdl(&next).unwrap(), Wdl::Loss);
+
+        let game = ChessGame::from_position(ChessVariant::Standard, position);
+        let mut engine =
+            NativeEngine::new(SearchBudget::nodes(1_000)).with_tablebase(tablebase.clone());
+        let (game_move, score) = engine.search(&game, SearchBudget::nodes(1_000)).unwrap();
+        assert!(score > 0);
+        let mut next = game.position().clone();
+        next.play(&game_move).unwrap();
+        assert_eq!(tablebase.probe_wdl(&next).unwrap(), Wdl::Loss);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,365 @@
+use std::{error, ffi::OsStr, fmt, io, process::Stdio, time::Duration};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+    process::{Child, ChildStdin, ChildStdout, Command},
+    time::timeout,
+};
+
+use super::UciMove;
+
+// How long the engine gets to answer anything but `go`, and extra time on top of `go movetime`
+pub const DEFAULT_ENGINE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+pub enum UciError {
+    Io(io::Error),
+    Timeout(String), // What we were waiting for
+    EngineExited(Option<i32>),
+    InvalidResponse(String),
+}
+
+impl fmt::Display for UciError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "Engine I/O failed: {}", e),
+            Self::Timeout(waiting_for) => write!(f, "Engine timed out waiting for {}", waiting_for),
+            Self::EngineExited(Some(code)) => write!(f, "Engine exited with code {}", code),
+            Self::EngineExited(None) => write!(f, "Engine exited"),
+            Self::InvalidResponse(line) => write!(f, "Unexpected engine output: {:?}", line),
+        }
+    }
+}
+
+impl error::Error for UciError {}
+
+impl From<io::Error> for UciError {
+    fn from(e: io::Error) -> Self {
+        UciError::Io(e)
+    }
+}
+
+/// How long the engine may think for
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GoLimit {
+    Depth(u32),
+    MoveTime(Duration),
+    Nodes(u64),
+    Infinite, // Until told to stop, which `UciEngine::go` does once its timeout is up
+}
+
+impl fmt::Display for GoLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GoLimit::Depth(depth) => write!(f, "depth {}", depth),
+            GoLimit::MoveTime(time) => write!(f, "movetime {}", time.as_millis()),
+            GoLimit::Nodes(nodes) => write!(f, "nodes {}", nodes),
+            GoLimit::Infinite => write!(f, "infinite"),
+        }
+    }
+}
+
+/// An evaluation from the side to move's point of view
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Score {
+    Centipawns(i32),
+    Mate(i32), // Moves until mate, negative when being mated
+}
+
+impl Score {
+    /// Comparable single number, with mates beyond any real evaluation
+    pub fn to_centipawns(self) -> i32 {
+        match self {
+            Score::Centipawns(cp) => cp,
+            Score::Mate(moves) if moves > 0 => 100_000 - moves,
+            Score::Mate(moves) => -100_000 - moves,
+        }
+    }
+}
+
+/// The latest `info` the engine gave for one of its MultiPV lines
+#[derive(Clone, Debug, PartialEq)]
+pub struct PvLine {
+    pub multipv: usize, // 1 for the best line
+    pub depth: u32,
+    pub score: Score,
+    pub pv: Vec<UciMove>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchResult {
+    pub best_move: UciMove,
+    pub ponder: Option<UciMove>,
+    pub lines: Vec<PvLine>, // Sorted by `multipv`
+}
+
+/// A UCI engine running as a child process, e.g. Stockfish
+pub struct UciEngine {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: Lines<BufReader<ChildStdout>>,
+    name: Option<String>,
+    options: Vec<String>,
+    timeout: Duration,
+}
+
+impl UciEngine {
+    /// Starts the engine and waits for it to be ready
+    pub async fn spawn<I, S>(program: impl AsRef<OsStr>, args: I) -> Result<UciEngine, UciError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        Self::spawn_with_timeout(program, args, DEFAULT_ENGINE_TIMEOUT).await
+    }
+
+    pub async fn spawn_with_timeout<I, S>(
+        program: impl AsRef<OsStr>,
+        args: I,
+        engine_timeout: Duration,
+    ) -> Result<UciEngine, UciError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()?;
+        let stdin = child.stdin.take().expect("Stdin is piped");
+        let stdout = BufReader::new(child.stdout.take().expect("Stdout is piped")).lines();
+
+        let mut engine = UciEngine {
+            child,
+            stdin,
+            stdout,
+            name: None,
+            options: vec![],
+            timeout: engine_timeout,
+        };
+
+        engine.send("uci").await?;
+        loop {
+            let line = engine.read_line("uciok", engine.timeout).await?;
+            if let Some(name) = line.strip_prefix("id name ") {
+                engine.name = Some(name.to_string());
+            } else if let Some(option) = line.strip_prefix("option name ") {
+                let name = option.split(" type ").next().unwrap_or(option);
+                engine.options.push(name.to_string());
+            } else if line == "uciok" {
+                break;
+            }
+        }
+        engine.sync().await?;
+        Ok(engine)
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Names of the options the engine says it has
+    pub fn options(&self) -> &[String] {
+        &self.options
+    }
+
+    pub fn has_option(&self, name: &str) -> bool {
+        self.options
+            .iter()
+            .any(|option| option.eq_ignore_ascii_case(name))
+    }
+
+    pub async fn set_option(&mut self, name: &str, value: &str) -> Result<(), UciError> {
+        self.send(&format!("setoption name {} value {}", name, value))
+            .await?;
+        self.sync().await
+    }
+
+    pub async fn new_game(&mut self) -> Result<(), UciError> {
+        self.send("ucinewgame").await?;
+        self.sync().await
+    }
+
+    /// Sets the position to search, as a start FEN and the moves played from it
+    pub async fn set_position(&mut self, fen: &str, moves: &[UciMove]) -> Result<(), UciError> {
+        let mut command = format!("position fen {}", fen);
+        if !moves.is_empty() {
+            command.push_str(" moves");
+        }
+        for game_move in moves {
+            command.push_str(&format!(" {}", game_move));
+        }
+        self.send(&command).await
+    }
+
+    /// Searches the current position, collecting the last `info` of every MultiPV line
+    ///
+    /// If the engine overruns, it is told to stop and given the usual timeout to answer.
+    pub async fn go(&mut self, limit: GoLimit) -> Result<SearchResult, UciError> {
+        self.send(&format!("go {}", limit)).await?;
+        let search_time = match limit {
+            GoLimit::MoveTime(time) => time + self.timeout,
+            _ => self.timeout,
+        };
+
+        let mut lines: Vec<PvLine> = vec![];
+        let mut stopped = false;
+        loop {
+            let line = match self.read_line("bestmove", search_time).await {
+                Err(UciError::Timeout(_)) if !stopped => {
+                    stopped = true;
+                    self.send("stop").await?;
+                    self.read_line("bestmove after stop", self.timeout).await
+                }
+                line => line,
+            };
+            let line = match line {
+                Err(UciError::Timeout(waiting_for)) => {
+                    // No use talking to it any more
+                    let _ = self.child.start_kill();
+                    return Err(UciError::Timeout(waiting_for));
+                }
+                line => line?,
+            };
+
+            if let Some(info) = line.strip_prefix("info ") {
+                if let Some(pv_line) = parse_info(info) {
+                    match lines.iter_mut().find(|old| old.multipv == pv_line.multipv) {
+                        Some(old) => *old = pv_line,
+                        None => lines.push(pv_line),
+                    }
+                }
+            } else if let Some(best) = line.strip_prefix("bestmove ") {
+                let mut tokens = best.split_whitespace();
+                let best_move = tokens
+                    .next()
+                    .and_then(|uci| uci.parse().ok())
+                    .ok_or_else(|| UciError::InvalidResponse(line.clone()))?;
+                let ponder = match (tokens.next(), tokens.next()) {
+                    (Some("ponder"), Some(uci)) => uci.parse().ok(),
+                    _ => None,
+                };
+                lines.sort_by_key(|pv_line| pv_line.multipv);
+                return Ok(SearchResult {
+                    best_move,
+                    ponder,
+                    lines,
+                });
+            }
+        }
+    }
+
+    /// Asks the engine to exit, killing it if it doesn't in time
+    pub async fn quit(mut self) -> Result<(), UciError> {
+        // It may already be gone, which is what we want anyway
+        let _ = self.send("quit").await;
+        if timeout(self.timeout, self.child.wait()).await.is_err() {
+            self.child.kill().await?;
+        }
+        Ok(())
+    }
+
+    async fn send(&mut self, command: &str) -> Result<(), UciError> {
+        let write = async {
+            self.stdin.write_all(command.as_bytes()).await?;
+            self.stdin.write_all(b"\n").await?;
+            self.stdin.flush().await
+        };
+        match write.await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Err(self.exited()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Waits for `isready` to be answered, so earlier commands are done with
+    async fn sync(&mut self) -> Result<(), UciError> {
+        self.send("isready").await?;
+        while self.read_line("readyok", self.timeout).await? != "readyok" {}
+        Ok(())
+    }
+
+    async fn read_line(&mut self, waiting_for: &str, limit: Duration) -> Result<String, UciError> {
+        match timeout(limit, self.stdout.next_line()).await {
+            Err(_) => Err(UciError::Timeout(waiting_for.to_string())),
+            Ok(Ok(Some(line))) => Ok(line.trim().to_string()),
+            Ok(Ok(None)) => Err(self.exited()),
+            Ok(Err(e)) => Err(e.into()),
+        }
+    }
+
+    fn exited(&mut self) -> UciError {
+        let code = self
+            .child
+            .try_wait()
+            .ok()
+            .flatten()
+            .and_then(|status| status.code());
+        UciError::EngineExited(code)
+    }
+}
+
+/// Reads the fields we use from an `info` line, skipping ones without a score and pv
+fn parse_info(info: &str) -> Option<PvLine> {
+    let mut tokens = info.split_whitespace();
+    let (mut multipv, mut depth, mut score, mut pv) = (1, 0, None, vec![]);
+    while let Some(token) = tokens.next() {
+        match token {
+            "depth" => depth = tokens.next()?.parse().ok()?,
+            "multipv" => multipv = tokens.next()?.parse().ok()?,
+            "score" => {
+                score = match (tokens.next()?, tokens.next()?.parse().ok()?) {
+                    ("cp", cp) => Some(Score::Centipawns(cp)),
+                    ("mate", moves) => Some(Score::Mate(moves)),
+                    _ => return None,
+                }
+            }
+            "pv" => {
+                pv = tokens
+                    .by_ref()
+                    .map(|uci| uci.parse())
+                    .collect::<Result<_, _>>()
+                    .ok()?
+            }
+            "string" => return None,
+            _ => {}
+        }
+    }
+    Some(PvLine {
+        multipv,
+        depth,
+        score: score?,
+        pv,
+    })
+    .filter(|pv_line| !pv_line.pv.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_info() {
+        let info = "depth 12 seldepth 18 multipv 2 score cp -35 upperbound nodes 4000 pv e7e5 g1f3";
+        assert_eq!(
+            parse_info(info),
+            Some(PvLine {
+                multipv: 2,
+                depth: 12,
+                score: Score::Centipawns(-35),
+                pv: vec!["e7e5".parse().unwrap(), "g1f3".parse().unwrap()],
+            })
+        );
+        assert_eq!(
+            parse_info("depth 3 score mate -2 pv h7h8q").map(|pv_line| pv_line.score),
+            Some(Score::Mate(-2))
+        );
+        assert_eq!(parse_info("depth 5 currmove e2e4 currmovenumber 1"), None);
+        assert_eq!(parse_info("string NNUE enabled"), None);
+
+        assert!(Score::Mate(3).to_centipawns() > Score::Centipawns(5000).to_centipawns());
+        assert!(Score::Mate(-3).to_centipawns() < Score::Mate(-5).to_centipawns());
+    }
+}
@@ -0,0 +1,258 @@
+use rand::{SeedableRng, rngs::StdRng, seq::IndexedRandom};
+use std::{env, sync::mpsc, thread, time::Duration};
+
+use super::{
+    ChessGame, ChessPlayer, DEFAULT_ENGINE_TIMEOUT, GoLimit, NativeEngine, Score, UciEngine,
+    UciError, UciMove,
+};
+use crate::{
+    game::Game,
+    strategy::{SearchBudget, Strategy},
+};
+
+// Overrides the engine program, which is otherwise `stockfish` on the PATH
+pub const ENGINE_ENV_VAR: &str = "UCI_ENGINE";
+
+#[derive(Clone, Debug)]
+pub struct UciPlayerConfig {
+    pub program: String,
+    pub args: Vec<String>,
+    pub top_n: usize,   // MultiPV lines asked for
+    pub window: i32,    // Centipawns behind the best move a move may be and still be picked
+    pub limit: GoLimit, // Used when no budget is given
+    pub timeout: Duration,
+    pub seed: Option<u64>,
+}
+
+impl Default for UciPlayerConfig {
+    fn default() -> Self {
+        UciPlayerConfig {
+            program: env::var(ENGINE_ENV_VAR).unwrap_or_else(|_| "stockfish".to_string()),
+            args: vec![],
+            top_n: 5,
+            window: 50,
+            limit: GoLimit::Depth(10),
+            timeout: DEFAULT_ENGINE_TIMEOUT,
+            seed: None,
+        }
+    }
+}
+
+/// The engine's top `top_n` moves for a game, best first, as their first move and score
+pub async fn get_best_moves(
+    engine: &mut UciEngine,
+    game: &ChessGame,
+    top_n: usize,
+    limit: GoLimit,
+) -> Result<Vec<(UciMove, Score)>, UciError> {
+    engine.set_option("MultiPV", &top_n.to_string()).await?;
+    if engine.has_option("UCI_Chess960") {
+        let chess960 = game.start().is_chess960();
+        engine
+            .set_option("UCI_Chess960", &chess960.to_string())
+            .await?;
+    }
+    if engine.has_option("UCI_Variant") {
+        let variant = game.start().rules().uci_variant();
+        engine.set_option("UCI_Variant", variant).await?;
+    }
+    let moves = game
+        .moves()
+        .iter()
+        .map(|game_move| game.start().uci(game_move));
+    engine
+        .set_position(&game.start().to_string(), &moves.collect::<Vec<_>>())
+        .await?;
+    let result = engine.go(limit).await?;
+    Ok(result
+        .lines
+        .into_iter()
+        .map(|line| (line.pv[0], line.score))
+        .collect())
+}
+
+/// Any of the moves scoring within `window` centipawns of the best one
+pub fn pick_within_window(
+    moves: &[(UciMove, Score)],
+    window: i32,
+    rng: &mut StdRng,
+) -> Option<UciMove> {
+    let best = moves.iter().map(|(_, score)| score.to_centipawns()).max()?;
+    let candidates = moves
+        .iter()
+        .filter(|(_, score)| score.to_centipawns() >= best.saturating_sub(window))
+        .collect::<Vec<_>>();
+    candidates.choose(rng).map(|&&(game_move, _)| game_move)
+}
+
+struct EngineRequest {
+    game: ChessGame,
+    limit: GoLimit,
+    new_game: bool,
+    reply: mpsc::Sender<Result<Vec<(UciMove, Score)>, UciError>>,
+}
+
+/// Plays a random one of the engine's best moves, for opponents of tunable strength
+///
+/// The engine runs on its own thread and is started on the first move, then restarted
+/// after any failure. The native engine plays instead whenever it can't answer.
+pub struct UciPlayer {
+    config: UciPlayerConfig,
+    fallback: NativeEngine,
+    rng: StdRng,
+    requests: mpsc::Sender<EngineRequest>,
+    new_game: bool,
+}
+
+impl UciPlayer {
+    pub fn new(config: UciPlayerConfig) -> UciPlayer {
+        let rng = match config.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_rng(&mut rand::rng()),
+        };
+        let (requests, receiver) = mpsc::channel();
+        let engine_config = config.clone();
+        thread::spawn(move || run_engine(engine_config, receiver));
+        UciPlayer {
+            config,
+            fallback: NativeEngine::new(SearchBudget::default()),
+            rng,
+            requests,
+            new_game: true,
+        }
+    }
+
+    /// Asks the engine for its top moves, waiting for the answer
+    pub fn best_moves(
+        &mut self,
+        game: &ChessGame,
+        limit: GoLimit,
+    ) -> Result<Vec<(UciMove, Score)>, UciError> {
+        let (reply, receiver) = mpsc::channel();
+        let request = EngineRequest {
+            game: game.clone(),
+            limit,
+            new_game: self.new_game,
+            reply,
+        };
+        self.new_game = false;
+        let gone = || UciError::EngineExited(None);
+        self.requests.send(request).map_err(|_| gone())?;
+        receiver.recv().map_err(|_| gone())?
+    }
+}
+
+impl Strategy<ChessGame> for UciPlayer {
+    fn choose_move(
+        &mut self,
+        game: &ChessGame,
+        side: ChessPlayer,
+        budget: Option<SearchBudget>,
+    ) -> UciMove {
+        let limit = match budget {
+            Some(SearchBudget {
+                max_time: Some(time),
+                ..
+            }) => GoLimit::MoveTime(time),
+            Some(SearchBudget {
+                max_nodes: Some(nodes),
+                ..
+            }) => GoLimit::Nodes(nodes),
+            _ => self.config.limit,
+        };
+
+        let legal_moves = game.legal_moves();
+        let chosen = self.best_moves(game, limit).map(|moves| {
+            // Never trust the engine to only give legal moves
+            let moves = moves
+                .into_iter()
+                .filter(|(game_move, _)| legal_moves.contains(game_move))
+                .collect::<Vec<_>>();
+            pick_within_window(&moves, self.config.window, &mut self.rng)
+        });
+        match chosen {
+            Ok(Some(game_move)) => game_move,
+            Ok(None) => {
+                eprintln!("Engine gave no legal moves, using the native engine");
+                self.fallback.choose_move(game, side, budget)
+            }
+            Err(e) => {
+                eprintln!("{}, using the native engine", e);
+                self.fallback.choose_move(game, side, budget)
+            }
+        }
+    }
+
+    fn new_game(&mut self) {
+        self.new_game = true;
+        self.fallback.new_game();
+    }
+}
+
+// Owns the engine process for a `UciPlayer`, until the player is dropped
+fn run_engine(config: UciPlayerConfig, requests: mpsc::Receiver<EngineRequest>) {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("Failed to start engine runtime");
+
+    runtime.block_on(async {
+        let mut engine: Option<UciEngine> = None;
+        while let Ok(request) = requests.recv() {
+            let result = async {
+                let engine = match &mut engine {
+                    Some(engine) => engine,
+                    None => engine.insert(
+                        UciEngine::spawn_with_timeout(
+                            &config.program,
+                            &config.args,
+                            config.timeout,
+                        )
+                        .await?,
+                    ),
+                };
+                if request.new_game {
+                    engine.new_game().await?;
+                }
+                get_best_moves(engine, &request.game, config.top_n, request.limit).await
+            }
+            .await;
+            if result.is_err() {
+                // Start again from scratch next time
+                engine = None;
+            }
+            let _ = request.reply.send(result);
+        }
+        if let Some(engine) = engine {
+            let _ = engine.quit().await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pick_within_window() {
+        let moves = [
+            ("e2e4".parse().unwrap(), Score::Centipawns(40)),
+            ("d2d4".parse().unwrap(), Score::Centipawns(25)),
+            ("g1f3".parse().unwrap(), Score::Centipawns(-20)),
+        ];
+        let mut rng = StdRng::seed_from_u64(1);
+        let picks = (0..50)
+            .map(|_| pick_within_window(&moves, 30, &mut rng).unwrap())
+            .collect::<Vec<_>>();
+        assert!(picks.contains(&moves[0].0) && picks.contains(&moves[1].0));
+        assert!(!picks.contains(&moves[2].0));
+
+        // A forced mate is always preferred
+        let mate = [
+            ("e2e4".parse().unwrap(), Score::Centipawns(900)),
+            ("h5f7".parse().unwrap(), Score::Mate(1)),
+        ];
+        assert_eq!(pick_within_window(&mate, 500, &mut rng), Some(mate[1].0));
+        assert_eq!(pick_within_window(&[], 500, &mut rng), None);
+    }
+}
@@ -0,0 +1,263 @@
+use super::{ChessGameResult, ChessMove, ChessPlayer, ChessPosition, PieceKind, Square};
+
+/// How pieces move and games end, on top of standard chess
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Rules {
+    #[default]
+    Standard,
+    KingOfTheHill, // Getting the king to the centre wins
+    ThreeCheck,    // Giving a third check wins
+    Atomic,        // Captures explode everything around them but pawns
+    Crazyhouse,    // Captured pieces can be dropped back on the board
+}
+
+impl Rules {
+    /// The name multi-variant engines take in their `UCI_Variant` option
+    pub fn uci_variant(&self) -> &'static str {
+        match self {
+            Rules::Standard => "chess",
+            Rules::KingOfTheHill => "kingofthehill",
+            Rules::ThreeCheck => "3check",
+            Rules::Atomic => "atomic",
+            Rules::Crazyhouse => "crazyhouse",
+        }
+    }
+}
+
+impl ChessPosition {
+    pub fn rules(&self) -> Rules {
+        self.rules
+    }
+
+    pub(crate) fn set_rules(&mut self, rules: Rules) {
+        self.rules = rules;
+    }
+
+    /// Pieces of `kind` which `player` has to drop in crazyhouse
+    pub fn pocket(&self, player: ChessPlayer, kind: PieceKind) -> u8 {
+        match kind {
+            PieceKind::King => 0,
+            _ => self.pockets[player as usize][kind as usize],
+        }
+    }
+
+    /// Checks `player` still has to give to win three-check
+    pub fn remaining_checks(&self, player: ChessPlayer) -> u8 {
+        self.remaining_checks[player as usize]
+    }
+
+    // Crazyhouse pieces which were pawns, and go back to being pawns once captured
+    pub(crate) fn is_promoted(&self, square: Square) -> bool {
+        self.promoted & (1 << square.index()) != 0
+    }
+
+    pub(crate) fn set_promoted(&mut self, square: Square, promoted: bool) {
+        match promoted {
+            true => self.promoted |= 1 << square.index(),
+            false => self.promoted &= !(1 << square.index()),
+        }
+    }
+
+    /// How the game has ended by the variant's own rules, whoever is to move
+    pub fn variant_outcome(&self) -> Option<ChessGameResult> {
+        let players = [ChessPlayer::White, ChessPlayer::Black];
+        match self.rules {
+            Rules::Standard | Rules::Crazyhouse => None,
+            Rules::KingOfTheHill => {
+                // The four centre squares, d4, e4, d5 and e5
+                let on_hill =
+                    |king: Square| (3..=4).contains(&king.file()) && (3..=4).contains(&king.rank());
+                players
+                    .into_iter()
+                    .find(|&player| self.king_square(player).is_some_and(on_hill))
+                    .map(ChessGameResult::KingOfTheHill)
+            }
+            Rules::ThreeCheck => players
+                .into_iter()
+                .find(|&player| self.remaining_checks(player) == 0)
+                .map(ChessGameResult::ThreeChecks),
+            Rules::Atomic => players
+                .into_iter()
+                .find(|&player| self.king_square(player.opponent()).is_none())
+                .map(ChessGameResult::KingExploded),
+        }
+    }
+
+    // Crazyhouse drops onto any empty square, but pawns not on the first or last rank
+    pub(super) fn drop_moves(&self, moves: &mut Vec<ChessMove>) {
+        if self.rules != Rules::Crazyhouse {
+            return;
+        }
+        let us = self.side_to_move;
+        for kind in PieceKind::ALL {
+            if self.pocket(us, kind) == 0 {
+                continue;
+            }
+            for to in Square::all().filter(|&to| self.piece_at(to).is_none()) {
+                if kind != PieceKind::Pawn || to.rank() % 7 != 0 {
+                    moves.push(ChessMove::Drop { kind, to });
+                }
+            }
+        }
+    }
+
+    // An atomic capture on `at`, blowing up the capturer and every piece next to it but pawns
+    pub(super) fn explode(&mut self, at: Square) {
+        let blast = Square::all().filter(|square| {
+            square.file().abs_diff(at.file()) <= 1 && square.rank().abs_diff(at.rank()) <= 1
+        });
+        for square in blast.collect::<Vec<_>>() {
+            let Some(piece) = self.piece_at(square) else {
+                continue;
+            };
+            if piece.kind == PieceKind::Pawn && square != at {
+                continue;
+            }
+            self.set_piece(square, None);
+            self.castling.remove_rook(square);
+            if piece.kind == PieceKind::King {
+                self.castling.clear(piece.color);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess::perft;
+
+    fn assert_perft(fen: &str, rules: Rules, expected: &[u64]) {
+        let position = ChessPosition::from_fen(fen, rules).unwrap();
+        for (depth, &nodes) in (1..).zip(expected) {
+            assert_eq!(perft(&position, depth), nodes, "{} at depth {}", fen, depth);
+        }
+    }
+
+    // Counts from shakmaty's variant perft suites
+    #[test]
+    fn test_perft() {
+        let tests = [
+            (
+                "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 1+1",
+                Rules::ThreeCheck,
+                &[48, 2039, 97848][..],
+            ),
+            (
+                "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 1+1",
+                Rules::ThreeCheck,
+                &[26, 562, 13410],
+            ),
+            (
+                "rn2kb1r/1pp1p2p/p2q1pp1/3P4/2P3b1/4PN2/PP3PPP/R2QKB1R b KQkq -",
+                Rules::Atomic,
+                &[40, 1238, 45237],
+            ),
+            (
+                "rn1qkb1r/p5pp/2p5/3p4/N3P3/5P2/PPP4P/R1BQK3 w Qkq -",
+                Rules::Atomic,
+                &[28, 833, 23353],
+            ),
+            (
+                "8/8/8/8/8/8/2k5/rR4KR w KQ -",
+                Rules::Atomic,
+                &[18, 180, 4364],
+            ),
+            (
+                "r3k1rR/5K2/8/8/8/8/8/8 b kq -",
+                Rules::Atomic,
+                &[25, 282, 6753],
+            ),
+            (
+                "Rr2k1rR/3K4/3p4/8/8/8/7P/8 w kq -",
+                Rules::Atomic,
+                &[21, 465, 10631],
+            ),
+            (
+                "2k5/8/8/8/8/8/8/4K3[QRBNPqrbnp] w - -",
+                Rules::Crazyhouse,
+                &[301, 75353],
+            ),
+            (
+                "2k5/8/8/8/8/8/8/4K3[Qn] w - -",
+                Rules::Crazyhouse,
+                &[67, 3083, 88634],
+            ),
+            (
+                "r1bqk2r/pppp1ppp/2n1p3/4P3/1b1Pn3/2NB1N2/PPP2PPP/R1BQK2R[] b KQkq -",
+                Rules::Crazyhouse,
+                &[42, 1347, 58057],
+            ),
+            (
+                "4k3/1Q~6/8/8/4b3/8/Kpp5/8/ b - - 0 1",
+                Rules::Crazyhouse,
+                &[20, 360, 5445],
+            ),
+        ];
+        for (fen, rules, expected) in tests {
+            assert_perft(fen, rules, expected);
+        }
+    }
+
+    #[test]
+    fn test_outcomes() {
+        let position =
+            ChessPosition::from_fen("8/8/8/8/3K4/8/8/7k b - - 0 1", Rules::KingOfTheHill);
+        let position = position.unwrap();
+        assert_eq!(
+            position.variant_outcome(),
+            Some(ChessGameResult::KingOfTheHill(ChessPlayer::White))
+        );
+        assert!(position.legal_moves().is_empty());
+
+        // Third check, with one left to give before it
+        let mut position =
+            ChessPosition::from_fen("4k3/8/8/8/8/8/8/4K2R w - - 1+3 0 1", Rules::ThreeCheck)
+                .unwrap();
+        let check = position.find_move(&"h1h8".parse().unwrap()).unwrap();
+        position.play(&check).unwrap();
+        assert_eq!(position.remaining_checks(ChessPlayer::White), 0);
+        assert_eq!(
+            position.variant_outcome(),
+            Some(ChessGameResult::ThreeChecks(ChessPlayer::White))
+        );
+        assert_eq!(position.to_string(), "4k2R/8/8/8/8/8/8/4K3 b - - 0+3 1 1");
+
+        // Taking next to the king blows it up, along with the rook but not the pawn
+        let mut position =
+            ChessPosition::from_fen("3rk3/3pp3/8/8/8/8/8/3RK3 w - - 0 1", Rules::Atomic).unwrap();
+        let capture = position.find_move(&"d1d7".parse().unwrap()).unwrap();
+        position.play(&capture).unwrap();
+        assert_eq!(position.to_string(), "8/4p3/8/8/8/8/8/4K3 b - - 0 1");
+        assert_eq!(
+            position.variant_outcome(),
+            Some(ChessGameResult::KingExploded(ChessPlayer::White))
+        );
+    }
+
+    #[test]
+    fn test_crazyhouse() {
+        // The promoted queen goes into the pocket as a pawn
+        let mut position =
+            ChessPosition::from_fen("4k3/8/8/8/8/8/3q~4/4K3[] w - - 0 1", Rules::Crazyhouse)
+                .unwrap();
+        let capture = position.find_move(&"e1d2".parse().unwrap()).unwrap();
+        position.play(&capture).unwrap();
+        assert_eq!(position.to_string(), "4k3/8/8/8/8/8/3K4/8[P] b - - 0 1");
+        assert_eq!(position.pocket(ChessPlayer::White, PieceKind::Pawn), 1);
+
+        let reply = position.find_move(&"e8e7".parse().unwrap()).unwrap();
+        position.play(&reply).unwrap();
+        assert_eq!(position.find_move(&"P@e8".parse().unwrap()), None);
+        let drop = position.find_move(&"P@e4".parse().unwrap()).unwrap();
+        assert_eq!(
+            drop,
+            ChessMove::Drop {
+                kind: PieceKind::Pawn,
+                to: Square::new(4, 3)
+            }
+        );
+        position.play(&drop).unwrap();
+        assert_eq!(position.to_string(), "8/4k3/8/8/4P3/8/3K4/8[] b - - 0 2");
+    }
+}
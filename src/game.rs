@@ -0,0 +1,150 @@
+use std::{
+    fmt,
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    Message, Rejection,
+    protocol::{GameKind, ProtocolError},
+    strategy::{Strategy, StrategyKind},
+};
+
+// Every this many moves, the full state is sent instead of a move
+pub const FULL_STATE_INTERVAL: usize = 50;
+
+/// How a finished game ended, beyond what its final state shows
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Ending<S> {
+    Finished,               // By the game's own rules, see `Game::status`
+    Claimed,                // A draw claimed by the side to move, see `Game::claim_draw`
+    Adjudicated(Option<S>), // By the referee, for the winner if any, see `Game::adjudicate`
+    Agreed,
+    Resigned(S),  // By that side
+    Forfeited(S), // That side had too many moves rejected
+}
+
+/// A finished game with what an archive of it should say about it
+pub struct GameRecord<G: Game> {
+    pub game: G,
+    pub ending: Ending<G::Side>,
+    pub players: Vec<(G::Side, String)>,
+    pub started: SystemTime,
+    pub move_time: Option<Duration>, // Per move, if it was limited
+}
+
+/// A two-player game, implemented by its state - the server and client only go through this trait
+pub trait Game: Clone + fmt::Display + Send + Sized + 'static {
+    type Move: Clone + fmt::Debug + PartialEq + Send;
+    type Side: Copy + Default + fmt::Debug + fmt::Display + PartialEq + Send;
+    type Config: Copy
+        + Default
+        + fmt::Debug
+        + fmt::Display
+        + FromStr<Err = anyhow::Error>
+        + PartialEq
+        + Send;
+    type Outcome: Copy + fmt::Debug + fmt::Display + PartialEq;
+    /// What the referee may end games early with, see `adjudicate`
+    type Referee: Default;
+    type MoveError: fmt::Display;
+
+    const KIND: GameKind;
+
+    fn with_config(config: Self::Config) -> Self;
+    fn config(&self) -> Self::Config;
+    fn opponent(side: Self::Side) -> Self::Side;
+    fn side_to_move(&self) -> Self::Side;
+    /// Moves played so far, including any before a position was set up
+    fn moves_played(&self) -> usize;
+
+    fn legal_moves(&self) -> Vec<Self::Move>;
+    /// Plays a move for `side`, failing without changing anything if it is illegal
+    fn apply_move(
+        &mut self,
+        game_move: &Self::Move,
+        side: Self::Side,
+    ) -> Result<(), Self::MoveError>;
+    /// Finds the single legal move for `side` which turns this state into `next`
+    fn diff(&self, next: &Self, side: Self::Side) -> Result<Self::Move, Self::MoveError>;
+    /// `None` while the game is still going
+    fn status(&self) -> Option<Self::Outcome>;
+    fn winner(outcome: &Self::Outcome) -> Option<Self::Side>;
+    /// The draw the side to move may claim instead of moving, if any
+    fn claim_draw(&self) -> Option<Self::Outcome> {
+        None
+    }
+    /// A result the referee may declare before the rules end the game, e.g. from tablebases
+    fn adjudicate(&self, _referee: &Self::Referee) -> Option<Self::Outcome> {
+        None
+    }
+    /// How a refused move is reported to the side which played it
+    fn rejection(_error: &Self::MoveError) -> Rejection {
+        Rejection::IllegalMove
+    }
+    /// Stable across processes, so both ends can compare states
+    fn state_hash(&self) -> u64;
+
+    /// Human-readable board, for the server and client logs
+    fn pretty_print(&self) {
+        println!("{}", self);
+    }
+
+    /// The finished game in the game's own archive format, if it has one
+    fn export(_record: &GameRecord<Self>) -> Option<String> {
+        None
+    }
+
+    /// The bot of that kind for this game, if there is one
+    fn strategy(kind: StrategyKind) -> Option<Box<dyn Strategy<Self>>>;
+
+    // Text form, `Display` is the inverse of `parse`
+    fn parse(str: &str) -> anyhow::Result<Self>;
+    fn format_move(game_move: &Self::Move) -> String;
+    fn parse_move(str: &str) -> Option<Self::Move>;
+    fn parse_side(str: &str) -> Option<Self::Side>;
+    /// A move typed in the game's notation, which may depend on the position
+    fn parse_move_text(&self, str: &str) -> Option<Self::Move> {
+        Self::parse_move(str)
+    }
+    /// A legal move as people write it in this state, for logs
+    fn move_text(&self, game_move: &Self::Move) -> String {
+        Self::format_move(game_move)
+    }
+
+    // Wire form, see `protocol::Packet`
+    fn encode_side(side: Self::Side) -> u8;
+    fn decode_side(byte: u8) -> Result<Self::Side, ProtocolError>;
+    fn encode_config(config: &Self::Config, bytes: &mut Vec<u8>);
+    fn decode_config(bytes: &mut &[u8]) -> Result<Self::Config, ProtocolError>;
+
+    fn encode_move(game_move: &Self::Move, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(Self::format_move(game_move).as_bytes());
+    }
+
+    fn decode_move(bytes: &[u8]) -> Result<Self::Move, ProtocolError> {
+        let str = String::from_utf8(bytes.to_vec()).map_err(|_| ProtocolError::InvalidUtf8)?;
+        Self::parse_move(&str).ok_or(ProtocolError::UnrecognisedText(str))
+    }
+}
+
+/// Plays a move chosen by a bot and builds the message announcing it
+pub fn play_move<G: Game>(game_state: &mut G, side: G::Side, game_move: G::Move) -> Message<G> {
+    if let Err(e) = game_state.apply_move(&game_move, side) {
+        panic!("Bot chose an illegal move {:?}: {}", game_move, e);
+    }
+
+    if let Some(outcome) = game_state.status() {
+        return Message::GameOver(game_state.to_string(), outcome.to_string());
+    }
+
+    if game_state
+        .moves_played()
+        .is_multiple_of(FULL_STATE_INTERVAL)
+    {
+        // Periodic resync, in case the two copies have drifted apart
+        return Message::GameMsg(game_state.to_string());
+    }
+
+    Message::Move(game_move, game_state.state_hash())
+}
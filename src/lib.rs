@@ -1,47 +1,154 @@
 pub mod chess;
+pub mod game;
+pub mod protocol;
+pub mod reliable;
+pub mod session;
+pub mod strategy;
 pub mod tictactoe;
 
 use std::fmt;
 
-use chess::ChessPlayer;
-use tictactoe::TTTPlayer;
+use game::Game;
+use protocol::ProtocolError;
+use strategy::StrategyKind;
 
-pub enum GameAndPlayer {
-    Chess(ChessPlayer),
-    TicTacToe(TTTPlayer),
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rejection {
+    IllegalMove,
+    OutOfTurn,
+    Forfeit,           // Too many rejected moves, the game is lost
+    PromotionRequired, // A pawn reached the last rank without saying what it becomes
 }
 
-pub enum Message {
-    NewGame(GameAndPlayer),
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let str = match self {
+            Rejection::IllegalMove => "illegal-move",
+            Rejection::OutOfTurn => "out-of-turn",
+            Rejection::Forfeit => "forfeit",
+            Rejection::PromotionRequired => "promotion-required",
+        };
+        write!(f, "{}", str)
+    }
+}
+
+pub enum Message<G: Game> {
+    NewGame(G::Side, G::Config, Option<StrategyKind>), // Sender's side, and the strategy the receiver is asked to play with
     GameMsg(String),
     GameOver(String, String),
+    Move(G::Move, u64),    // Hash of the state after the move
+    MoveText(String, u64), // A move in the game's notation, e.g. UCI or SAN for chess
+    ResyncRequest,         // Reply with a full-state `GameMsg`
+    Rejected(Rejection, String),
+    DrawOffer,   // Made on the sender's turn, before it moves
+    DrawAccept,  // The game is drawn
+    DrawDecline, // The offering side carries on with its move
+    Resign,      // The sender loses the game
+    Claim,       // The sender claims a draw by the game's rules, answered with `GameOver`
 }
 
-impl fmt::Display for Message {
+/// Legacy text form, kept for debugging - see `protocol::Packet` for the wire format
+impl<G: Game> fmt::Display for Message<G> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::NewGame(GameAndPlayer::TicTacToe(player)) => write!(f, "start:ttc,{}", player),
-            Self::NewGame(GameAndPlayer::Chess(player)) => write!(f, "start:chess,{}", player),
+            Self::NewGame(side, config, strategy) => {
+                write!(f, "start:{},{},{}", G::KIND, side, config)?;
+                match strategy {
+                    Some(strategy) => write!(f, ",{}", strategy),
+                    None => Ok(()),
+                }
+            }
             Self::GameMsg(board) => write!(f, "{}", board),
             Self::GameOver(board, result) => write!(f, "game-over:\n{}\n{}", result, board),
+            Self::Move(game_move, hash) => {
+                write!(f, "move:{}@{:016x}", G::format_move(game_move), hash)
+            }
+            Self::MoveText(text, hash) => write!(f, "move-text:{}@{:016x}", text, hash),
+            Self::ResyncRequest => write!(f, "resync"),
+            Self::Rejected(rejection, reason) => write!(f, "rejected:{},{}", rejection, reason),
+            Self::DrawOffer => write!(f, "draw-offer"),
+            Self::DrawAccept => write!(f, "draw-accept"),
+            Self::DrawDecline => write!(f, "draw-decline"),
+            Self::Resign => write!(f, "resign"),
+            Self::Claim => write!(f, "claim"),
         }
     }
 }
 
-impl From<&str> for Message {
-    fn from(str: &str) -> Self {
+impl<G: Game> TryFrom<&str> for Message<G> {
+    type Error = ProtocolError;
+    fn try_from(str: &str) -> Result<Self, Self::Error> {
+        let unrecognised = || ProtocolError::UnrecognisedText(str.to_string());
         match str {
-            "start:ttc,o" => Self::NewGame(GameAndPlayer::TicTacToe(TTTPlayer::Circle)),
-            "start:ttc,x" => Self::NewGame(GameAndPlayer::TicTacToe(TTTPlayer::Cross)),
-            "start:chess,w" => Self::NewGame(GameAndPlayer::Chess(ChessPlayer::White)),
-            "start:chess,b" => Self::NewGame(GameAndPlayer::Chess(ChessPlayer::Black)),
-            str if str.starts_with("game-over") => {
-                let mut lines = str.split('\n').map(String::from).collect::<Vec<String>>();
-                let result = lines.remove(1);
-                let board = lines.remove(1); // Becomes new line 1
-                Self::GameOver(board, result)
+            str if str.starts_with("start:") => {
+                let mut parts = str["start:".len()..].split(',');
+                if parts.next() != Some(&G::KIND.to_string()[..]) {
+                    return Err(unrecognised());
+                }
+                let side = parts
+                    .next()
+                    .and_then(G::parse_side)
+                    .ok_or_else(unrecognised)?;
+                let config = match parts.next() {
+                    Some(config) => config.parse().map_err(|e: anyhow::Error| {
+                        ProtocolError::InvalidGameConfig(e.to_string())
+                    })?,
+                    None => G::Config::default(),
+                };
+                let strategy = match parts.next() {
+                    Some(strategy) => Some(strategy.parse().map_err(|_| unrecognised())?),
+                    None => None,
+                };
+                if parts.next().is_some() {
+                    return Err(unrecognised());
+                }
+                Ok(Self::NewGame(side, config, strategy))
+            }
+            str if str.starts_with("game-over:\n") => {
+                let mut lines = str.splitn(3, '\n').skip(1);
+                match (lines.next(), lines.next()) {
+                    (Some(result), Some(board)) => {
+                        Ok(Self::GameOver(board.to_string(), result.to_string()))
+                    }
+                    _ => Err(unrecognised()),
+                }
+            }
+            "resync" => Ok(Self::ResyncRequest),
+            "draw-offer" => Ok(Self::DrawOffer),
+            "draw-accept" => Ok(Self::DrawAccept),
+            "draw-decline" => Ok(Self::DrawDecline),
+            "resign" => Ok(Self::Resign),
+            "claim" => Ok(Self::Claim),
+            str if str.starts_with("rejected:") => {
+                let (rejection, reason) = str["rejected:".len()..]
+                    .split_once(',')
+                    .ok_or_else(unrecognised)?;
+                let rejection = match rejection {
+                    "illegal-move" => Rejection::IllegalMove,
+                    "out-of-turn" => Rejection::OutOfTurn,
+                    "forfeit" => Rejection::Forfeit,
+                    "promotion-required" => Rejection::PromotionRequired,
+                    _ => return Err(unrecognised()),
+                };
+                Ok(Self::Rejected(rejection, reason.to_string()))
+            }
+            str if str.starts_with("move:") => {
+                let parse_move = |str: &str| {
+                    let (game_move, hash) = str.strip_prefix("move:")?.rsplit_once('@')?;
+                    let hash = u64::from_str_radix(hash, 16).ok()?;
+                    Some(Self::Move(G::parse_move(game_move)?, hash))
+                };
+                parse_move(str).ok_or_else(unrecognised)
+            }
+            str if str.starts_with("move-text:") => {
+                let (text, hash) = str["move-text:".len()..]
+                    .rsplit_once('@')
+                    .ok_or_else(unrecognised)?;
+                let hash = u64::from_str_radix(hash, 16).map_err(|_| unrecognised())?;
+                Ok(Self::MoveText(text.to_string(), hash))
             }
-            str => Self::GameMsg(str.to_string()),
+            str if G::parse(str).is_ok() => Ok(Self::GameMsg(str.to_string())),
+            _ => Err(unrecognised()),
         }
     }
 }
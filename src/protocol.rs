@@ -0,0 +1,394 @@
+use anyhow::anyhow;
+use std::{error, fmt, str::FromStr};
+
+use crate::{Message, Rejection, game::Game, strategy::StrategyKind};
+
+pub const PROTOCOL_VERSION: u8 = 2;
+
+// Version, game kind, message type, session id (u32), payload length (u32)
+pub const HEADER_LEN: usize = 11;
+
+const MSG_NEW_GAME: u8 = 0;
+const MSG_GAME: u8 = 1;
+const MSG_GAME_OVER: u8 = 2;
+const MSG_MOVE: u8 = 3;
+const MSG_RESYNC_REQUEST: u8 = 4;
+const MSG_REJECTED: u8 = 5;
+const MSG_MOVE_TEXT: u8 = 6;
+const MSG_DRAW_OFFER: u8 = 7;
+const MSG_DRAW_ACCEPT: u8 = 8;
+const MSG_DRAW_DECLINE: u8 = 9;
+const MSG_RESIGN: u8 = 10;
+const MSG_CLAIM: u8 = 11;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GameKind {
+    TicTacToe,
+    Chess,
+}
+
+impl TryFrom<u8> for GameKind {
+    type Error = ProtocolError;
+    fn try_from(byte: u8) -> Result<Self, Self::Error> {
+        match byte {
+            0 => Ok(GameKind::TicTacToe),
+            1 => Ok(GameKind::Chess),
+            _ => Err(ProtocolError::UnknownGameKind(byte)),
+        }
+    }
+}
+
+impl From<GameKind> for u8 {
+    fn from(game: GameKind) -> Self {
+        match game {
+            GameKind::TicTacToe => 0,
+            GameKind::Chess => 1,
+        }
+    }
+}
+
+impl fmt::Display for GameKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let str = match self {
+            GameKind::TicTacToe => "ttc",
+            GameKind::Chess => "chess",
+        };
+        write!(f, "{}", str)
+    }
+}
+
+impl FromStr for GameKind {
+    type Err = anyhow::Error;
+    fn from_str(str: &str) -> Result<Self, Self::Err> {
+        match str {
+            "ttc" => Ok(GameKind::TicTacToe),
+            "chess" => Ok(GameKind::Chess),
+            _ => Err(anyhow!("Unknown game {:?}, expected ttc or chess", str)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ProtocolError {
+    Truncated { expected: usize, actual: usize },
+    TrailingBytes(usize),
+    UnsupportedVersion(u8),
+    UnknownGameKind(u8),
+    WrongGameKind(GameKind),
+    UnknownMessageType(u8),
+    InvalidPlayer(u8),
+    UnknownRejection(u8),
+    UnknownStrategy(u8),
+    InvalidGameConfig(String),
+    InvalidUtf8,
+    UnrecognisedText(String),
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Truncated { expected, actual } => write!(
+                f,
+                "Packet truncated: Expected {} bytes, Actual {}",
+                expected, actual
+            ),
+            Self::TrailingBytes(len) => write!(f, "{} unexpected bytes after payload", len),
+            Self::UnsupportedVersion(version) => write!(
+                f,
+                "Unsupported protocol version {} (expected {})",
+                version, PROTOCOL_VERSION
+            ),
+            Self::UnknownGameKind(byte) => write!(f, "Unknown game kind {}", byte),
+            Self::WrongGameKind(game) => write!(f, "Unexpected {} packet", game),
+            Self::UnknownMessageType(byte) => write!(f, "Unknown message type {}", byte),
+            Self::InvalidPlayer(byte) => write!(f, "Invalid player {}", byte),
+            Self::UnknownRejection(byte) => write!(f, "Unknown rejection {}", byte),
+            Self::UnknownStrategy(byte) => write!(f, "Unknown strategy {}", byte),
+            Self::InvalidGameConfig(reason) => write!(f, "Invalid game config: {}", reason),
+            Self::InvalidUtf8 => write!(f, "Payload is not valid UTF-8"),
+            Self::UnrecognisedText(str) => write!(f, "Unrecognised text message: {:?}", str),
+        }
+    }
+}
+
+impl error::Error for ProtocolError {}
+
+impl TryFrom<u8> for StrategyKind {
+    type Error = ProtocolError;
+    fn try_from(byte: u8) -> Result<Self, Self::Error> {
+        StrategyKind::ALL
+            .get(byte as usize)
+            .copied()
+            .ok_or(ProtocolError::UnknownStrategy(byte))
+    }
+}
+
+impl From<StrategyKind> for u8 {
+    fn from(strategy: StrategyKind) -> Self {
+        StrategyKind::ALL
+            .iter()
+            .position(|&kind| kind == strategy)
+            .unwrap() as u8
+    }
+}
+
+/// A `Message` together with the header fields sent alongside it on the wire
+pub struct Packet<G: Game> {
+    pub session_id: u32,
+    pub message: Message<G>,
+}
+
+impl<G: Game> Packet<G> {
+    pub fn new(session_id: u32, message: Message<G>) -> Packet<G> {
+        Packet {
+            session_id,
+            message,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        encode_message(self.session_id, &self.message)
+    }
+}
+
+/// Encodes a message without taking ownership of it, see `Packet::encode`
+pub fn encode_message<G: Game>(session_id: u32, message: &Message<G>) -> Vec<u8> {
+    let (msg_type, payload) = match message {
+        Message::NewGame(side, config, strategy) => {
+            let mut payload = vec![G::encode_side(*side)];
+            G::encode_config(config, &mut payload);
+            // Optional, so older payloads without it still decode
+            if let Some(strategy) = strategy {
+                payload.push((*strategy).into());
+            }
+            (MSG_NEW_GAME, payload)
+        }
+        Message::GameMsg(board) => (MSG_GAME, board.as_bytes().to_vec()),
+        Message::GameOver(board, result) => {
+            let mut payload = Vec::with_capacity(4 + result.len() + board.len());
+            payload.extend_from_slice(&(result.len() as u32).to_be_bytes());
+            payload.extend_from_slice(result.as_bytes());
+            payload.extend_from_slice(board.as_bytes());
+            (MSG_GAME_OVER, payload)
+        }
+        Message::Move(game_move, hash) => {
+            let mut payload = hash.to_be_bytes().to_vec();
+            G::encode_move(game_move, &mut payload);
+            (MSG_MOVE, payload)
+        }
+        Message::MoveText(text, hash) => {
+            let mut payload = hash.to_be_bytes().to_vec();
+            payload.extend_from_slice(text.as_bytes());
+            (MSG_MOVE_TEXT, payload)
+        }
+        Message::ResyncRequest => (MSG_RESYNC_REQUEST, vec![]),
+        Message::Rejected(rejection, reason) => {
+            let mut payload = vec![match rejection {
+                Rejection::IllegalMove => 0,
+                Rejection::OutOfTurn => 1,
+                Rejection::Forfeit => 2,
+                Rejection::PromotionRequired => 3,
+            }];
+            payload.extend_from_slice(reason.as_bytes());
+            (MSG_REJECTED, payload)
+        }
+        Message::DrawOffer => (MSG_DRAW_OFFER, vec![]),
+        Message::DrawAccept => (MSG_DRAW_ACCEPT, vec![]),
+        Message::DrawDecline => (MSG_DRAW_DECLINE, vec![]),
+        Message::Resign => (MSG_RESIGN, vec![]),
+        Message::Claim => (MSG_CLAIM, vec![]),
+    };
+
+    let mut bytes = Vec::with_capacity(HEADER_LEN + payload.len());
+    bytes.push(PROTOCOL_VERSION);
+    bytes.push(G::KIND.into());
+    bytes.push(msg_type);
+    bytes.extend_from_slice(&session_id.to_be_bytes());
+    bytes.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(&payload);
+    bytes
+}
+
+/// Reads which game a packet is for, so it can be decoded as that game's `Packet`
+pub fn peek_game(bytes: &[u8]) -> Result<GameKind, ProtocolError> {
+    let mut bytes = bytes;
+    let header = take(&mut bytes, 2)?;
+    if header[0] != PROTOCOL_VERSION {
+        return Err(ProtocolError::UnsupportedVersion(header[0]));
+    }
+    GameKind::try_from(header[1])
+}
+
+pub(crate) fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Result<&'a [u8], ProtocolError> {
+    if bytes.len() < len {
+        return Err(ProtocolError::Truncated {
+            expected: len,
+            actual: bytes.len(),
+        });
+    }
+    let (head, tail) = bytes.split_at(len);
+    *bytes = tail;
+    Ok(head)
+}
+
+pub(crate) fn take_u32(bytes: &mut &[u8]) -> Result<u32, ProtocolError> {
+    Ok(u32::from_be_bytes(take(bytes, 4)?.try_into().unwrap()))
+}
+
+fn to_string(bytes: &[u8]) -> Result<String, ProtocolError> {
+    String::from_utf8(bytes.to_vec()).map_err(|_| ProtocolError::InvalidUtf8)
+}
+
+impl<G: Game> TryFrom<&[u8]> for Packet<G> {
+    type Error = ProtocolError;
+    fn try_from(mut bytes: &[u8]) -> Result<Self, Self::Error> {
+        let game = peek_game(bytes)?;
+        if game != G::KIND {
+            return Err(ProtocolError::WrongGameKind(game));
+        }
+        let msg_type = take(&mut bytes, 3)?[2];
+        let session_id = take_u32(&mut bytes)?;
+        let payload_len = take_u32(&mut bytes)? as usize;
+        let mut payload = take(&mut bytes, payload_len)?;
+        if !bytes.is_empty() {
+            return Err(ProtocolError::TrailingBytes(bytes.len()));
+        }
+
+        let message = match msg_type {
+            MSG_NEW_GAME => {
+                let side = G::decode_side(take(&mut payload, 1)?[0])?;
+                let config = G::decode_config(&mut payload)?;
+                let strategy = match payload {
+                    [] => None,
+                    [byte] => Some(StrategyKind::try_from(*byte)?),
+                    _ => return Err(ProtocolError::TrailingBytes(payload.len() - 1)),
+                };
+                Message::NewGame(side, config, strategy)
+            }
+            MSG_GAME => Message::GameMsg(to_string(payload)?),
+            MSG_GAME_OVER => {
+                let result_len = take_u32(&mut payload)? as usize;
+                let result = to_string(take(&mut payload, result_len)?)?;
+                Message::GameOver(to_string(payload)?, result)
+            }
+            MSG_MOVE => {
+                let hash = u64::from_be_bytes(take(&mut payload, 8)?.try_into().unwrap());
+                Message::Move(G::decode_move(payload)?, hash)
+            }
+            MSG_RESYNC_REQUEST => Message::ResyncRequest,
+            MSG_REJECTED => {
+                let rejection = match take(&mut payload, 1)?[0] {
+                    0 => Rejection::IllegalMove,
+                    1 => Rejection::OutOfTurn,
+                    2 => Rejection::Forfeit,
+                    3 => Rejection::PromotionRequired,
+                    byte => return Err(ProtocolError::UnknownRejection(byte)),
+                };
+                Message::Rejected(rejection, to_string(payload)?)
+            }
+            MSG_MOVE_TEXT => {
+                let hash = u64::from_be_bytes(take(&mut payload, 8)?.try_into().unwrap());
+                Message::MoveText(to_string(payload)?, hash)
+            }
+            MSG_DRAW_OFFER | MSG_DRAW_ACCEPT | MSG_DRAW_DECLINE | MSG_RESIGN | MSG_CLAIM => {
+                if !payload.is_empty() {
+                    return Err(ProtocolError::TrailingBytes(payload.len()));
+                }
+                match msg_type {
+                    MSG_DRAW_OFFER => Message::DrawOffer,
+                    MSG_DRAW_ACCEPT => Message::DrawAccept,
+                    MSG_DRAW_DECLINE => Message::DrawDecline,
+                    MSG_RESIGN => Message::Resign,
+                    _ => Message::Claim,
+                }
+            }
+            _ => return Err(ProtocolError::UnknownMessageType(msg_type)),
+        };
+
+        Ok(Packet {
+            session_id,
+            message,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tictactoe::{TTTConfig, TTTGameState, TTTPlayer};
+
+    type TTTPacket = Packet<TTTGameState>;
+    type TTTMessage = Message<TTTGameState>;
+
+    #[test]
+    fn test_round_trip() {
+        let packet = TTTPacket::new(42, Message::GameOver("xo ".to_string(), "win".to_string()));
+        let decoded = TTTPacket::try_from(&packet.encode()[..]).unwrap();
+        assert_eq!(decoded.session_id, 42);
+        assert_eq!(decoded.message.to_string(), packet.message.to_string());
+
+        for strategy in [None, Some(StrategyKind::Mcts)] {
+            let msg = TTTMessage::NewGame(TTTPlayer::Cross, TTTConfig::CLASSIC, strategy);
+            let bytes = encode_message(7, &msg);
+            let decoded = TTTPacket::try_from(&bytes[..]).unwrap().message;
+            assert_eq!(decoded.to_string(), msg.to_string());
+            assert_eq!(
+                TTTMessage::try_from(&msg.to_string()[..])
+                    .unwrap()
+                    .to_string(),
+                msg.to_string()
+            );
+        }
+
+        for msg in [
+            TTTMessage::MoveText("b2".to_string(), 0xfeed),
+            TTTMessage::Rejected(Rejection::PromotionRequired, "e7e8".to_string()),
+            TTTMessage::DrawOffer,
+            TTTMessage::DrawAccept,
+            TTTMessage::DrawDecline,
+            TTTMessage::Resign,
+            TTTMessage::Claim,
+        ] {
+            let decoded = TTTPacket::try_from(&encode_message(3, &msg)[..]).unwrap();
+            assert_eq!(decoded.message.to_string(), msg.to_string());
+            let parsed = TTTMessage::try_from(&msg.to_string()[..]).unwrap();
+            assert_eq!(parsed.to_string(), msg.to_string());
+        }
+
+        let msg = TTTMessage::Move((12, 3), 0xdeadbeef);
+        let decoded = TTTPacket::try_from(&encode_message(1, &msg)[..]).unwrap();
+        assert!(matches!(
+            decoded.message,
+            Message::Move((12, 3), 0xdeadbeef)
+        ));
+    }
+
+    #[test]
+    fn test_malformed() {
+        let bytes = TTTPacket::new(1, Message::GameMsg("x".to_string())).encode();
+        for len in 0..bytes.len() {
+            assert!(matches!(
+                TTTPacket::try_from(&bytes[..len]),
+                Err(ProtocolError::Truncated { .. })
+            ));
+        }
+
+        let mut bad_version = bytes.clone();
+        bad_version[0] = 0;
+        assert_eq!(
+            TTTPacket::try_from(&bad_version[..]).err(),
+            Some(ProtocolError::UnsupportedVersion(0))
+        );
+
+        let mut chess = bytes.clone();
+        chess[1] = GameKind::Chess.into();
+        assert_eq!(peek_game(&chess), Ok(GameKind::Chess));
+        assert_eq!(
+            TTTPacket::try_from(&chess[..]).err(),
+            Some(ProtocolError::WrongGameKind(GameKind::Chess))
+        );
+
+        assert!(TTTMessage::try_from("game-over:\nwin").is_err());
+        assert!(TTTMessage::try_from("hello").is_err());
+    }
+}
@@ -0,0 +1,561 @@
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    io,
+    net::SocketAddr,
+    sync::Mutex,
+    time::Duration,
+};
+use tokio::{
+    net::UdpSocket,
+    sync::{Mutex as AsyncMutex, mpsc},
+    time::{Instant, sleep_until},
+};
+
+const FRAME_DATA: u8 = 0;
+const FRAME_ACK: u8 = 1;
+const HEADER_LEN: usize = 9; // Frame type + u32 stream epoch + u32 sequence number
+const MAX_DATAGRAM: usize = 65536;
+const MAX_OUT_OF_ORDER: u32 = 256; // Frames held back per peer, later ones are left to retransmission
+
+/// Minimal datagram interface, so the reliability layer can run over UDP or an in-process test socket
+pub trait DatagramSocket {
+    fn send_to(
+        &self,
+        buf: &[u8],
+        target: SocketAddr,
+    ) -> impl Future<Output = io::Result<usize>> + Send;
+    fn recv_from(
+        &self,
+        buf: &mut [u8],
+    ) -> impl Future<Output = io::Result<(usize, SocketAddr)>> + Send;
+}
+
+impl DatagramSocket for UdpSocket {
+    fn send_to(
+        &self,
+        buf: &[u8],
+        target: SocketAddr,
+    ) -> impl Future<Output = io::Result<usize>> + Send {
+        UdpSocket::send_to(self, buf, target)
+    }
+
+    fn recv_from(
+        &self,
+        buf: &mut [u8],
+    ) -> impl Future<Output = io::Result<(usize, SocketAddr)>> + Send {
+        UdpSocket::recv_from(self, buf)
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct ReliableConfig {
+    pub initial_rto: Duration,
+    pub max_rto: Duration,
+    pub max_retries: u32,
+}
+
+impl Default for ReliableConfig {
+    fn default() -> Self {
+        ReliableConfig {
+            initial_rto: Duration::from_millis(200),
+            max_rto: Duration::from_secs(5),
+            max_retries: 8,
+        }
+    }
+}
+
+struct Pending {
+    frame: Vec<u8>,
+    deadline: Instant,
+    rto: Duration,
+    retries: u32,
+}
+
+#[derive(Default)]
+struct PeerState {
+    send_epoch: Option<u32>, // Picked at random for each stream sent to the peer
+    next_send_seq: u32,
+    recv_epoch: Option<u32>, // Of the stream being received, once its seq 0 has arrived
+    next_recv_seq: u32,
+    unacked: BTreeMap<u32, Pending>,
+    out_of_order: BTreeMap<u32, Vec<u8>>,
+}
+
+enum Event {
+    Datagram(usize, SocketAddr),
+    RetransmitDue,
+}
+
+/// In-order, exactly-once delivery on top of a datagram socket
+///
+/// Every payload is framed with a sequence number and retransmitted with exponential backoff
+/// until the peer acknowledges it. Duplicates are dropped and out-of-order frames are held back
+/// until the gap is filled. Retransmissions and ACKs are only processed while `recv_from` or
+/// `flush` is being awaited.
+///
+/// Each stream is tagged with an epoch. Frames are only acknowledged once their stream has
+/// started at seq 0, so a sender whose peer forgot it gets `TimedOut` rather than waiting
+/// forever. A sender which gave up starts a new epoch, and its seq 0 resets the receiver.
+pub struct ReliableSocket<S = UdpSocket> {
+    sock: S,
+    config: ReliableConfig,
+    peers: HashMap<SocketAddr, PeerState>,
+    ready: VecDeque<(Vec<u8>, SocketAddr)>,
+    buf: Vec<u8>,
+}
+
+impl<S: DatagramSocket> ReliableSocket<S> {
+    pub fn new(sock: S) -> ReliableSocket<S> {
+        Self::with_config(sock, ReliableConfig::default())
+    }
+
+    pub fn with_config(sock: S, config: ReliableConfig) -> ReliableSocket<S> {
+        ReliableSocket {
+            sock,
+            config,
+            peers: HashMap::new(),
+            ready: VecDeque::new(),
+            buf: vec![0; MAX_DATAGRAM],
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.sock
+    }
+
+    pub async fn send_to(&mut self, payload: &[u8], target: SocketAddr) -> io::Result<usize> {
+        let peer = self.peers.entry(target).or_default();
+        let epoch = *peer.send_epoch.get_or_insert_with(rand::random);
+        let seq = peer.next_send_seq;
+        peer.next_send_seq = peer.next_send_seq.wrapping_add(1);
+
+        let frame = encode_frame(FRAME_DATA, epoch, seq, payload);
+        peer.unacked.insert(
+            seq,
+            Pending {
+                frame: frame.clone(),
+                deadline: Instant::now() + self.config.initial_rto,
+                rto: self.config.initial_rto,
+                retries: 0,
+            },
+        );
+
+        self.sock.send_to(&frame, target).await?;
+        Ok(payload.len())
+    }
+
+    /// Waits for the next in-order payload from any peer
+    ///
+    /// Returns `TimedOut` if a peer stopped acknowledging; that peer's state is dropped.
+    /// Cancelling this future loses nothing that the peer will not retransmit.
+    pub async fn recv_from(&mut self) -> io::Result<(Vec<u8>, SocketAddr)> {
+        loop {
+            if let Some(next) = self.ready.pop_front() {
+                return Ok(next);
+            }
+            self.poll().await?;
+        }
+    }
+
+    /// Waits until every payload sent so far has been acknowledged
+    pub async fn flush(&mut self) -> io::Result<()> {
+        while self.peers.values().any(|peer| !peer.unacked.is_empty()) {
+            self.poll().await?;
+        }
+        Ok(())
+    }
+
+    /// Drops sequence state for a peer, e.g. once its session has ended
+    pub fn forget_peer(&mut self, addr: &SocketAddr) {
+        self.peers.remove(addr);
+        self.ready.retain(|(_, from)| from != addr);
+    }
+
+    async fn poll(&mut self) -> io::Result<()> {
+        let next_deadline = self
+            .peers
+            .values()
+            .flat_map(|peer| peer.unacked.values().map(|pending| pending.deadline))
+            .min();
+
+        let event = match next_deadline {
+            Some(deadline) => tokio::select! {
+                res = self.sock.recv_from(&mut self.buf) => res.map(|(len, addr)| Event::Datagram(len, addr))?,
+                _ = sleep_until(deadline) => Event::RetransmitDue,
+            },
+            None => {
+                let (len, addr) = self.sock.recv_from(&mut self.buf).await?;
+                Event::Datagram(len, addr)
+            }
+        };
+
+        match event {
+            Event::Datagram(len, addr) => self.handle_frame(len, addr).await,
+            Event::RetransmitDue => self.retransmit_due().await,
+        }
+    }
+
+    async fn handle_frame(&mut self, len: usize, addr: SocketAddr) -> io::Result<()> {
+        if len < HEADER_LEN {
+            return Ok(()); // Not one of ours
+        }
+        let kind = self.buf[0];
+        let epoch = u32::from_be_bytes(self.buf[1..5].try_into().unwrap());
+        let seq = u32::from_be_bytes(self.buf[5..HEADER_LEN].try_into().unwrap());
+        let peer = self.peers.entry(addr).or_default();
+
+        match kind {
+            FRAME_DATA => {
+                if peer.recv_epoch != Some(epoch) {
+                    if seq != 0 {
+                        return Ok(()); // Not ACKed, so the sender gives up and starts over
+                    }
+                    // A new stream, replacing whatever the peer sent before
+                    peer.recv_epoch = Some(epoch);
+                    peer.next_recv_seq = 0;
+                    peer.out_of_order.clear();
+                }
+                let offset = seq.wrapping_sub(peer.next_recv_seq);
+                let duplicate = offset >= u32::MAX / 2 || peer.out_of_order.contains_key(&seq);
+                if !duplicate && offset >= MAX_OUT_OF_ORDER {
+                    return Ok(()); // Not ACKed, so retransmitted once there is room
+                }
+                // Always ACK, the previous ACK may have been lost
+                let ack = encode_frame(FRAME_ACK, epoch, seq, &[]);
+                self.sock.send_to(&ack, addr).await?;
+                if duplicate {
+                    return Ok(());
+                }
+                peer.out_of_order
+                    .insert(seq, self.buf[HEADER_LEN..len].to_vec());
+
+                while let Some(payload) = peer.out_of_order.remove(&peer.next_recv_seq) {
+                    self.ready.push_back((payload, addr));
+                    peer.next_recv_seq = peer.next_recv_seq.wrapping_add(1);
+                }
+            }
+            FRAME_ACK if peer.send_epoch == Some(epoch) => {
+                peer.unacked.remove(&seq);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    async fn retransmit_due(&mut self) -> io::Result<()> {
+        let now = Instant::now();
+        let mut resend = vec![];
+        let mut failed = None;
+
+        for (addr, peer) in self.peers.iter_mut() {
+            for pending in peer.unacked.values_mut() {
+                if pending.deadline > now {
+                    continue;
+                }
+                if pending.retries >= self.config.max_retries {
+                    failed = Some(*addr);
+                    break;
+                }
+                pending.retries += 1;
+                pending.rto = (pending.rto * 2).min(self.config.max_rto);
+                pending.deadline = now + pending.rto;
+                resend.push((pending.frame.clone(), *addr));
+            }
+        }
+
+        if let Some(addr) = failed {
+            self.forget_peer(&addr);
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("{} stopped acknowledging packets", addr),
+            ));
+        }
+
+        for (frame, addr) in resend {
+            self.sock.send_to(&frame, addr).await?;
+        }
+        Ok(())
+    }
+}
+
+fn encode_frame(kind: u8, epoch: u32, seq: u32, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(HEADER_LEN + payload.len());
+    frame.push(kind);
+    frame.extend_from_slice(&epoch.to_be_bytes());
+    frame.extend_from_slice(&seq.to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// Packet loss profile for `LossySocket`, each rate being a probability per sent datagram
+#[derive(Clone, Copy, Default)]
+pub struct LossConfig {
+    pub drop_rate: f64,
+    pub duplicate_rate: f64,
+    pub reorder_rate: f64,
+    pub seed: u64,
+}
+
+struct LossState {
+    rng: StdRng,
+    held_back: Option<Vec<u8>>,
+}
+
+/// In-process datagram socket which drops, duplicates and reorders packets deterministically
+///
+/// Created in connected pairs; the decisions only depend on the seed and the order of sends.
+pub struct LossySocket {
+    local_addr: SocketAddr,
+    peer_addr: SocketAddr,
+    config: LossConfig,
+    state: Mutex<LossState>,
+    tx: mpsc::UnboundedSender<Vec<u8>>,
+    rx: AsyncMutex<mpsc::UnboundedReceiver<Vec<u8>>>,
+}
+
+impl LossySocket {
+    pub fn pair(a_to_b: LossConfig, b_to_a: LossConfig) -> (LossySocket, LossySocket) {
+        let a_addr = "127.0.0.1:1".parse::<SocketAddr>().unwrap();
+        let b_addr = "127.0.0.1:2".parse::<SocketAddr>().unwrap();
+        let (a_tx, b_rx) = mpsc::unbounded_channel();
+        let (b_tx, a_rx) = mpsc::unbounded_channel();
+
+        let new = |local_addr, peer_addr, config: LossConfig, tx, rx| LossySocket {
+            local_addr,
+            peer_addr,
+            config,
+            state: Mutex::new(LossState {
+                rng: StdRng::seed_from_u64(config.seed),
+                held_back: None,
+            }),
+            tx,
+            rx: AsyncMutex::new(rx),
+        };
+
+        (
+            new(a_addr, b_addr, a_to_b, a_tx, a_rx),
+            new(b_addr, a_addr, b_to_a, b_tx, b_rx),
+        )
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    fn transmit(&self, buf: &[u8]) {
+        let mut state = self.state.lock().unwrap();
+        let deliver = |packet: Vec<u8>| {
+            let _ = self.tx.send(packet); // Peer gone: the packet is lost anyway
+        };
+
+        if state.rng.random_bool(self.config.drop_rate) {
+            return;
+        }
+        if state.rng.random_bool(self.config.reorder_rate) && state.held_back.is_none() {
+            // Delivered after the next packet
+            state.held_back = Some(buf.to_vec());
+            return;
+        }
+
+        deliver(buf.to_vec());
+        if state.rng.random_bool(self.config.duplicate_rate) {
+            deliver(buf.to_vec());
+        }
+        if let Some(packet) = state.held_back.take() {
+            deliver(packet);
+        }
+    }
+}
+
+impl DatagramSocket for LossySocket {
+    fn send_to(
+        &self,
+        buf: &[u8],
+        target: SocketAddr,
+    ) -> impl Future<Output = io::Result<usize>> + Send {
+        let res = if target == self.peer_addr {
+            self.transmit(buf);
+            Ok(buf.len())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::AddrNotAvailable,
+                format!("LossySocket is only connected to {}", self.peer_addr),
+            ))
+        };
+        async { res }
+    }
+
+    async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let packet = self.rx.lock().await.recv().await.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::ConnectionAborted, "LossySocket peer dropped")
+        })?;
+        let len = packet.len().min(buf.len());
+        buf[..len].copy_from_slice(&packet[..len]);
+        Ok((len, self.peer_addr))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOSSY: LossConfig = LossConfig {
+        drop_rate: 0.3,
+        duplicate_rate: 0.2,
+        reorder_rate: 0.2,
+        seed: 0,
+    };
+
+    fn test_config() -> ReliableConfig {
+        ReliableConfig {
+            initial_rto: Duration::from_millis(10),
+            max_rto: Duration::from_millis(100),
+            max_retries: 20,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_in_order_exactly_once() {
+        let (a, b) = LossySocket::pair(LOSSY, LossConfig { seed: 1, ..LOSSY });
+        let (a_addr, b_addr) = (a.local_addr(), b.local_addr());
+        let mut a = ReliableSocket::with_config(a, test_config());
+        let mut b = ReliableSocket::with_config(b, test_config());
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            // Keeps acknowledging retransmissions until the runtime shuts down
+            while let Ok((payload, from)) = b.recv_from().await {
+                assert_eq!(from, a_addr);
+                tx.send(u32::from_be_bytes(payload.try_into().unwrap()))
+                    .unwrap();
+            }
+        });
+
+        for i in 0u32..100 {
+            a.send_to(&i.to_be_bytes(), b_addr).await.unwrap();
+        }
+        a.flush().await.unwrap();
+
+        for i in 0u32..100 {
+            assert_eq!(rx.recv().await, Some(i));
+        }
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_ping_pong() {
+        let (a, b) = LossySocket::pair(LOSSY, LossConfig { seed: 2, ..LOSSY });
+        let b_addr = b.local_addr();
+        let mut a = ReliableSocket::with_config(a, test_config());
+        let mut b = ReliableSocket::with_config(b, test_config());
+
+        tokio::spawn(async move {
+            while let Ok((payload, from)) = b.recv_from().await {
+                b.send_to(&payload, from).await.unwrap();
+            }
+        });
+
+        for i in 0u32..50 {
+            a.send_to(&i.to_be_bytes(), b_addr).await.unwrap();
+            let (payload, _) = a.recv_from().await.unwrap();
+            assert_eq!(payload, i.to_be_bytes());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_on_silent_peer() {
+        let (a, b) = LossySocket::pair(LossConfig::default(), LossConfig::default());
+        let b_addr = b.local_addr();
+        let mut a = ReliableSocket::with_config(a, test_config());
+
+        a.send_to(b"hello", b_addr).await.unwrap();
+        let err = a.flush().await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        drop(b);
+    }
+
+    #[tokio::test]
+    async fn test_forgotten_peer() {
+        let (a, b) = LossySocket::pair(LossConfig::default(), LossConfig::default());
+        let (a_addr, b_addr) = (a.local_addr(), b.local_addr());
+        let config = ReliableConfig {
+            max_retries: 3,
+            ..test_config()
+        };
+        let mut a = ReliableSocket::with_config(a, config);
+        let mut b = ReliableSocket::with_config(b, test_config());
+
+        a.send_to(b"first", b_addr).await.unwrap();
+        assert_eq!(b.recv_from().await.unwrap().0, b"first");
+        b.forget_peer(&a_addr);
+
+        // Its next frame isn't acknowledged, so the sender gives up instead of hanging
+        a.send_to(b"second", b_addr).await.unwrap();
+        let receiver = tokio::spawn(async move {
+            let received = b.recv_from().await.unwrap();
+            (b, received)
+        });
+        assert_eq!(a.flush().await.unwrap_err().kind(), io::ErrorKind::TimedOut);
+
+        // Then starts over from seq 0
+        a.send_to(b"third", b_addr).await.unwrap();
+        a.flush().await.unwrap();
+        let (_, (payload, from)) = receiver.await.unwrap();
+        assert_eq!((payload, from), (b"third".to_vec(), a_addr));
+    }
+
+    #[tokio::test]
+    async fn test_sender_starts_over() {
+        let (a, b) = LossySocket::pair(LossConfig::default(), LossConfig::default());
+        let b_addr = b.local_addr();
+        let config = ReliableConfig {
+            max_retries: 3,
+            ..test_config()
+        };
+        let mut a = ReliableSocket::with_config(a, config);
+        let mut b = ReliableSocket::with_config(b, test_config());
+
+        a.send_to(b"first", b_addr).await.unwrap();
+        assert_eq!(b.recv_from().await.unwrap().0, b"first");
+        a.flush().await.unwrap();
+
+        // The receiver isn't listening, so the sender gives up while it still expects seq 1
+        a.send_to(b"second", b_addr).await.unwrap();
+        assert_eq!(a.flush().await.unwrap_err().kind(), io::ErrorKind::TimedOut);
+
+        // Its new stream starts at seq 0 again, which mustn't be taken for a duplicate
+        a.send_to(b"third", b_addr).await.unwrap();
+        let receiver = tokio::spawn(async move {
+            let mut received = vec![];
+            for _ in 0..2 {
+                received.push(b.recv_from().await.unwrap().0);
+            }
+            received
+        });
+        a.flush().await.unwrap();
+        assert_eq!(
+            receiver.await.unwrap(),
+            [b"second".to_vec(), b"third".to_vec()]
+        );
+    }
+
+    #[test]
+    fn test_lossy_socket_deterministic() {
+        let run = || {
+            let (a, b) = LossySocket::pair(LOSSY, LOSSY);
+            for i in 0u8..100 {
+                a.transmit(&[i]);
+            }
+            let mut rx = b.rx.try_lock().unwrap();
+            let mut delivered = vec![];
+            while let Ok(packet) = rx.try_recv() {
+                delivered.push(packet[0]);
+            }
+            delivered
+        };
+        let delivered = run();
+        assert_eq!(delivered, run());
+        assert!(delivered.len() < 100);
+    }
+}
@@ -0,0 +1,230 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    net::SocketAddr,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{
+    game::Game,
+    strategy::{RandomStrategy, Strategy, StrategyKind},
+};
+
+pub const DEFAULT_SESSION_TIMEOUT: Duration = Duration::from_secs(300);
+
+#[derive(Clone, Copy, Default)]
+pub struct SessionStats {
+    pub wins: usize,
+    pub draws: usize,
+    pub losses: usize,
+}
+
+impl SessionStats {
+    pub fn games_played(&self) -> usize {
+        self.wins + self.draws + self.losses
+    }
+}
+
+impl fmt::Display for SessionStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} W | {} D | {} L", self.wins, self.draws, self.losses)
+    }
+}
+
+/// A single client's game, independent of every other peer talking to the server
+pub struct Session<G: Game> {
+    pub id: u32,
+    pub game_state: G,
+    pub player: G::Side,
+    pub strategy: Box<dyn Strategy<G>>, // Plays `player`
+    pub strategy_kind: StrategyKind,
+    pub move_count: usize,
+    pub stats: SessionStats,
+    pub awaiting_client: bool, // Whether the client is the one to move
+    pub draw_offered: bool,    // At most once a game
+    pub awaiting_draw_answer: bool,
+    pub rejected_moves: usize,
+    pub started: SystemTime, // When the current game began
+    last_seen: Instant,
+}
+
+impl<G: Game> Session<G> {
+    fn new(id: u32) -> Session<G> {
+        Session {
+            id,
+            game_state: G::with_config(G::Config::default()),
+            player: G::Side::default(),
+            strategy: Box::new(RandomStrategy),
+            strategy_kind: StrategyKind::Random,
+            move_count: 0,
+            stats: SessionStats::default(),
+            awaiting_client: false,
+            draw_offered: false,
+            awaiting_draw_answer: false,
+            rejected_moves: 0,
+            started: SystemTime::now(),
+            last_seen: Instant::now(),
+        }
+    }
+
+    /// Resets the board for a new game, keeping stats
+    pub fn new_game(&mut self, player: G::Side, config: G::Config) {
+        self.game_state = G::with_config(config);
+        self.player = player;
+        self.move_count = 0;
+        self.awaiting_client = false;
+        self.draw_offered = false;
+        self.awaiting_draw_answer = false;
+        self.rejected_moves = 0;
+        self.started = SystemTime::now();
+        self.strategy.new_game();
+    }
+
+    /// Switches to another strategy, failing if this game has no such bot
+    ///
+    /// The current strategy is kept if it is already of that kind.
+    pub fn set_strategy(&mut self, kind: StrategyKind) -> bool {
+        if kind == self.strategy_kind {
+            return true;
+        }
+        match G::strategy(kind) {
+            Some(strategy) => {
+                self.strategy = strategy;
+                self.strategy_kind = kind;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+pub struct SessionManager<G: Game> {
+    sessions: HashMap<SocketAddr, Session<G>>,
+    timeout: Duration,
+    next_id: u32,
+}
+
+impl<G: Game> SessionManager<G> {
+    pub fn new(timeout: Duration) -> SessionManager<G> {
+        SessionManager {
+            sessions: HashMap::new(),
+            timeout,
+            next_id: 1, // 0 is sent by clients without a session
+        }
+    }
+
+    /// Returns the session for `addr`, creating it if needed, and marks it as active
+    pub fn get_or_create(&mut self, addr: SocketAddr) -> &mut Session<G> {
+        let session = self.sessions.entry(addr).or_insert_with(|| {
+            let id = self.next_id;
+            self.next_id = self.next_id.wrapping_add(1).max(1);
+            Session::new(id)
+        });
+        session.last_seen = Instant::now();
+        session
+    }
+
+    pub fn remove(&mut self, addr: &SocketAddr) -> Option<Session<G>> {
+        self.sessions.remove(addr)
+    }
+
+    /// Drops every session idle for longer than the timeout, returning their addresses
+    pub fn expire_idle(&mut self) -> Vec<SocketAddr> {
+        self.expire_idle_at(Instant::now())
+    }
+
+    fn expire_idle_at(&mut self, now: Instant) -> Vec<SocketAddr> {
+        let expired = self
+            .sessions
+            .iter()
+            .filter(|(_, session)| now.duration_since(session.last_seen) > self.timeout)
+            .map(|(addr, _)| *addr)
+            .collect::<Vec<SocketAddr>>();
+
+        for addr in &expired {
+            self.sessions.remove(addr);
+        }
+        expired
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+    }
+}
+
+impl<G: Game> Default for SessionManager<G> {
+    fn default() -> Self {
+        Self::new(DEFAULT_SESSION_TIMEOUT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tictactoe::TTTGameState;
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn test_get_or_create() {
+        let mut sessions = SessionManager::<TTTGameState>::default();
+        assert!(sessions.is_empty());
+        assert_eq!(sessions.get_or_create(addr(1)).id, 1);
+        assert_eq!(sessions.get_or_create(addr(2)).id, 2);
+
+        // The same peer keeps its session and game
+        sessions.get_or_create(addr(1)).move_count = 3;
+        let session = sessions.get_or_create(addr(1));
+        assert_eq!((session.id, session.move_count), (1, 3));
+        assert_eq!(sessions.len(), 2);
+
+        // A peer coming back after removal starts over with a new id
+        assert!(sessions.remove(&addr(1)).is_some());
+        assert_eq!(sessions.get_or_create(addr(1)).id, 3);
+        assert_eq!(sessions.len(), 2);
+    }
+
+    #[test]
+    fn test_id_wraparound() {
+        // 0 means no session, so it is never handed out
+        let mut sessions = SessionManager::<TTTGameState> {
+            next_id: u32::MAX,
+            ..SessionManager::default()
+        };
+        assert_eq!(sessions.get_or_create(addr(1)).id, u32::MAX);
+        assert_eq!(sessions.get_or_create(addr(2)).id, 1);
+    }
+
+    #[test]
+    fn test_expire_idle() {
+        let timeout = Duration::from_secs(10);
+        let mut sessions = SessionManager::<TTTGameState>::new(timeout);
+        sessions.get_or_create(addr(1));
+        sessions.get_or_create(addr(2));
+        let seen = sessions.get_or_create(addr(1)).last_seen;
+        sessions.get_or_create(addr(2)).last_seen = seen + Duration::from_secs(5);
+
+        // Idle for exactly the timeout is still fine
+        assert!(sessions.expire_idle_at(seen + timeout).is_empty());
+        let expired = sessions.expire_idle_at(seen + timeout + Duration::from_millis(1));
+        assert_eq!(expired, [addr(1)]);
+        assert_eq!(sessions.len(), 1);
+
+        // Activity resets the clock
+        let seen = sessions.get_or_create(addr(2)).last_seen;
+        assert!(sessions.expire_idle_at(seen + timeout).is_empty());
+        assert_eq!(sessions.expire_idle_at(seen + 2 * timeout), [addr(2)]);
+        assert!(sessions.is_empty());
+        assert!(sessions.expire_idle().is_empty());
+    }
+}
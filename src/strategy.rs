@@ -0,0 +1,178 @@
+use anyhow::anyhow;
+use rand::seq::IndexedRandom;
+use std::{
+    fmt,
+    io::{self, Write},
+    str::FromStr,
+    time::Duration,
+};
+
+use crate::game::Game;
+
+/// Limits for a search - it stops at whichever is hit first, and runs to full depth if both are `None`
+#[derive(Clone, Copy, Debug)]
+pub struct SearchBudget {
+    pub max_time: Option<Duration>,
+    pub max_nodes: Option<u64>,
+}
+
+impl SearchBudget {
+    pub fn time(max_time: Duration) -> SearchBudget {
+        SearchBudget {
+            max_time: Some(max_time),
+            max_nodes: None,
+        }
+    }
+
+    pub fn nodes(max_nodes: u64) -> SearchBudget {
+        SearchBudget {
+            max_time: None,
+            max_nodes: Some(max_nodes),
+        }
+    }
+
+    pub fn unlimited() -> SearchBudget {
+        SearchBudget {
+            max_time: None,
+            max_nodes: None,
+        }
+    }
+}
+
+impl Default for SearchBudget {
+    fn default() -> Self {
+        Self::time(Duration::from_secs(1))
+    }
+}
+
+/// A bot which picks moves for one side, possibly remembering things between moves of a game
+pub trait Strategy<G: Game>: Send {
+    /// Picks a legal move for `side` within `budget`, or the strategy's own default if `None`
+    fn choose_move(
+        &mut self,
+        game_state: &G,
+        side: G::Side,
+        budget: Option<SearchBudget>,
+    ) -> G::Move;
+
+    /// Called before the first move of every game
+    fn new_game(&mut self) {}
+
+    /// Whether to agree to a draw the opponent offers, or claim one which is available
+    fn accept_draw(&mut self, _game_state: &G, _side: G::Side) -> bool {
+        false
+    }
+
+    /// Whether to offer a draw before moving
+    fn offer_draw(&mut self, _game_state: &G, _side: G::Side) -> bool {
+        false
+    }
+
+    /// Whether to give up instead of moving
+    fn resign(&mut self, _game_state: &G, _side: G::Side) -> bool {
+        false
+    }
+}
+
+/// Names for every strategy, each game decides which ones it supports in `Game::strategy`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum StrategyKind {
+    #[default]
+    Random,
+    Minimax,
+    Threats,
+    Mcts,
+    Engine, // An external UCI engine
+    Human,  // Moves typed on stdin, only ever played by the client
+}
+
+impl StrategyKind {
+    // Wire form is the index, so new kinds go at the end
+    pub const ALL: [StrategyKind; 6] = [
+        StrategyKind::Random,
+        StrategyKind::Minimax,
+        StrategyKind::Threats,
+        StrategyKind::Mcts,
+        StrategyKind::Engine,
+        StrategyKind::Human,
+    ];
+}
+
+impl fmt::Display for StrategyKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let str = match self {
+            StrategyKind::Random => "random",
+            StrategyKind::Minimax => "minimax",
+            StrategyKind::Threats => "threats",
+            StrategyKind::Mcts => "mcts",
+            StrategyKind::Engine => "engine",
+            StrategyKind::Human => "human",
+        };
+        write!(f, "{}", str)
+    }
+}
+
+impl FromStr for StrategyKind {
+    type Err = anyhow::Error;
+    fn from_str(str: &str) -> Result<Self, Self::Err> {
+        StrategyKind::ALL
+            .into_iter()
+            .find(|kind| kind.to_string() == str)
+            .ok_or_else(|| {
+                let names = StrategyKind::ALL.map(|kind| kind.to_string());
+                anyhow!(
+                    "Unknown strategy {:?}, expected one of {}",
+                    str,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// Asks for each move on stdin, in the game's notation, until a legal one is typed
+pub struct HumanStrategy;
+
+impl<G: Game> Strategy<G> for HumanStrategy {
+    fn choose_move(
+        &mut self,
+        game_state: &G,
+        side: G::Side,
+        _budget: Option<SearchBudget>,
+    ) -> G::Move {
+        game_state.pretty_print();
+        let stdin = io::stdin();
+        loop {
+            print!("Your move as {}: ", side);
+            io::stdout().flush().expect("Failed to write stdout");
+            let mut line = String::new();
+            if stdin.read_line(&mut line).expect("Failed to read stdin") == 0 {
+                panic!("No more moves on stdin");
+            }
+            match game_state.parse_move_text(line.trim()) {
+                Some(game_move) if game_state.legal_moves().contains(&game_move) => {
+                    return game_move;
+                }
+                Some(_) => println!("Illegal move {:?}", line.trim()),
+                None => println!("Unrecognised move {:?}", line.trim()),
+            }
+        }
+    }
+}
+
+/// Any legal move, for every game
+pub struct RandomStrategy;
+
+impl<G: Game> Strategy<G> for RandomStrategy {
+    fn choose_move(
+        &mut self,
+        game_state: &G,
+        _side: G::Side,
+        _budget: Option<SearchBudget>,
+    ) -> G::Move {
+        game_state
+            .legal_moves()
+            .choose(&mut rand::rng())
+            .expect("No legal moves left")
+            .clone()
+    }
+}
@@ -1,12 +1,110 @@
 use anyhow::anyhow;
 use rand::seq::IndexedRandom;
-use std::fmt;
+use std::{fmt, str::FromStr};
+
+use crate::{
+    Message,
+    game::{Game, play_move},
+    protocol::{GameKind, ProtocolError, take_u32},
+    strategy::{RandomStrategy, SearchBudget, Strategy, StrategyKind},
+};
+
+mod mcts;
+mod minimax;
+mod threats;
+
+pub use mcts::{MctsConfig, MctsPlayer, tictactoe_mcts};
+pub use minimax::{best_move, tictactoe_best};
+pub use threats::{
+    Threat, ThreatKind, find_forced_win, find_threats, threat_move, tictactoe_threats,
+};
+
+// Keeps a full board within a single datagram
+pub const MAX_BOARD_SIZE: usize = 200;
+
+/// Board dimensions and how many in a row are needed to win
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TTTConfig {
+    pub rows: usize,
+    pub cols: usize,
+    pub win_condition: usize,
+}
+
+impl TTTConfig {
+    pub const CLASSIC: TTTConfig = TTTConfig {
+        rows: 3,
+        cols: 3,
+        win_condition: 3,
+    };
+    pub const GOMOKU: TTTConfig = TTTConfig {
+        rows: 15,
+        cols: 15,
+        win_condition: 5,
+    };
+
+    pub fn new(rows: usize, cols: usize, win_condition: usize) -> anyhow::Result<TTTConfig> {
+        if rows == 0 || cols == 0 || rows > MAX_BOARD_SIZE || cols > MAX_BOARD_SIZE {
+            return Err(anyhow!(
+                "Board must be between 1x1 and {0}x{0}, got {1}x{2}",
+                MAX_BOARD_SIZE,
+                rows,
+                cols
+            ));
+        }
+        if win_condition == 0 || win_condition > rows.max(cols) {
+            return Err(anyhow!(
+                "Win condition {} impossible on a {}x{} board",
+                win_condition,
+                rows,
+                cols
+            ));
+        }
+        Ok(TTTConfig {
+            rows,
+            cols,
+            win_condition,
+        })
+    }
 
-use crate::Message;
+    fn blocks(&self) -> usize {
+        self.rows * self.cols
+    }
+}
 
-// Implemented for possible extensibility
-const BOARD_SIZE: usize = 20;
-const WIN_CONDITION: usize = 10;
+impl Default for TTTConfig {
+    fn default() -> Self {
+        TTTConfig {
+            rows: 20,
+            cols: 20,
+            win_condition: 10,
+        }
+    }
+}
+
+impl fmt::Display for TTTConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}/{}", self.rows, self.cols, self.win_condition)
+    }
+}
+
+/// Parses "<rows>x<cols>/<win condition>", e.g. "15x15/5"
+impl FromStr for TTTConfig {
+    type Err = anyhow::Error;
+    fn from_str(str: &str) -> Result<Self, Self::Err> {
+        let parse = || {
+            let (size, win_condition) = str.split_once('/')?;
+            let (rows, cols) = size.split_once('x')?;
+            Some((
+                rows.parse().ok()?,
+                cols.parse().ok()?,
+                win_condition.parse().ok()?,
+            ))
+        };
+        let (rows, cols, win_condition) =
+            parse().ok_or_else(|| anyhow!("Invalid board config: '{}'", str))?;
+        TTTConfig::new(rows, cols, win_condition)
+    }
+}
 
 #[derive(Clone, Copy, PartialEq)]
 pub enum TTTBlockState {
@@ -25,12 +123,31 @@ impl TTTBlockState {
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub enum TTTPlayer {
+    #[default]
     Circle,
     Cross,
 }
 
+impl TTTPlayer {
+    pub fn opponent(self) -> TTTPlayer {
+        match self {
+            TTTPlayer::Circle => TTTPlayer::Cross,
+            TTTPlayer::Cross => TTTPlayer::Circle,
+        }
+    }
+}
+
+impl From<TTTPlayer> for TTTBlockState {
+    fn from(player: TTTPlayer) -> Self {
+        match player {
+            TTTPlayer::Circle => TTTBlockState::Circle,
+            TTTPlayer::Cross => TTTBlockState::Cross,
+        }
+    }
+}
+
 impl fmt::Display for TTTPlayer {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let str = match self {
@@ -41,7 +158,7 @@ impl fmt::Display for TTTPlayer {
     }
 }
 
-#[derive(PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum TTTGameResult {
     Draw,
     CircleWin,
@@ -58,16 +175,136 @@ impl fmt::Display for TTTGameResult {
     }
 }
 
+#[derive(Debug, PartialEq)]
+pub enum TTTMoveError {
+    OffBoard(usize, usize),
+    Occupied(usize, usize),
+    Overwritten(usize, usize),
+    WrongSymbol(usize, usize),
+    NoNewPiece,
+    TooManyPieces(usize),
+    ConfigMismatch(TTTConfig, TTTConfig),
+}
+
+impl fmt::Display for TTTMoveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OffBoard(px, py) => write!(f, "Move ({}, {}) is off the board", px, py),
+            Self::Occupied(px, py) => write!(f, "Block ({}, {}) is already taken", px, py),
+            Self::Overwritten(px, py) => {
+                write!(f, "Existing piece at ({}, {}) was changed", px, py)
+            }
+            Self::WrongSymbol(px, py) => {
+                write!(f, "Piece at ({}, {}) has the wrong symbol", px, py)
+            }
+            Self::NoNewPiece => write!(f, "No new piece was placed"),
+            Self::TooManyPieces(count) => write!(f, "{} new pieces placed, expected 1", count),
+            Self::ConfigMismatch(expected, actual) => {
+                write!(f, "Board is {}, expected {}", actual, expected)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TTTMoveError {}
+
+#[derive(Clone)]
 pub struct TTTGameState {
-    board: [[TTTBlockState; BOARD_SIZE]; BOARD_SIZE],
+    config: TTTConfig,
+    board: Vec<TTTBlockState>, // Row-major
 }
 
 impl TTTGameState {
     pub fn new() -> TTTGameState {
+        Self::with_config(TTTConfig::default())
+    }
+
+    pub fn with_config(config: TTTConfig) -> TTTGameState {
         TTTGameState {
-            board: [[TTTBlockState::Empty; BOARD_SIZE]; BOARD_SIZE],
+            config,
+            board: vec![TTTBlockState::Empty; config.blocks()],
+        }
+    }
+
+    pub fn config(&self) -> TTTConfig {
+        self.config
+    }
+
+    fn get(&self, px: usize, py: usize) -> TTTBlockState {
+        self.board[px * self.config.cols + py]
+    }
+
+    fn set(&mut self, px: usize, py: usize, block: TTTBlockState) {
+        self.board[px * self.config.cols + py] = block;
+    }
+
+    fn empty_blocks(&self) -> Vec<(usize, usize)> {
+        (0..self.config.rows)
+            .flat_map(|i| (0..self.config.cols).map(move |j| (i, j)))
+            .filter(|&(i, j)| self.get(i, j) == TTTBlockState::Empty)
+            .collect()
+    }
+
+    /// Places a piece for `player`, failing if the block is off the board or taken
+    pub fn apply_move(
+        &mut self,
+        (px, py): (usize, usize),
+        player: TTTPlayer,
+    ) -> Result<(), TTTMoveError> {
+        if px >= self.config.rows || py >= self.config.cols {
+            return Err(TTTMoveError::OffBoard(px, py));
+        }
+        if self.get(px, py) != TTTBlockState::Empty {
+            return Err(TTTMoveError::Occupied(px, py));
+        }
+        self.set(px, py, player.into());
+        Ok(())
+    }
+
+    /// Checks that `next` is this board plus exactly one new piece for `player`, returning its position
+    pub fn diff(
+        &self,
+        next: &TTTGameState,
+        player: TTTPlayer,
+    ) -> Result<(usize, usize), TTTMoveError> {
+        if self.config != next.config {
+            return Err(TTTMoveError::ConfigMismatch(self.config, next.config));
+        }
+
+        let symbol = TTTBlockState::from(player);
+        let mut new_pieces = vec![];
+
+        for i in 0..self.config.rows {
+            for j in 0..self.config.cols {
+                let (before, after) = (self.get(i, j), next.get(i, j));
+                if before == after {
+                    continue;
+                }
+                if before != TTTBlockState::Empty {
+                    return Err(TTTMoveError::Overwritten(i, j));
+                }
+                if after != symbol {
+                    return Err(TTTMoveError::WrongSymbol(i, j));
+                }
+                new_pieces.push((i, j));
+            }
+        }
+
+        match new_pieces[..] {
+            [] => Err(TTTMoveError::NoNewPiece),
+            [new_piece] => Ok(new_piece),
+            _ => Err(TTTMoveError::TooManyPieces(new_pieces.len())),
         }
     }
+
+    /// FNV-1a hash of the board, stable across processes so both ends can compare states
+    pub fn state_hash(&self) -> u64 {
+        self.to_string()
+            .bytes()
+            .fold(0xcbf29ce484222325, |hash, byte| {
+                (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+            })
+    }
 }
 
 impl Default for TTTGameState {
@@ -76,18 +313,24 @@ impl Default for TTTGameState {
     }
 }
 
+/// Parses "<config>:<board>", or a bare board for the default config
 impl TryFrom<String> for TTTGameState {
     type Error = anyhow::Error;
     fn try_from(str: String) -> Result<Self, Self::Error> {
-        if str.len() != (BOARD_SIZE * BOARD_SIZE) {
+        let (config, board) = match str.split_once(':') {
+            Some((config, board)) => (config.parse::<TTTConfig>()?, board),
+            None => (TTTConfig::default(), str.as_str()),
+        };
+
+        if board.len() != config.blocks() {
             return Err(anyhow!(
                 "String length incorrect: Expected {}, Actual {}",
-                BOARD_SIZE * BOARD_SIZE,
-                str.len()
+                config.blocks(),
+                board.len()
             ));
         }
 
-        let blocks = str
+        let board = board
             .chars()
             .map(|x| match x {
                 ' ' => Ok(TTTBlockState::Empty),
@@ -97,40 +340,20 @@ impl TryFrom<String> for TTTGameState {
             })
             .collect::<anyhow::Result<Vec<TTTBlockState>>>()?;
 
-        let mut board = [[TTTBlockState::Empty; BOARD_SIZE]; BOARD_SIZE];
-
-        for i in 0..BOARD_SIZE {
-            for j in 0usize..BOARD_SIZE {
-                board[i][j] = blocks[BOARD_SIZE * i + j];
-            }
-        }
-
-        Ok(TTTGameState { board })
+        Ok(TTTGameState { config, board })
     }
 }
 
 impl fmt::Display for TTTGameState {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let str = self
-            .board
-            .as_flattened()
-            .iter()
-            .map(|x| x.to_char())
-            .collect::<String>();
-        write!(f, "{}", str)
+        let str = self.board.iter().map(|x| x.to_char()).collect::<String>();
+        write!(f, "{}:{}", self.config, str)
     }
 }
 
-pub fn pretty_print_board(msg: &str) {
-    let extra_len = msg.len() - BOARD_SIZE * BOARD_SIZE; // "win"/"draw"
-    if extra_len > 0 {
-        println!("{}", &msg[0..extra_len]);
-    }
-
-    for row in 0..BOARD_SIZE {
-        let start = extra_len + row * BOARD_SIZE;
-        let end = start + BOARD_SIZE;
-        println!("{}", &msg[start..end]);
+pub fn pretty_print_board(game_state: &TTTGameState) {
+    for row in game_state.board.chunks(game_state.config.cols) {
+        println!("{}", row.iter().map(|x| x.to_char()).collect::<String>());
     }
 }
 
@@ -141,23 +364,29 @@ fn check_line(
     start_y: isize,
     direction: (isize, isize),
 ) -> bool {
+    let TTTConfig {
+        rows,
+        cols,
+        win_condition,
+    } = game_state.config;
+    let max_len = rows.max(cols);
     let (mut x, mut y) = (start_x, start_y);
-    let mut acc: u32 = 0;
+    let mut acc = 0;
 
-    for t in 0..BOARD_SIZE {
-        if x < 0 || x >= BOARD_SIZE as isize || y < 0 || y >= BOARD_SIZE as isize {
+    for t in 0..max_len {
+        if x < 0 || x >= rows as isize || y < 0 || y >= cols as isize {
             break;
         }
 
-        if game_state.board[x as usize][y as usize] == *current {
+        if game_state.get(x as usize, y as usize) == *current {
             acc += 1;
-            if acc == WIN_CONDITION as u32 {
+            if acc == win_condition {
                 return true;
             }
         } else {
             acc = 0;
-            // Max blocks left: BOARD_SIZE - t - 1
-            if BOARD_SIZE - t - 1 < WIN_CONDITION {
+            // Max blocks left: max_len - t - 1
+            if max_len - t - 1 < win_condition {
                 return false;
             }
         }
@@ -173,45 +402,44 @@ pub fn ttt_get_game_status(
     last_move: Option<(&usize, &usize)>,
 ) -> Option<TTTGameResult> {
     let directions: [(isize, isize); 4] = [(0, 1), (1, 0), (1, 1), (1, -1)]; // horizontal, vertical, two diagonals
-    let board = &game_state.board;
+    let TTTConfig {
+        rows,
+        cols,
+        win_condition,
+    } = game_state.config;
     let mut winner: Option<TTTBlockState> = None;
     let mut is_draw = true;
 
     if let Some((px, py)) = last_move {
         // Last move known - only check relevant lines
-        let player = &game_state.board[*px][*py];
+        let player = &game_state.get(*px, *py);
         if check_line(game_state, player, *px as isize, 0, (0, 1)) {
-            // Column
+            // Row
             winner = Some(*player);
         } else if check_line(game_state, player, 0, *py as isize, (1, 0)) {
-            // Row
+            // Column
             winner = Some(*player);
         } else {
-            // Diagonals
-            let max_diag_len = BOARD_SIZE - px.abs_diff(*py);
+            // Diagonals, starting from the edge of the board
             let diag_distance = px.min(py);
+            let (diag_x, diag_y) = (px - diag_distance, py - diag_distance);
+            let max_diag_len = (rows - diag_x).min(cols - diag_y);
 
-            let max_antidag_len =
-                BOARD_SIZE - (*px as isize + *py as isize - BOARD_SIZE as isize + 1).unsigned_abs();
-            let antidiag_distance = (BOARD_SIZE - px - 1).min(*py);
+            let antidiag_distance = (rows - px - 1).min(*py);
+            let (antidiag_x, antidiag_y) = (px + antidiag_distance, py - antidiag_distance);
+            let max_antidiag_len = (antidiag_x + 1).min(cols - antidiag_y);
 
-            if max_diag_len >= WIN_CONDITION
-                && check_line(
-                    game_state,
-                    player,
-                    (px - diag_distance) as isize,
-                    (py - diag_distance) as isize,
-                    (1, 1),
-                )
+            if max_diag_len >= win_condition
+                && check_line(game_state, player, diag_x as isize, diag_y as isize, (1, 1))
             {
                 // \ direction
                 winner = Some(*player);
-            } else if max_antidag_len >= WIN_CONDITION
+            } else if max_antidiag_len >= win_condition
                 && check_line(
                     game_state,
                     player,
-                    (px + antidiag_distance) as isize,
-                    (py - antidiag_distance) as isize,
+                    antidiag_x as isize,
+                    antidiag_y as isize,
                     (-1, 1),
                 )
             {
@@ -222,47 +450,36 @@ pub fn ttt_get_game_status(
         }
     } else {
         // No last move - full board check
-        'outer: for i in 0..BOARD_SIZE {
-            for j in 0..BOARD_SIZE {
-                if board[i][j] == TTTBlockState::Empty {
+        'outer: for i in 0..rows {
+            for j in 0..cols {
+                let current = game_state.get(i, j);
+                if current == TTTBlockState::Empty {
                     is_draw = false;
                     continue;
                 }
 
-                // Given board size n and win condition m,
-                //   a m-in-a-row cannot start anywhere between
-                //   (n-m+1, n-m+1) and (m-2, m-2)
-                // Example: n=5, m=4, cannot start in (2, 2)
-                #[allow(clippy::int_plus_one)]
-                if i >= BOARD_SIZE - WIN_CONDITION + 1
-                    && i <= WIN_CONDITION - 2
-                    && j >= BOARD_SIZE - WIN_CONDITION + 1
-                    && j <= WIN_CONDITION - 2
-                {
+                // Every line goes either down or right by win condition blocks,
+                //   so none can start in the bottom right corner
+                if i + win_condition > rows && j + win_condition > cols {
                     continue;
                 }
 
-                let current = &board[i][j];
                 for &(dx, dy) in &directions {
                     let mut failed = false; // Whether win condition fails
-                    for step in 1..WIN_CONDITION as isize {
+                    for step in 1..win_condition as isize {
                         let (px, py) = (i as isize + dx * step, j as isize + dy * step);
-                        if px < 0
-                            || px >= BOARD_SIZE as isize
-                            || py < 0
-                            || py >= BOARD_SIZE as isize
-                        {
+                        if px < 0 || px >= rows as isize || py < 0 || py >= cols as isize {
                             failed = true;
                             break;
                         }
-                        if &board[px as usize][py as usize] != current {
+                        if game_state.get(px as usize, py as usize) != current {
                             failed = true;
                             break;
                         }
                     }
                     if !failed {
                         is_draw = false;
-                        winner = Some(*current);
+                        winner = Some(current);
                         break 'outer;
                     }
                 }
@@ -281,43 +498,250 @@ pub fn ttt_get_game_status(
     })
 }
 
-#[cfg(any())]
-pub fn tictactoe_best(game_state: TTTGameState, player: TTTPlayer) {}
-
 pub fn tictactoe_rand(
-    mut game_state: TTTGameState,
+    game_state: &mut TTTGameState,
     player: &TTTPlayer,
-) -> ((usize, usize), Message) {
-    let mut empty_blocks = vec![];
+) -> ((usize, usize), Message<TTTGameState>) {
+    let empty_blocks = game_state.empty_blocks();
+    let chosen_move = *empty_blocks.choose(&mut rand::rng()).unwrap();
+    (chosen_move, play_move(game_state, *player, chosen_move))
+}
 
-    for i in 0..BOARD_SIZE {
-        for j in 0..BOARD_SIZE {
-            if game_state.board[i][j] == TTTBlockState::Empty {
-                empty_blocks.push((i, j));
-            }
+/// `tictactoe_best`, with the budget used when none is given
+pub struct MinimaxStrategy(pub SearchBudget);
+
+impl Strategy<TTTGameState> for MinimaxStrategy {
+    fn choose_move(
+        &mut self,
+        game_state: &TTTGameState,
+        side: TTTPlayer,
+        budget: Option<SearchBudget>,
+    ) -> (usize, usize) {
+        best_move(game_state, side, budget.unwrap_or(self.0))
+    }
+}
+
+/// `tictactoe_threats`, with the budget used when none is given
+pub struct ThreatStrategy(pub SearchBudget);
+
+impl Strategy<TTTGameState> for ThreatStrategy {
+    fn choose_move(
+        &mut self,
+        game_state: &TTTGameState,
+        side: TTTPlayer,
+        budget: Option<SearchBudget>,
+    ) -> (usize, usize) {
+        threat_move(game_state, side, budget.unwrap_or(self.0))
+    }
+}
+
+impl Game for TTTGameState {
+    type Move = (usize, usize);
+    type Side = TTTPlayer;
+    type Config = TTTConfig;
+    type Outcome = TTTGameResult;
+    type Referee = ();
+    type MoveError = TTTMoveError;
+
+    const KIND: GameKind = GameKind::TicTacToe;
+
+    fn with_config(config: TTTConfig) -> Self {
+        TTTGameState::with_config(config)
+    }
+
+    fn config(&self) -> TTTConfig {
+        self.config
+    }
+
+    fn opponent(side: TTTPlayer) -> TTTPlayer {
+        side.opponent()
+    }
+
+    fn side_to_move(&self) -> TTTPlayer {
+        // Whoever receives the new game moves first, and always plays cross
+        let crosses = self.board.iter().filter(|&&x| x == TTTBlockState::Cross);
+        if crosses.count() * 2 > self.moves_played() {
+            TTTPlayer::Circle
+        } else {
+            TTTPlayer::Cross
         }
     }
 
-    let (px, py) = empty_blocks.choose(&mut rand::rng()).unwrap();
-    game_state.board[*px][*py] = match player {
-        TTTPlayer::Circle => TTTBlockState::Circle,
-        TTTPlayer::Cross => TTTBlockState::Cross,
-    };
+    fn moves_played(&self) -> usize {
+        self.board
+            .iter()
+            .filter(|&&x| x != TTTBlockState::Empty)
+            .count()
+    }
 
-    if let Some(result) = ttt_get_game_status(&game_state, Some((px, py))) {
-        return (
-            (*px, *py),
-            Message::GameOver(game_state.to_string(), result.to_string()),
-        );
+    fn legal_moves(&self) -> Vec<(usize, usize)> {
+        match self.status() {
+            Some(_) => vec![],
+            None => self.empty_blocks(),
+        }
+    }
+
+    fn apply_move(&mut self, &block: &(usize, usize), side: TTTPlayer) -> Result<(), TTTMoveError> {
+        TTTGameState::apply_move(self, block, side)
+    }
+
+    fn diff(&self, next: &Self, side: TTTPlayer) -> Result<(usize, usize), TTTMoveError> {
+        TTTGameState::diff(self, next, side)
+    }
+
+    fn status(&self) -> Option<TTTGameResult> {
+        ttt_get_game_status(self, None)
+    }
+
+    fn winner(outcome: &TTTGameResult) -> Option<TTTPlayer> {
+        match outcome {
+            TTTGameResult::Draw => None,
+            TTTGameResult::CircleWin => Some(TTTPlayer::Circle),
+            TTTGameResult::CrossWin => Some(TTTPlayer::Cross),
+        }
     }
 
-    if empty_blocks.len() == 1 {
-        // Draw - last move made, since ttt_get_game_status above only checks changed lines and cannot detect draws
-        return (
-            (*px, *py),
-            Message::GameOver(game_state.to_string(), TTTGameResult::Draw.to_string()),
+    fn state_hash(&self) -> u64 {
+        TTTGameState::state_hash(self)
+    }
+
+    fn pretty_print(&self) {
+        pretty_print_board(self);
+    }
+
+    fn strategy(kind: StrategyKind) -> Option<Box<dyn Strategy<Self>>> {
+        match kind {
+            StrategyKind::Random => Some(Box::new(RandomStrategy)),
+            StrategyKind::Minimax => Some(Box::new(MinimaxStrategy(SearchBudget::default()))),
+            StrategyKind::Threats => Some(Box::new(ThreatStrategy(SearchBudget::default()))),
+            StrategyKind::Mcts => Some(Box::new(MctsPlayer::new(MctsConfig::default()))),
+            StrategyKind::Engine | StrategyKind::Human => None,
+        }
+    }
+
+    fn parse(str: &str) -> anyhow::Result<Self> {
+        TTTGameState::try_from(str.to_string())
+    }
+
+    fn format_move((px, py): &(usize, usize)) -> String {
+        format!("{},{}", px, py)
+    }
+
+    fn parse_move(str: &str) -> Option<(usize, usize)> {
+        let (px, py) = str.split_once(',')?;
+        Some((px.parse().ok()?, py.parse().ok()?))
+    }
+
+    fn parse_side(str: &str) -> Option<TTTPlayer> {
+        match str {
+            "o" => Some(TTTPlayer::Circle),
+            "x" => Some(TTTPlayer::Cross),
+            _ => None,
+        }
+    }
+
+    fn encode_side(side: TTTPlayer) -> u8 {
+        match side {
+            TTTPlayer::Circle => 0,
+            TTTPlayer::Cross => 1,
+        }
+    }
+
+    fn decode_side(byte: u8) -> Result<TTTPlayer, ProtocolError> {
+        match byte {
+            0 => Ok(TTTPlayer::Circle),
+            1 => Ok(TTTPlayer::Cross),
+            _ => Err(ProtocolError::InvalidPlayer(byte)),
+        }
+    }
+
+    fn encode_config(config: &TTTConfig, bytes: &mut Vec<u8>) {
+        for dimension in [config.rows, config.cols, config.win_condition] {
+            bytes.extend_from_slice(&(dimension as u32).to_be_bytes());
+        }
+    }
+
+    fn decode_config(bytes: &mut &[u8]) -> Result<TTTConfig, ProtocolError> {
+        let rows = take_u32(bytes)? as usize;
+        let cols = take_u32(bytes)? as usize;
+        let win_condition = take_u32(bytes)? as usize;
+        TTTConfig::new(rows, cols, win_condition)
+            .map_err(|e| ProtocolError::InvalidGameConfig(e.to_string()))
+    }
+
+    fn encode_move((px, py): &(usize, usize), bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&(*px as u32).to_be_bytes());
+        bytes.extend_from_slice(&(*py as u32).to_be_bytes());
+    }
+
+    fn decode_move(mut bytes: &[u8]) -> Result<(usize, usize), ProtocolError> {
+        Ok((
+            take_u32(&mut bytes)? as usize,
+            take_u32(&mut bytes)? as usize,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff() {
+        let mut before = TTTGameState::new();
+        before.apply_move((0, 0), TTTPlayer::Circle).unwrap();
+
+        let mut after = before.clone();
+        after.apply_move((1, 1), TTTPlayer::Cross).unwrap();
+        assert_eq!(before.diff(&after, TTTPlayer::Cross), Ok((1, 1)));
+        assert_eq!(
+            before.diff(&after, TTTPlayer::Circle),
+            Err(TTTMoveError::WrongSymbol(1, 1))
+        );
+        assert_eq!(
+            before.diff(&before, TTTPlayer::Cross),
+            Err(TTTMoveError::NoNewPiece)
+        );
+
+        let mut ten_crosses = before.clone();
+        for i in 0..10 {
+            ten_crosses.apply_move((i, 5), TTTPlayer::Cross).unwrap();
+        }
+        assert_eq!(
+            before.diff(&ten_crosses, TTTPlayer::Cross),
+            Err(TTTMoveError::TooManyPieces(10))
+        );
+
+        let mut overwritten = before.clone();
+        overwritten.set(0, 0, TTTBlockState::Cross);
+        assert_eq!(
+            before.diff(&overwritten, TTTPlayer::Cross),
+            Err(TTTMoveError::Overwritten(0, 0))
         );
     }
 
-    ((*px, *py), Message::GameMsg(game_state.to_string()))
+    #[test]
+    fn test_game_status() {
+        let config = TTTConfig::new(4, 6, 4).unwrap();
+        let lines: [&[(usize, usize)]; 4] = [
+            &[(1, 1), (1, 2), (1, 3), (1, 4)],
+            &[(0, 5), (1, 5), (2, 5), (3, 5)],
+            &[(0, 2), (1, 3), (2, 4), (3, 5)],
+            &[(3, 1), (2, 2), (1, 3), (0, 4)],
+        ];
+        for line in lines {
+            let mut game_state = TTTGameState::with_config(config);
+            for (n, &block) in line.iter().enumerate() {
+                assert!(ttt_get_game_status(&game_state, None).is_none());
+                game_state.apply_move(block, TTTPlayer::Cross).unwrap();
+                let (px, py) = block;
+                let expected = (n == 3).then_some(TTTGameResult::CrossWin);
+                assert!(ttt_get_game_status(&game_state, Some((&px, &py))) == expected);
+            }
+            assert!(ttt_get_game_status(&game_state, None) == Some(TTTGameResult::CrossWin));
+        }
+
+        let draw = TTTGameState::try_from("3x3/3:xoxxoooxx".to_string()).unwrap();
+        assert!(ttt_get_game_status(&draw, None) == Some(TTTGameResult::Draw));
+    }
 }
@@ -0,0 +1,358 @@
+use std::time::Instant;
+
+use rand::{Rng, SeedableRng, rngs::StdRng, seq::SliceRandom};
+
+use super::{TTTBlockState, TTTGameState, TTTPlayer, ttt_get_game_status};
+use crate::{
+    Message,
+    game::play_move,
+    strategy::{SearchBudget, Strategy},
+};
+
+const UNLIMITED_ITERATIONS: u64 = 100_000;
+
+/// Settings for `MctsPlayer` - `budget.max_nodes` limits the number of playouts
+#[derive(Clone, Copy, Debug)]
+pub struct MctsConfig {
+    pub budget: SearchBudget,
+    pub exploration: f64, // UCT constant, higher explores more
+    pub seed: Option<u64>,
+}
+
+impl Default for MctsConfig {
+    fn default() -> Self {
+        MctsConfig {
+            budget: SearchBudget::default(),
+            exploration: std::f64::consts::SQRT_2,
+            seed: None,
+        }
+    }
+}
+
+struct Node {
+    block: (usize, usize), // Move leading here, unused for the root
+    player: TTTPlayer,     // Who made that move
+    parent: Option<usize>,
+    children: Vec<usize>,
+    untried: Vec<(usize, usize)>,
+    terminal: bool,
+    visits: u32,
+    score: f64, // For `player` - 1 per win, 0.5 per draw
+}
+
+impl Node {
+    fn new(
+        block: (usize, usize),
+        player: TTTPlayer,
+        parent: Option<usize>,
+        board: &TTTGameState,
+        rng: &mut StdRng,
+    ) -> Node {
+        let terminal =
+            parent.is_some() && ttt_get_game_status(board, Some((&block.0, &block.1))).is_some();
+        let mut untried = if terminal {
+            vec![]
+        } else {
+            board.empty_blocks()
+        };
+        untried.shuffle(rng);
+
+        Node {
+            block,
+            player,
+            parent,
+            children: vec![],
+            untried,
+            terminal,
+            visits: 0,
+            score: 0.0,
+        }
+    }
+}
+
+/// UCT player which keeps its tree between moves of the same game
+pub struct MctsPlayer {
+    config: MctsConfig,
+    rng: StdRng,
+    nodes: Vec<Node>,
+    board: TTTGameState, // Position at the root
+}
+
+impl MctsPlayer {
+    pub fn new(config: MctsConfig) -> MctsPlayer {
+        let rng = match config.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_rng(&mut rand::rng()),
+        };
+        MctsPlayer {
+            config,
+            rng,
+            nodes: vec![],
+            board: TTTGameState::new(),
+        }
+    }
+
+    /// Moves the root to `game_state` if it is in the tree, otherwise starts a new tree
+    fn reuse_tree(&mut self, game_state: &TTTGameState, player: TTTPlayer) {
+        let mut root =
+            (!self.nodes.is_empty() && self.board.config == game_state.config).then_some(0);
+
+        // Walk down through every piece placed since the last search
+        while let Some(node) = root
+            && self.board.board != game_state.board
+        {
+            let next = self.nodes[node].children.iter().copied().find(|&child| {
+                let (px, py) = self.nodes[child].block;
+                game_state.get(px, py) == self.nodes[child].player.into()
+                    && self.board.get(px, py) == TTTBlockState::Empty
+            });
+            if let Some(child) = next {
+                let (px, py) = self.nodes[child].block;
+                self.board.set(px, py, self.nodes[child].player.into());
+            }
+            root = next;
+        }
+
+        match root {
+            Some(root) if self.nodes[root].player != player => self.prune(root),
+            _ => {
+                self.board = game_state.clone();
+                self.nodes = vec![Node::new(
+                    (0, 0),
+                    player.opponent(),
+                    None,
+                    &self.board,
+                    &mut self.rng,
+                )];
+            }
+        }
+    }
+
+    /// Keeps only the subtree below `root`, which becomes node 0
+    fn prune(&mut self, root: usize) {
+        let mut old_nodes = std::mem::take(&mut self.nodes)
+            .into_iter()
+            .map(Some)
+            .collect::<Vec<Option<Node>>>();
+        let mut stack = vec![(root, None)];
+
+        while let Some((old, parent)) = stack.pop() {
+            let mut node = old_nodes[old].take().unwrap();
+            let index = self.nodes.len();
+            node.parent = parent;
+            for &child in node.children.iter().rev() {
+                stack.push((child, Some(index)));
+            }
+            node.children.clear();
+            if let Some(parent) = parent {
+                self.nodes[parent].children.push(index);
+            }
+            self.nodes.push(node);
+        }
+    }
+
+    fn select_child(&self, node: usize) -> usize {
+        let log_visits = (self.nodes[node].visits as f64).ln();
+        let uct = |child: usize| {
+            let child = &self.nodes[child];
+            child.score / child.visits as f64
+                + self.config.exploration * (log_visits / child.visits as f64).sqrt()
+        };
+        self.nodes[node]
+            .children
+            .iter()
+            .copied()
+            .max_by(|&a, &b| uct(a).total_cmp(&uct(b)))
+            .unwrap()
+    }
+
+    /// Plays random moves until the game ends, returning the winner
+    fn playout(&mut self, board: &mut TTTGameState, last_player: TTTPlayer) -> Option<TTTPlayer> {
+        let mut empty_blocks = board.empty_blocks();
+        let mut player = last_player;
+        loop {
+            if empty_blocks.is_empty() {
+                return None;
+            }
+            player = player.opponent();
+            let (px, py) = empty_blocks.swap_remove(self.rng.random_range(0..empty_blocks.len()));
+            board.set(px, py, player.into());
+            if ttt_get_game_status(board, Some((&px, &py))).is_some() {
+                return Some(player);
+            }
+        }
+    }
+
+    fn iterate(&mut self) {
+        let mut board = self.board.clone();
+        let mut node = 0;
+
+        // Selection
+        while self.nodes[node].untried.is_empty() && !self.nodes[node].children.is_empty() {
+            node = self.select_child(node);
+            let (px, py) = self.nodes[node].block;
+            board.set(px, py, self.nodes[node].player.into());
+        }
+
+        // Expansion
+        if let Some(block) = self.nodes[node].untried.pop() {
+            let player = self.nodes[node].player.opponent();
+            board.set(block.0, block.1, player.into());
+            let child = Node::new(block, player, Some(node), &board, &mut self.rng);
+            self.nodes.push(child);
+            let index = self.nodes.len() - 1;
+            self.nodes[node].children.push(index);
+            node = index;
+        }
+
+        // Simulation
+        let winner = if self.nodes[node].terminal {
+            Some(self.nodes[node].player)
+        } else {
+            self.playout(&mut board, self.nodes[node].player)
+        };
+
+        // Backpropagation
+        let mut current = Some(node);
+        while let Some(index) = current {
+            let node = &mut self.nodes[index];
+            node.visits += 1;
+            node.score += match winner {
+                Some(winner) if winner == node.player => 1.0,
+                Some(_) => 0.0,
+                None => 0.5,
+            };
+            current = node.parent;
+        }
+    }
+
+    /// Most visited move for `player` after searching within the configured budget
+    pub fn choose_move(&mut self, game_state: &TTTGameState, player: TTTPlayer) -> (usize, usize) {
+        self.choose_move_within(game_state, player, self.config.budget)
+    }
+
+    /// Same as `choose_move`, with a different budget for this move only
+    pub fn choose_move_within(
+        &mut self,
+        game_state: &TTTGameState,
+        player: TTTPlayer,
+        budget: SearchBudget,
+    ) -> (usize, usize) {
+        self.reuse_tree(game_state, player);
+
+        let deadline = budget.max_time.map(|max_time| Instant::now() + max_time);
+        // The search never finishes by itself, so an unlimited budget gets a fixed number of playouts
+        let max_iterations = budget
+            .max_nodes
+            .or(deadline.is_none().then_some(UNLIMITED_ITERATIONS));
+        let mut iterations = 0;
+        loop {
+            self.iterate();
+            iterations += 1;
+
+            if max_iterations.is_some_and(|max_iterations| iterations >= max_iterations)
+                || deadline.is_some_and(|deadline| Instant::now() >= deadline)
+            {
+                break;
+            }
+        }
+
+        self.nodes[0]
+            .children
+            .iter()
+            .max_by_key(|&&child| self.nodes[child].visits)
+            .map(|&child| self.nodes[child].block)
+            .unwrap()
+    }
+
+    /// Drops the tree, e.g. between games
+    pub fn reset(&mut self) {
+        self.nodes.clear();
+    }
+
+    /// Plays the chosen move, see `tictactoe_rand` for the return value
+    pub fn play(
+        &mut self,
+        game_state: &mut TTTGameState,
+        player: &TTTPlayer,
+    ) -> ((usize, usize), Message<TTTGameState>) {
+        let chosen_move = self.choose_move(game_state, *player);
+        (chosen_move, play_move(game_state, *player, chosen_move))
+    }
+}
+
+impl Strategy<TTTGameState> for MctsPlayer {
+    fn choose_move(
+        &mut self,
+        game_state: &TTTGameState,
+        side: TTTPlayer,
+        budget: Option<SearchBudget>,
+    ) -> (usize, usize) {
+        let budget = budget.unwrap_or(self.config.budget);
+        self.choose_move_within(game_state, side, budget)
+    }
+
+    fn new_game(&mut self) {
+        self.reset();
+    }
+}
+
+/// Plays a move by Monte Carlo tree search, without keeping the tree for later moves
+pub fn tictactoe_mcts(
+    game_state: &mut TTTGameState,
+    player: &TTTPlayer,
+    config: MctsConfig,
+) -> ((usize, usize), Message<TTTGameState>) {
+    MctsPlayer::new(config).play(game_state, player)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tictactoe::TTTConfig;
+
+    fn seeded(seed: u64) -> MctsConfig {
+        MctsConfig {
+            budget: SearchBudget::nodes(5_000),
+            seed: Some(seed),
+            ..MctsConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_takes_win_and_blocks() {
+        let mut board = TTTGameState::with_config(TTTConfig::CLASSIC);
+        board.apply_move((0, 0), TTTPlayer::Circle).unwrap();
+        board.apply_move((1, 1), TTTPlayer::Cross).unwrap();
+        board.apply_move((0, 1), TTTPlayer::Circle).unwrap();
+
+        // Cross must block the top row
+        let mut player = MctsPlayer::new(seeded(1));
+        assert_eq!(player.choose_move(&board, TTTPlayer::Cross), (0, 2));
+
+        // Circle then has to block the diagonal, and Cross wins if it does not
+        board.apply_move((0, 2), TTTPlayer::Cross).unwrap();
+        assert_eq!(player.choose_move(&board, TTTPlayer::Circle), (2, 0));
+        board.apply_move((1, 0), TTTPlayer::Circle).unwrap();
+        assert_eq!(player.choose_move(&board, TTTPlayer::Cross), (2, 0));
+    }
+
+    #[test]
+    fn test_reproducible_and_reuses_tree() {
+        let board = TTTGameState::with_config(TTTConfig::CLASSIC);
+        let first = MctsPlayer::new(seeded(7)).choose_move(&board, TTTPlayer::Circle);
+        let mut player = MctsPlayer::new(seeded(7));
+        assert_eq!(player.choose_move(&board, TTTPlayer::Circle), first);
+
+        let mut board = board.clone();
+        board.apply_move(first, TTTPlayer::Circle).unwrap();
+        let reply = board.empty_blocks()[0];
+        board.apply_move(reply, TTTPlayer::Cross).unwrap();
+
+        // The subtree for the position reached is kept
+        player.reuse_tree(&board, TTTPlayer::Circle);
+        assert!(player.nodes[0].visits > 0);
+        assert!(player.board.board == board.board);
+        assert!(player.nodes[0].parent.is_none());
+    }
+}
@@ -0,0 +1,333 @@
+use std::time::Instant;
+
+use super::{TTTBlockState, TTTGameState, TTTPlayer, ttt_get_game_status};
+use crate::{Message, game::play_move, strategy::SearchBudget};
+
+const WIN_SCORE: i64 = 1 << 40;
+const INFINITY: i64 = WIN_SCORE * 2;
+// Windows count at most this many stones, so even a 200x200 board's heuristic stays below a win
+const MAX_WEIGHTED_STONES: isize = 10;
+
+// Only blocks this close to an existing piece are searched
+const CANDIDATE_RADIUS: usize = 2;
+
+struct Search {
+    deadline: Option<Instant>,
+    max_nodes: Option<u64>,
+    nodes: u64,
+    aborted: bool,
+}
+
+impl Search {
+    fn new(budget: SearchBudget) -> Search {
+        Search {
+            deadline: budget.max_time.map(|max_time| Instant::now() + max_time),
+            max_nodes: budget.max_nodes,
+            nodes: 0,
+            aborted: false,
+        }
+    }
+
+    fn out_of_budget(&mut self) -> bool {
+        self.nodes += 1;
+        if let Some(max_nodes) = self.max_nodes
+            && self.nodes >= max_nodes
+        {
+            self.aborted = true;
+        }
+        // Checking the clock is comparatively slow
+        if let Some(deadline) = self.deadline
+            && self.nodes.is_multiple_of(1024)
+            && Instant::now() >= deadline
+        {
+            self.aborted = true;
+        }
+        self.aborted
+    }
+
+    /// Score of `player` placing a piece at `block`, searching `depth` plies including this one
+    #[allow(clippy::too_many_arguments)]
+    fn play(
+        &mut self,
+        board: &mut TTTGameState,
+        player: TTTPlayer,
+        (px, py): (usize, usize),
+        depth: usize,
+        alpha: i64,
+        beta: i64,
+        ply: usize,
+        empty_count: usize,
+    ) -> i64 {
+        if self.out_of_budget() {
+            return 0;
+        }
+
+        board.set(px, py, player.into());
+        let score = if ttt_get_game_status(board, Some((&px, &py))).is_some() {
+            WIN_SCORE - ply as i64 // Prefer quicker wins
+        } else if empty_count == 1 {
+            0 // Draw
+        } else if depth == 1 {
+            evaluate(board, player)
+        } else {
+            -self.negamax(
+                board,
+                player.opponent(),
+                depth - 1,
+                -beta,
+                -alpha,
+                ply + 1,
+                empty_count - 1,
+            )
+        };
+        board.set(px, py, TTTBlockState::Empty);
+        score
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn negamax(
+        &mut self,
+        board: &mut TTTGameState,
+        player: TTTPlayer,
+        depth: usize,
+        mut alpha: i64,
+        beta: i64,
+        ply: usize,
+        empty_count: usize,
+    ) -> i64 {
+        let mut best = -INFINITY;
+        for block in candidate_moves(board) {
+            let score = self.play(board, player, block, depth, alpha, beta, ply, empty_count);
+            if self.aborted {
+                return 0;
+            }
+            best = best.max(score);
+            alpha = alpha.max(score);
+            if alpha >= beta {
+                break;
+            }
+        }
+        best
+    }
+}
+
+/// Empty blocks near existing pieces, most promising first
+fn candidate_moves(board: &TTTGameState) -> Vec<(usize, usize)> {
+    let (rows, cols) = (board.config.rows, board.config.cols);
+    let (centre_x, centre_y) = (rows / 2, cols / 2);
+    let mut candidates = vec![];
+
+    for i in 0..rows {
+        for j in 0..cols {
+            if board.get(i, j) != TTTBlockState::Empty {
+                continue;
+            }
+
+            // Adjacent pieces count double
+            let mut neighbours = 0;
+            for x in i.saturating_sub(CANDIDATE_RADIUS)..(i + CANDIDATE_RADIUS + 1).min(rows) {
+                for y in j.saturating_sub(CANDIDATE_RADIUS)..(j + CANDIDATE_RADIUS + 1).min(cols) {
+                    if board.get(x, y) != TTTBlockState::Empty {
+                        neighbours += if x.abs_diff(i) <= 1 && y.abs_diff(j) <= 1 {
+                            2
+                        } else {
+                            1
+                        };
+                    }
+                }
+            }
+
+            if neighbours > 0 {
+                let centre_distance = i.abs_diff(centre_x) + j.abs_diff(centre_y);
+                candidates.push((neighbours, centre_distance, (i, j)));
+            }
+        }
+    }
+
+    if candidates.is_empty() {
+        // Empty board
+        return vec![(centre_x, centre_y)];
+    }
+
+    candidates.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+    candidates.into_iter().map(|(_, _, block)| block).collect()
+}
+
+/// Heuristic score for `player`, who has just moved, from every k-block window still open to one side
+pub(super) fn evaluate(board: &TTTGameState, player: TTTPlayer) -> i64 {
+    let (rows, cols, win_condition) = (
+        board.config.rows as isize,
+        board.config.cols as isize,
+        board.config.win_condition as isize,
+    );
+    let own = TTTBlockState::from(player);
+    // Long lines only count the stones nearest to completing them
+    let weight = |stones: isize| 2 * (stones - (win_condition - MAX_WEIGHTED_STONES).max(0)).max(0);
+    let mut score = 0;
+
+    for (dx, dy) in [(0, 1), (1, 0), (1, 1), (1, -1)] {
+        for i in 0..rows {
+            for j in 0..cols {
+                let (end_x, end_y) = (i + dx * (win_condition - 1), j + dy * (win_condition - 1));
+                if end_x >= rows || end_y < 0 || end_y >= cols {
+                    continue;
+                }
+
+                let (mut mine, mut theirs) = (0, 0);
+                for step in 0..win_condition {
+                    match board.get((i + dx * step) as usize, (j + dy * step) as usize) {
+                        TTTBlockState::Empty => {}
+                        block if block == own => mine += 1,
+                        _ => theirs += 1,
+                    }
+                }
+
+                if theirs == 0 && mine > 0 {
+                    score += 1 << weight(mine);
+                } else if mine == 0 && theirs > 0 {
+                    // The opponent moves next, so their lines are worth more
+                    score -= 2 << weight(theirs);
+                }
+            }
+        }
+    }
+    score
+}
+
+/// Best block for `player` by iteratively deepened negamax with alpha-beta pruning
+pub fn best_move(
+    game_state: &TTTGameState,
+    player: TTTPlayer,
+    budget: SearchBudget,
+) -> (usize, usize) {
+    let mut board = game_state.clone();
+    let mut moves = candidate_moves(&board);
+    let empty_count = board.empty_blocks().len();
+    let mut search = Search::new(budget);
+    let mut best = moves[0];
+
+    for depth in 1..=empty_count {
+        let (mut alpha, beta) = (-INFINITY, INFINITY);
+        let mut depth_best = (-INFINITY, moves[0]);
+
+        for &block in &moves {
+            let score = search.play(
+                &mut board,
+                player,
+                block,
+                depth,
+                alpha,
+                beta,
+                0,
+                empty_count,
+            );
+            if search.aborted {
+                break;
+            }
+            if score > depth_best.0 {
+                depth_best = (score, block);
+            }
+            alpha = alpha.max(score);
+        }
+
+        if search.aborted {
+            break; // Incomplete iteration, keep the previous result
+        }
+
+        best = depth_best.1;
+        // Search the best move first at the next depth
+        moves.retain(|&block| block != best);
+        moves.insert(0, best);
+
+        if depth_best.0.abs() >= WIN_SCORE - empty_count as i64 {
+            break; // Forced result found
+        }
+    }
+
+    best
+}
+
+/// Plays the best move found within the budget, see `tictactoe_rand` for the return value
+pub fn tictactoe_best(
+    game_state: &mut TTTGameState,
+    player: &TTTPlayer,
+    budget: SearchBudget,
+) -> ((usize, usize), Message<TTTGameState>) {
+    let chosen_move = best_move(game_state, *player, budget);
+    (chosen_move, play_move(game_state, *player, chosen_move))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tictactoe::TTTConfig;
+
+    fn winner(board: &TTTGameState, (px, py): (usize, usize)) -> bool {
+        ttt_get_game_status(board, Some((&px, &py))).is_some()
+    }
+
+    /// Plays every possible opponent reply, failing if the bot ever loses
+    fn assert_never_loses(board: &mut TTTGameState, bot: TTTPlayer, to_move: TTTPlayer) {
+        if board.empty_blocks().is_empty() {
+            return;
+        }
+        if to_move == bot {
+            let block = best_move(board, bot, SearchBudget::unlimited());
+            board.apply_move(block, bot).unwrap();
+            if !winner(board, block) {
+                assert_never_loses(board, bot, bot.opponent());
+            }
+            board.set(block.0, block.1, TTTBlockState::Empty);
+        } else {
+            for block in board.empty_blocks() {
+                board.apply_move(block, to_move).unwrap();
+                assert!(!winner(board, block), "Bot lost:\n{}", board);
+                assert_never_loses(board, bot, bot);
+                board.set(block.0, block.1, TTTBlockState::Empty);
+            }
+        }
+    }
+
+    #[test]
+    fn test_never_loses_classic() {
+        for bot in [TTTPlayer::Circle, TTTPlayer::Cross] {
+            let mut board = TTTGameState::with_config(TTTConfig::CLASSIC);
+            assert_never_loses(&mut board, bot, TTTPlayer::Circle);
+        }
+    }
+
+    #[test]
+    fn test_takes_win_and_blocks() {
+        let config = TTTConfig::GOMOKU;
+        let mut board = TTTGameState::with_config(config);
+        for j in 5..9 {
+            board.apply_move((7, j), TTTPlayer::Cross).unwrap();
+        }
+        board.apply_move((7, 4), TTTPlayer::Circle).unwrap();
+
+        // Cross wins immediately, Circle has to block the open end
+        let (block, msg) = tictactoe_best(
+            &mut board.clone(),
+            &TTTPlayer::Cross,
+            SearchBudget::nodes(10_000),
+        );
+        assert_eq!(block, (7, 9));
+        assert!(matches!(msg, Message::GameOver(_, result) if result == "win"));
+        let block = best_move(&board, TTTPlayer::Circle, SearchBudget::nodes(10_000));
+        assert_eq!(block, (7, 9));
+    }
+
+    #[test]
+    fn test_long_win_condition() {
+        // One stone short of forty in a row, which scores below a win
+        let config = TTTConfig::new(1, 40, 40).unwrap();
+        let mut board = TTTGameState::with_config(config);
+        for j in 0..39 {
+            board.apply_move((0, j), TTTPlayer::Cross).unwrap();
+        }
+        let score = evaluate(&board, TTTPlayer::Cross);
+        assert!(score > 0 && score < WIN_SCORE - 40);
+        let block = best_move(&board, TTTPlayer::Cross, SearchBudget::nodes(1_000));
+        assert_eq!(block, (0, 39));
+    }
+}
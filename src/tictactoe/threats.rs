@@ -0,0 +1,437 @@
+use std::{cmp::Reverse, time::Instant};
+
+use super::{TTTBlockState, TTTGameState, TTTPlayer, minimax::best_move};
+use crate::{Message, game::play_move, strategy::SearchBudget};
+
+// Attacker moves in a row of fours (VCF) or of any threats (VCT)
+const VCF_DEPTH: usize = 12;
+const VCT_DEPTH: usize = 3;
+
+/// How close a single move brings a player to winning, least severe first
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ThreatKind {
+    OpenThree,    // Next move can make a straight four
+    Four,         // One block left to win, which can be blocked
+    StraightFour, // Two or more blocks left to win, which cannot all be blocked
+    Win,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Threat {
+    pub kind: ThreatKind,
+    pub block: (usize, usize), // Move which creates the threat
+}
+
+/// Every k-block window on the board, with piece counts kept up to date as moves are tried
+struct ThreatBoard {
+    board: TTTGameState,
+    win_condition: usize,
+    windows: Vec<Vec<usize>>,
+    cell_windows: Vec<Vec<usize>>,
+    counts: Vec<[usize; 2]>, // Circle, Cross
+    deadline: Option<Instant>,
+    max_nodes: Option<u64>,
+    nodes: u64,
+    aborted: bool,
+}
+
+fn side(player: TTTPlayer) -> usize {
+    match player {
+        TTTPlayer::Circle => 0,
+        TTTPlayer::Cross => 1,
+    }
+}
+
+impl ThreatBoard {
+    fn new(game_state: &TTTGameState, budget: SearchBudget) -> ThreatBoard {
+        let config = game_state.config;
+        let (rows, cols, win_condition) = (
+            config.rows as isize,
+            config.cols as isize,
+            config.win_condition as isize,
+        );
+        let mut windows = vec![];
+
+        for (dx, dy) in [(0, 1), (1, 0), (1, 1), (1, -1)] {
+            for i in 0..rows {
+                for j in 0..cols {
+                    let (end_x, end_y) =
+                        (i + dx * (win_condition - 1), j + dy * (win_condition - 1));
+                    if end_x >= rows || end_y < 0 || end_y >= cols {
+                        continue;
+                    }
+                    windows.push(
+                        (0..win_condition)
+                            .map(|step| ((i + dx * step) * cols + j + dy * step) as usize)
+                            .collect::<Vec<usize>>(),
+                    );
+                }
+            }
+        }
+
+        let mut cell_windows = vec![vec![]; config.blocks()];
+        let mut counts = vec![[0, 0]; windows.len()];
+        for (w, window) in windows.iter().enumerate() {
+            for &cell in window {
+                cell_windows[cell].push(w);
+                match game_state.board[cell] {
+                    TTTBlockState::Circle => counts[w][0] += 1,
+                    TTTBlockState::Cross => counts[w][1] += 1,
+                    TTTBlockState::Empty => {}
+                }
+            }
+        }
+
+        ThreatBoard {
+            board: game_state.clone(),
+            win_condition: config.win_condition,
+            windows,
+            cell_windows,
+            counts,
+            deadline: budget.max_time.map(|max_time| Instant::now() + max_time),
+            max_nodes: budget.max_nodes,
+            nodes: 0,
+            aborted: false,
+        }
+    }
+
+    fn to_block(&self, cell: usize) -> (usize, usize) {
+        (cell / self.board.config.cols, cell % self.board.config.cols)
+    }
+
+    fn out_of_budget(&mut self) -> bool {
+        self.nodes += 1;
+        self.aborted |= self
+            .max_nodes
+            .is_some_and(|max_nodes| self.nodes >= max_nodes)
+            || self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline);
+        self.aborted
+    }
+
+    fn place(&mut self, cell: usize, player: TTTPlayer) {
+        self.board.board[cell] = player.into();
+        for &w in &self.cell_windows[cell] {
+            self.counts[w][side(player)] += 1;
+        }
+    }
+
+    fn remove(&mut self, cell: usize, player: TTTPlayer) {
+        self.board.board[cell] = TTTBlockState::Empty;
+        for &w in &self.cell_windows[cell] {
+            self.counts[w][side(player)] -= 1;
+        }
+    }
+
+    /// Empty blocks in windows which `player` has exactly `own` pieces in and the opponent none
+    fn cells_in_windows(&self, player: TTTPlayer, own: usize) -> Vec<usize> {
+        let (me, them) = (side(player), side(player.opponent()));
+        let mut cells = vec![];
+        for (w, window) in self.windows.iter().enumerate() {
+            if self.counts[w][me] != own || self.counts[w][them] != 0 {
+                continue;
+            }
+            for &cell in window {
+                if self.board.board[cell] == TTTBlockState::Empty && !cells.contains(&cell) {
+                    cells.push(cell);
+                }
+            }
+        }
+        cells
+    }
+
+    /// Blocks which win immediately for `player`
+    fn winning_cells(&self, player: TTTPlayer) -> Vec<usize> {
+        self.cells_in_windows(player, self.win_condition - 1)
+    }
+
+    fn classify(&mut self, cell: usize, player: TTTPlayer) -> Option<ThreatKind> {
+        if self.winning_cells(player).contains(&cell) {
+            return Some(ThreatKind::Win);
+        }
+
+        self.place(cell, player);
+        let kind = match self.winning_cells(player).len() {
+            0 => {
+                let straight_four = self
+                    .cells_in_windows(player, self.win_condition.saturating_sub(2))
+                    .into_iter()
+                    .any(|next| {
+                        self.place(next, player);
+                        let wins = self.winning_cells(player).len();
+                        self.remove(next, player);
+                        wins >= 2
+                    });
+                straight_four.then_some(ThreatKind::OpenThree)
+            }
+            1 => Some(ThreatKind::Four),
+            _ => Some(ThreatKind::StraightFour),
+        };
+        self.remove(cell, player);
+        kind
+    }
+
+    /// First move of a win by continuous fours, each forcing a single reply
+    fn vcf(&mut self, attacker: TTTPlayer, depth: usize) -> Option<usize> {
+        let defender = attacker.opponent();
+        if let Some(&cell) = self.winning_cells(attacker).first() {
+            return Some(cell);
+        }
+        if depth == 0 || self.out_of_budget() {
+            return None;
+        }
+
+        let defender_wins = self.winning_cells(defender);
+        let mut candidates = self.cells_in_windows(attacker, self.win_condition.saturating_sub(2));
+        match defender_wins[..] {
+            [] => {}
+            // Must block, and the block has to be a four as well to keep the initiative
+            [block] => candidates.retain(|&cell| cell == block),
+            _ => return None,
+        }
+
+        for cell in candidates {
+            if self.aborted {
+                break;
+            }
+            self.place(cell, attacker);
+            let wins = self.winning_cells(attacker);
+            let forced = match wins[..] {
+                [] => false,
+                [reply] => {
+                    self.place(reply, defender);
+                    let forced = self.vcf(attacker, depth - 1).is_some();
+                    self.remove(reply, defender);
+                    forced
+                }
+                _ => true, // Cannot block both
+            };
+            self.remove(cell, attacker);
+
+            if forced {
+                return Some(cell);
+            }
+        }
+        None
+    }
+
+    /// First move of a win by continuous threats, trying every reasonable defence to each
+    ///
+    /// Defences are restricted to blocks in the attacker's lines and the defender's own fours,
+    /// so this is a practical search rather than a proof.
+    fn vct(&mut self, attacker: TTTPlayer, depth: usize) -> Option<usize> {
+        let defender = attacker.opponent();
+        if let Some(cell) = self.vcf(attacker, VCF_DEPTH) {
+            return Some(cell);
+        }
+        if depth == 0 || self.out_of_budget() {
+            return None;
+        }
+
+        let k = self.win_condition;
+        let mut candidates = self.cells_in_windows(attacker, k.saturating_sub(3));
+        match self.winning_cells(defender)[..] {
+            [] => {}
+            // A forced block can still be a threat of its own
+            [block] => candidates = vec![block],
+            _ => return None,
+        }
+
+        for cell in candidates {
+            if self.aborted {
+                break;
+            }
+            self.place(cell, attacker);
+
+            // Only a threat if the attacker would win by force were the defender to pass
+            let mut forced = self.vcf(attacker, VCF_DEPTH).is_some();
+            if forced {
+                let mut defences = self.cells_in_windows(attacker, k.saturating_sub(2));
+                defences.extend(self.cells_in_windows(attacker, k - 1));
+                defences.extend(self.cells_in_windows(defender, k.saturating_sub(2)));
+                defences.sort_unstable();
+                defences.dedup();
+
+                forced = defences.into_iter().all(|defence| {
+                    self.place(defence, defender);
+                    let forced = self.vct(attacker, depth - 1).is_some();
+                    self.remove(defence, defender);
+                    forced
+                });
+            }
+            self.remove(cell, attacker);
+
+            if forced {
+                return Some(cell);
+            }
+        }
+        None
+    }
+
+    /// A reply for `player` after which the opponent's forced win starting at `gain` is gone
+    fn defend(&mut self, player: TTTPlayer, gain: usize) -> Option<usize> {
+        let opponent = player.opponent();
+        let k = self.win_condition;
+        let mut defences = vec![gain];
+        for own in k.saturating_sub(3)..k {
+            defences.extend(self.cells_in_windows(opponent, own));
+        }
+        // Counter-attacking with a four also gains a tempo
+        defences.extend(self.cells_in_windows(player, k.saturating_sub(2)));
+
+        let mut tried = vec![];
+        for defence in defences {
+            if self.aborted {
+                break;
+            }
+            if tried.contains(&defence) {
+                continue;
+            }
+            tried.push(defence);
+
+            self.place(defence, player);
+            let refuted = self.vct(opponent, VCT_DEPTH).is_none() && !self.aborted;
+            self.remove(defence, player);
+            if refuted {
+                return Some(defence);
+            }
+        }
+        None
+    }
+}
+
+/// Every move that creates a threat for `player`, most severe first
+pub fn find_threats(game_state: &TTTGameState, player: TTTPlayer) -> Vec<Threat> {
+    let mut board = ThreatBoard::new(game_state, SearchBudget::unlimited());
+    let mut cells = board.cells_in_windows(player, board.win_condition.saturating_sub(3));
+    for own in board.win_condition.saturating_sub(2)..board.win_condition {
+        cells.extend(board.cells_in_windows(player, own));
+    }
+    cells.sort_unstable();
+    cells.dedup();
+
+    let mut threats = cells
+        .into_iter()
+        .filter_map(|cell| {
+            board.classify(cell, player).map(|kind| Threat {
+                kind,
+                block: board.to_block(cell),
+            })
+        })
+        .collect::<Vec<Threat>>();
+    threats.sort_by_key(|threat| Reverse(threat.kind));
+    threats
+}
+
+/// First move of a forced win for `player` found by threat-space search, if any
+pub fn find_forced_win(
+    game_state: &TTTGameState,
+    player: TTTPlayer,
+    budget: SearchBudget,
+) -> Option<(usize, usize)> {
+    let mut board = ThreatBoard::new(game_state, budget);
+    board
+        .vct(player, VCT_DEPTH)
+        .map(|cell| board.to_block(cell))
+}
+
+/// Forced win or block found by threat-space search, otherwise the `best_move` within the rest of the budget
+pub fn threat_move(
+    game_state: &TTTGameState,
+    player: TTTPlayer,
+    budget: SearchBudget,
+) -> (usize, usize) {
+    let start = Instant::now();
+    let mut board = ThreatBoard::new(game_state, budget);
+    let opponent = player.opponent();
+
+    let chosen = board
+        .winning_cells(player)
+        .first()
+        .or(board.winning_cells(opponent).first())
+        .copied()
+        .or_else(|| board.vct(player, VCT_DEPTH))
+        // Break up the opponent's forced win, or at least take its first square
+        .or_else(|| {
+            let gain = board.vct(opponent, VCT_DEPTH)?;
+            Some(board.defend(player, gain).unwrap_or(gain))
+        })
+        .map(|cell| board.to_block(cell));
+
+    chosen.unwrap_or_else(|| {
+        let remaining = SearchBudget {
+            max_time: budget
+                .max_time
+                .map(|max_time| max_time.saturating_sub(start.elapsed())),
+            max_nodes: budget
+                .max_nodes
+                .map(|max_nodes| max_nodes.saturating_sub(board.nodes).max(1)),
+        };
+        best_move(game_state, player, remaining)
+    })
+}
+
+/// Plays the `threat_move`, see `tictactoe_rand` for the return value
+pub fn tictactoe_threats(
+    game_state: &mut TTTGameState,
+    player: &TTTPlayer,
+    budget: SearchBudget,
+) -> ((usize, usize), Message<TTTGameState>) {
+    let chosen_move = threat_move(game_state, *player, budget);
+    (chosen_move, play_move(game_state, *player, chosen_move))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tictactoe::TTTConfig;
+
+    fn board_with(pieces: &[((usize, usize), TTTPlayer)]) -> TTTGameState {
+        let mut game_state = TTTGameState::with_config(TTTConfig::GOMOKU);
+        for &(block, player) in pieces {
+            game_state.apply_move(block, player).unwrap();
+        }
+        game_state
+    }
+
+    #[test]
+    fn test_find_threats() {
+        use TTTPlayer::{Circle, Cross};
+        let game_state = board_with(&[((7, 5), Cross), ((7, 6), Cross), ((7, 7), Cross)]);
+
+        let threats = find_threats(&game_state, Cross);
+        assert_eq!(threats[0].kind, ThreatKind::StraightFour);
+        assert!(threats.iter().any(|threat| threat.block == (7, 4)));
+        assert!(find_threats(&game_state, Circle).is_empty());
+    }
+
+    #[test]
+    fn test_forced_win() {
+        use TTTPlayer::{Circle, Cross};
+        // Two twos pointing at (7, 7), where a stone makes two broken threes at once - not a win
+        // in one or two moves, but a win by force
+        let game_state = board_with(&[
+            ((7, 4), Cross),
+            ((7, 5), Cross),
+            ((4, 7), Cross),
+            ((5, 7), Cross),
+            ((0, 0), Circle),
+            ((0, 14), Circle),
+            ((14, 0), Circle),
+            ((14, 14), Circle),
+        ]);
+
+        let block = find_forced_win(&game_state, Cross, SearchBudget::unlimited());
+        assert!(block.is_some());
+        assert!(find_forced_win(&game_state, Circle, SearchBudget::unlimited()).is_none());
+
+        // Circle must stop it before anything else
+        let (chosen, _) = tictactoe_threats(
+            &mut game_state.clone(),
+            &Circle,
+            SearchBudget::nodes(100_000),
+        );
+        assert!([(7, 6), (7, 7), (6, 7), (7, 3), (3, 7)].contains(&chosen));
+    }
+}
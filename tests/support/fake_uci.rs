@@ -0,0 +1,91 @@
+use std::{
+    env,
+    io::{self, BufRead, Write},
+    process,
+};
+
+use rusty_moves::chess::{ChessGame, ChessVariant, STANDARD_CHESS960};
+
+// A tiny scripted UCI engine for testing the engine driver without Stockfish
+//
+// Scores legal moves by the order they are generated in. The flags make it misbehave:
+//   --crash-on-go   exits as soon as it is asked to search
+//   --hang-on-go    never answers a search, even when told to stop
+//   --wait-for-stop only answers a search once told to stop
+fn main() {
+    let flag = |name: &str| env::args().any(|arg| arg == name);
+    let (crash, hang, wait_for_stop) = (
+        flag("--crash-on-go"),
+        flag("--hang-on-go"),
+        flag("--wait-for-stop"),
+    );
+
+    let mut stdout = io::stdout();
+    let mut multipv = 1;
+    let mut chess960 = false;
+    let mut game = ChessGame::new(ChessVariant::Standard);
+    let mut searching = false;
+
+    for line in io::stdin().lock().lines() {
+        let line = line.expect("Failed to read stdin");
+        let mut reply = vec![];
+        match line.split_whitespace().collect::<Vec<_>>()[..] {
+            ["uci"] => {
+                reply.push("id name FakeEngine".to_string());
+                reply.push("option name MultiPV type spin default 1 min 1 max 500".to_string());
+                reply.push("option name UCI_Chess960 type check default false".to_string());
+                reply.push("uciok".to_string());
+            }
+            ["isready"] => reply.push("readyok".to_string()),
+            ["setoption", "name", "MultiPV", "value", value] => {
+                multipv = value.parse().expect("Invalid MultiPV");
+            }
+            ["setoption", "name", "UCI_Chess960", "value", value] => {
+                chess960 = value.parse().expect("Invalid UCI_Chess960");
+            }
+            ["position", "fen", ..] => {
+                let mut position = line["position fen ".len()..].to_string();
+                if chess960 {
+                    // Only the castling notation matters here, not the start number
+                    position = format!("chess960:{} {}", STANDARD_CHESS960, position);
+                }
+                game = position.parse().expect("Invalid position");
+            }
+            ["go", ..] if crash => process::exit(3),
+            ["go", ..] if hang => {}
+            ["go", ..] if wait_for_stop => searching = true,
+            ["go", ..] => reply = search(&game, multipv),
+            ["stop"] if searching && !hang => {
+                searching = false;
+                reply = search(&game, multipv);
+            }
+            ["quit"] => break,
+            _ => {}
+        }
+        for line in reply {
+            writeln!(stdout, "{}", line).expect("Failed to write stdout");
+        }
+        stdout.flush().expect("Failed to write stdout");
+    }
+}
+
+fn search(game: &ChessGame, multipv: usize) -> Vec<String> {
+    let moves = game.position().legal_moves();
+    let mut reply = vec![];
+    for depth in 1..=2 {
+        for (i, game_move) in moves.iter().take(multipv).enumerate() {
+            reply.push(format!(
+                "info depth {} multipv {} score cp {} nodes 10 pv {}",
+                depth,
+                i + 1,
+                50 - 20 * i as i32 + depth,
+                game.position().uci(game_move)
+            ));
+        }
+    }
+    match moves.first() {
+        Some(best) => reply.push(format!("bestmove {}", game.position().uci(best))),
+        None => reply.push("bestmove (none)".to_string()),
+    }
+    reply
+}
@@ -0,0 +1,238 @@
+use std::{env, path::PathBuf, time::Duration};
+
+use rusty_moves::{
+    chess::{
+        BookEntry, ChessGame, ChessPlayer, ChessVariant, GoLimit, STANDARD_CHESS960,
+        STARTING_POSITION, UciEngine, UciError, UciPlayer, UciPlayerConfig, get_best_moves,
+        polyglot_key,
+    },
+    game::Game,
+    strategy::Strategy,
+};
+
+const OUR_ENGINE: &str = env!("CARGO_BIN_EXE_uci");
+
+// Removed when dropped, so a failing test doesn't leave it behind
+struct TempFile(PathBuf);
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+// The scripted engine in tests/support, an example so it isn't installed, which
+// `cargo test` builds next to this test's deps directory
+fn fake_engine() -> PathBuf {
+    let exe = env::current_exe().unwrap();
+    let dir = exe.parent().and_then(|deps| deps.parent()).unwrap();
+    dir.join("examples")
+        .join(format!("fake_uci{}", env::consts::EXE_SUFFIX))
+}
+
+#[tokio::test]
+async fn test_search() {
+    let mut engine = UciEngine::spawn(fake_engine(), [""; 0]).await.unwrap();
+    assert_eq!(engine.name(), Some("FakeEngine"));
+    assert!(engine.has_option("multipv"));
+    assert!(!engine.has_option("Hash"));
+
+    engine.set_option("MultiPV", "3").await.unwrap();
+    engine.new_game().await.unwrap();
+    let moves = ["e2e4".parse().unwrap()];
+    engine
+        .set_position(STARTING_POSITION, &moves)
+        .await
+        .unwrap();
+    let result = engine.go(GoLimit::Depth(2)).await.unwrap();
+
+    assert_eq!(result.lines.len(), 3);
+    assert!(result.lines.iter().all(|line| line.depth == 2));
+    assert_eq!(result.lines[0].multipv, 1);
+    assert_eq!(result.lines[0].pv[0], result.best_move);
+    assert_eq!(result.best_move.from.rank(), 6, "Black's move");
+    engine.quit().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_engine_misbehaving() {
+    let crashes = UciEngine::spawn(fake_engine(), ["--crash-on-go"]).await;
+    let mut engine = crashes.unwrap();
+    engine.set_position(STARTING_POSITION, &[]).await.unwrap();
+    assert!(matches!(
+        engine.go(GoLimit::Depth(1)).await,
+        Err(UciError::EngineExited(_))
+    ));
+
+    let timeout = Duration::from_millis(200);
+    let mut engine = UciEngine::spawn_with_timeout(fake_engine(), ["--hang-on-go"], timeout)
+        .await
+        .unwrap();
+    let limit = GoLimit::MoveTime(Duration::from_millis(10));
+    assert!(matches!(engine.go(limit).await, Err(UciError::Timeout(_))));
+
+    // Answers once it is told to stop
+    let mut engine = UciEngine::spawn_with_timeout(fake_engine(), ["--wait-for-stop"], timeout)
+        .await
+        .unwrap();
+    assert!(engine.go(limit).await.is_ok());
+    engine.quit().await.unwrap();
+
+    assert!(matches!(
+        UciEngine::spawn("./no-such-engine", [""; 0]).await,
+        Err(UciError::Io(_))
+    ));
+}
+
+#[test]
+fn test_uci_player() {
+    let config = UciPlayerConfig {
+        program: fake_engine().to_string_lossy().into_owned(),
+        top_n: 3,
+        window: 30,
+        seed: Some(1),
+        ..UciPlayerConfig::default()
+    };
+    let game = ChessGame::new(ChessVariant::Standard);
+    // The fake engine scores its first two moves within 30 centipawns
+    let top_two = &game.legal_moves()[..2];
+
+    let mut player = UciPlayer::new(config.clone());
+    let picks = (0..20)
+        .map(|_| player.choose_move(&game, ChessPlayer::White, None))
+        .collect::<Vec<_>>();
+    assert!(top_two.iter().all(|game_move| picks.contains(game_move)));
+    assert!(picks.iter().all(|game_move| top_two.contains(game_move)));
+
+    // Falls back to the native engine
+    let mut player = UciPlayer::new(UciPlayerConfig {
+        program: "./no-such-engine".to_string(),
+        ..config
+    });
+    let game_move = player.choose_move(&game, ChessPlayer::White, None);
+    assert!(game.legal_moves().contains(&game_move));
+}
+
+#[tokio::test]
+async fn test_uci_binary() {
+    let mut engine = UciEngine::spawn(OUR_ENGINE, [""; 0]).await.unwrap();
+    assert_eq!(engine.name(), Some("Rusty Moves"));
+    assert!(engine.has_option("Strategy") && engine.has_option("EnginePath"));
+
+    // The native engine by default
+    let back_rank = "6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1";
+    engine.set_position(back_rank, &[]).await.unwrap();
+    let result = engine.go(GoLimit::Nodes(20_000)).await.unwrap();
+    assert_eq!(result.best_move, "a1a8".parse().unwrap());
+
+    let moves = ["e2e4".parse().unwrap()];
+    let mut game = ChessGame::new(ChessVariant::Standard);
+    game.play(&moves[0]).unwrap();
+    engine.set_option("Strategy", "random").await.unwrap();
+    engine.new_game().await.unwrap();
+    engine
+        .set_position(STARTING_POSITION, &moves)
+        .await
+        .unwrap();
+    let result = engine.go(GoLimit::Depth(1)).await.unwrap();
+    assert!(game.legal_moves().contains(&result.best_move));
+
+    // Top moves of another engine, here the fake one's best
+    engine.set_option("Strategy", "engine").await.unwrap();
+    engine
+        .set_option("EnginePath", &fake_engine().to_string_lossy())
+        .await
+        .unwrap();
+    engine.set_option("TopN", "1").await.unwrap();
+    let result = engine.go(GoLimit::Depth(1)).await.unwrap();
+    assert_eq!(result.best_move, game.legal_moves()[0]);
+    engine.quit().await.unwrap();
+
+    // Searches until told to stop, then answers straight away
+    let timeout = Duration::from_millis(300);
+    let mut engine = UciEngine::spawn_with_timeout(OUR_ENGINE, [""; 0], timeout)
+        .await
+        .unwrap();
+    engine.set_position(STARTING_POSITION, &[]).await.unwrap();
+    let result = engine.go(GoLimit::Infinite).await.unwrap();
+    assert!(
+        ChessGame::new(ChessVariant::Standard)
+            .legal_moves()
+            .contains(&result.best_move)
+    );
+    engine.quit().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_chess960() {
+    // Castling could be written either way from the standard start, so the option decides
+    let mut game = ChessGame::new(ChessVariant::Chess960(STANDARD_CHESS960));
+    for uci in ["e2e4", "e7e5", "g1f3", "b8c6", "f1c4", "g8f6"] {
+        game.play(&uci.parse().unwrap()).unwrap();
+    }
+    let castle = "e1h1".parse().unwrap();
+    assert!(game.legal_moves().contains(&castle));
+
+    let mut engine = UciEngine::spawn(fake_engine(), [""; 0]).await.unwrap();
+    let moves = get_best_moves(&mut engine, &game, 100, GoLimit::Depth(1))
+        .await
+        .unwrap();
+    assert!(moves.iter().any(|&(game_move, _)| game_move == castle));
+    assert!(
+        moves
+            .iter()
+            .all(|(game_move, _)| game.legal_moves().contains(game_move))
+    );
+    engine.quit().await.unwrap();
+
+    let mut engine = UciEngine::spawn(OUR_ENGINE, [""; 0]).await.unwrap();
+    assert!(engine.has_option("UCI_Chess960"));
+    engine.set_option("UCI_Chess960", "true").await.unwrap();
+    let moves = game
+        .moves()
+        .iter()
+        .map(|game_move| game.start().uci(game_move));
+    let mut moves = moves.collect::<Vec<_>>();
+    moves.push(castle);
+    let fen = game.start().to_string();
+    engine.set_position(&fen, &moves).await.unwrap();
+    let result = engine.go(GoLimit::Depth(1)).await.unwrap();
+    let mut castled = game.clone();
+    castled.play(&castle).unwrap();
+    assert!(
+        castled.legal_moves().contains(&result.best_move),
+        "Black's move after castling"
+    );
+    engine.quit().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_opening_book() {
+    let start = ChessGame::new(ChessVariant::Standard);
+    // a2a3 in Polyglot's move bits, from a2 to a3
+    let entry = BookEntry {
+        key: polyglot_key(start.position()),
+        raw_move: (1 << 3) << 6 | 2 << 3,
+        weight: 1,
+        learn: 0,
+    };
+    let book =
+        TempFile(env::temp_dir().join(format!("rusty_moves_book_{}.bin", std::process::id())));
+    std::fs::write(&book.0, entry.to_bytes()).unwrap();
+
+    let mut engine = UciEngine::spawn(OUR_ENGINE, [""; 0]).await.unwrap();
+    assert!(engine.has_option("BookFile") && engine.has_option("BookDepth"));
+    engine
+        .set_option("BookFile", book.0.to_str().unwrap())
+        .await
+        .unwrap();
+    engine.set_position(STARTING_POSITION, &[]).await.unwrap();
+    let result = engine.go(GoLimit::Nodes(1_000)).await.unwrap();
+    assert_eq!(result.best_move, "a2a3".parse().unwrap());
+
+    // Out of the book, and past its depth, the native engine plays
+    engine.set_option("BookDepth", "0").await.unwrap();
+    let result = engine.go(GoLimit::Nodes(1_000)).await.unwrap();
+    assert!(start.legal_moves().contains(&result.best_move));
+    engine.quit().await.unwrap();
+}